@@ -20,12 +20,10 @@ use dot;
 use dot::IntoCow;
 
 use std::fmt::Debug;
-use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 use std::marker::PhantomData;
 use std::mem;
-use std::path::Path;
 
 use super::super::MirBorrowckCtxtPreDataflow;
 use super::{BitDenotation, DataflowState};
@@ -100,9 +98,9 @@ struct Graph<'a, 'tcx, MWF:'a, P> where
     render_idx: P,
 }
 
-pub fn print_borrowck_graph_to<'a, BD, P>(
+pub fn write_borrowck_graph<'a, BD, P>(
     mbcx: &MirBorrowckCtxtPreDataflow<'a, BD>,
-    path: &Path,
+    w: &mut Write,
     render_idx: P)
     -> io::Result<()>
     where BD: BitDenotation,
@@ -111,9 +109,7 @@ pub fn print_borrowck_graph_to<'a, BD, P>(
     let g = Graph { mbcx: mbcx, phantom: PhantomData, render_idx: render_idx };
     let mut v = Vec::new();
     dot::render(&g, &mut v)?;
-    debug!("print_borrowck_graph_to path: {}",
-           path.display());
-    File::create(path).and_then(|mut f| f.write_all(&v))
+    w.write_all(&v)
 }
 
 pub type Node = BasicBlock;
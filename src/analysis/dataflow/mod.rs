@@ -16,9 +16,7 @@ use mir::{self, Mir};
 use ty::TyCtxt;
 
 use std::fmt::Debug;
-use std::io;
 use std::mem;
-use std::path::PathBuf;
 use std::usize;
 
 use super::MirBorrowckCtxtPreDataflow;
@@ -29,17 +27,17 @@ mod graphviz;
 mod impls;
 
 pub trait Dataflow<BD: BitDenotation> {
-    fn dataflow<P>(&mut self, p: P) where P: Fn(&BD, BD::Idx) -> &Debug;
+    fn dataflow<P>(&mut self, tcx: TyCtxt, p: P) where P: Fn(&BD, BD::Idx) -> &Debug;
 }
 
 impl<'a, BD> Dataflow<BD> for MirBorrowckCtxtPreDataflow<'a, BD>
     where BD: BitDenotation + DataflowOperator
 {
-    fn dataflow<P>(&mut self, p: P) where P: Fn(&BD, BD::Idx) -> &Debug {
+    fn dataflow<P>(&mut self, tcx: TyCtxt, p: P) where P: Fn(&BD, BD::Idx) -> &Debug {
         self.flow_state.build_sets();
-        self.pre_dataflow_instrumentation(|c,i| p(c,i)).unwrap();
+        self.pre_dataflow_instrumentation(tcx, |c,i| p(c,i));
         self.flow_state.propagate();
-        self.post_dataflow_instrumentation(|c,i| p(c,i)).unwrap();
+        self.post_dataflow_instrumentation(tcx, |c,i| p(c,i));
     }
 }
 
@@ -121,39 +119,28 @@ impl<'b, 'a: 'b, BD> PropagationContext<'b, 'a, BD>
     }
 }
 
-fn dataflow_path(context: &str, prepost: &str, path: &str) -> PathBuf {
-    format!("{}_{}", context, prepost);
-    let mut path = PathBuf::from(path);
-    let new_file_name = {
-        let orig_file_name = path.file_name().unwrap().to_str().unwrap();
-        format!("{}_{}", context, orig_file_name)
-    };
-    path.set_file_name(new_file_name);
-    path
-}
-
 impl<'a, BD> MirBorrowckCtxtPreDataflow<'a, BD>
     where BD: BitDenotation
 {
-    fn pre_dataflow_instrumentation<P>(&self, p: P) -> io::Result<()>
+    fn pre_dataflow_instrumentation<P>(&self, tcx: TyCtxt, p: P)
         where P: Fn(&BD, BD::Idx) -> &Debug
     {
-        if let Some(ref path_str) = self.print_preflow_to {
-            let path = dataflow_path(BD::name(), "preflow", path_str);
-            graphviz::print_borrowck_graph_to(self, &path, p)
-        } else {
-            Ok(())
+        if let Some(ref path) = self.print_preflow_to {
+            let mir = self.flow_state.mir;
+            mir.config.write_dump(&tcx, mir.span, path, |file| {
+                graphviz::write_borrowck_graph(self, file, p)
+            });
         }
     }
 
-    fn post_dataflow_instrumentation<P>(&self, p: P) -> io::Result<()>
+    fn post_dataflow_instrumentation<P>(&self, tcx: TyCtxt, p: P)
         where P: Fn(&BD, BD::Idx) -> &Debug
     {
-        if let Some(ref path_str) = self.print_postflow_to {
-            let path = dataflow_path(BD::name(), "postflow", path_str);
-            graphviz::print_borrowck_graph_to(self, &path, p)
-        } else{
-            Ok(())
+        if let Some(ref path) = self.print_postflow_to {
+            let mir = self.flow_state.mir;
+            mir.config.write_dump(&tcx, mir.span, path, |file| {
+                graphviz::write_borrowck_graph(self, file, p)
+            });
         }
     }
 }
@@ -542,7 +542,7 @@ impl<'a, 'tcx> MoveDataBuilder<'a, 'tcx> {
         match *operand {
             Operand::Constant(..) => {} // not-a-move
             Operand::Copy(..) => {} // not-a-move
-            Operand::Consume(ref lval) => { // a move
+            Operand::Consume(ref lval, _) => { // a move
                 self.gather_move(loc, lval);
             }
         }
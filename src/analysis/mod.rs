@@ -9,8 +9,8 @@
 // except according to those terms.
 
 use mir::*;
-use std::env;
 use std::fmt;
+use std::path::PathBuf;
 use ty::TyCtxt;
 
 mod abs_domain;
@@ -35,10 +35,13 @@ fn do_dataflow<BD, P>(tcx: TyCtxt,
     where BD: BitDenotation<Idx=MovePathIndex> + DataflowOperator,
           P: Fn(&BD, BD::Idx) -> &fmt::Debug
 {
-    let print_preflow_to =
-        env::var("STATEFUL_BORROWCK_GRAPHVIZ_PREFLOW").ok();
-    let print_postflow_to =
-        env::var("STATEFUL_BORROWCK_GRAPHVIZ_POSTFLOW").ok();
+    let (print_preflow_to, print_postflow_to) = if mir.config.dump.dataflow {
+        let ident = mir.fn_decl.ident;
+        (Some(mir.config.dump_path(ident, &format!("{}.preflow.dot", BD::name()))),
+         Some(mir.config.dump_path(ident, &format!("{}.postflow.dot", BD::name()))))
+    } else {
+        (None, None)
+    };
 
     let mut mbcx = MirBorrowckCtxtPreDataflow {
         print_preflow_to: print_preflow_to,
@@ -46,7 +49,7 @@ fn do_dataflow<BD, P>(tcx: TyCtxt,
         flow_state: DataflowAnalysis::new(tcx, mir, bd),
     };
 
-    mbcx.dataflow(p);
+    mbcx.dataflow(tcx, p);
     mbcx.flow_state.results()
 }
 
@@ -54,8 +57,8 @@ fn do_dataflow<BD, P>(tcx: TyCtxt,
 pub struct MirBorrowckCtxtPreDataflow<'a, BD> where BD: BitDenotation
 {
     flow_state: DataflowAnalysis<'a, BD>,
-    print_preflow_to: Option<String>,
-    print_postflow_to: Option<String>,
+    print_preflow_to: Option<PathBuf>,
+    print_postflow_to: Option<PathBuf>,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
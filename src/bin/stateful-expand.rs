@@ -0,0 +1,262 @@
+//! Print the Rust code a state machine function, like a `#[generator]`, expands into:
+//!
+//! ```text
+//! stateful-expand [--rustfmt] [--states] <file.rs> <function>
+//! ```
+//!
+//! This is the same text that `dump(source)` writes. With `--states`, every arm that matches on
+//! a state gets a `// state N` comment pointing back at the source line its block came from, and
+//! with `--rustfmt` the output is piped through `rustfmt`.
+
+#![feature(rustc_private)]
+
+extern crate stateful;
+extern crate syntax;
+
+use stateful::config::Config;
+use stateful::data_structures::indexed_vec::Idx;
+use stateful::mir::{BasicBlock, FunctionDecl, Mir, StateMachineKind};
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use syntax::ast;
+use syntax::attr;
+use syntax::ext::base::{DummyResolver, ExtCtxt};
+use syntax::ext::expand::ExpansionConfig;
+use syntax::parse::{self, ParseSess};
+use syntax::print::pprust;
+use syntax::ptr::P;
+
+const USAGE: &'static str = "usage: stateful-expand [--rustfmt] [--states] <file.rs> <function>";
+
+fn main() {
+    let mut rustfmt = false;
+    let mut states = false;
+    let mut args = vec![];
+
+    for arg in env::args().skip(1) {
+        match &*arg {
+            "--rustfmt" => { rustfmt = true; }
+            "--states" => { states = true; }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => { args.push(arg); }
+        }
+    }
+
+    if args.len() != 2 {
+        fail(USAGE);
+    }
+
+    let sess = ParseSess::new();
+
+    let krate = match parse::parse_crate_from_file(Path::new(&args[0]), &sess) {
+        Ok(krate) => krate,
+        Err(mut err) => {
+            err.emit();
+            process::exit(1);
+        }
+    };
+
+    let item = match find_fn(&krate.module.items, &args[1]) {
+        Some(item) => item,
+        None => fail(&format!("no function `{}` found in `{}`", args[1], args[0])),
+    };
+
+    let mut resolver = DummyResolver;
+    let cx = ExtCtxt::new(
+        &sess,
+        ExpansionConfig::default("stateful_expand".to_string()),
+        &mut resolver);
+
+    let expanded = match expand(&cx, &item) {
+        Ok(expanded) => expanded,
+        Err(msg) => fail(&msg),
+    };
+
+    // The options were rejected, and the errors have already been reported.
+    let (mir, item) = match expanded {
+        Some(expanded) if !sess.span_diagnostic.has_errors() => expanded,
+        _ => process::exit(1),
+    };
+
+    let mut source = pprust::item_to_string(&item);
+
+    if states {
+        source = annotate_states(&cx, &mir, &source);
+    }
+
+    if rustfmt {
+        source = run_rustfmt(source);
+    }
+
+    println!("{}", source);
+}
+
+fn fail(msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "{}", msg);
+    process::exit(1)
+}
+
+/// Find a function by name. State machines are often declared inside of other functions, like
+/// tests, so this also looks into function bodies as well as modules.
+fn find_fn(items: &[P<ast::Item>], name: &str) -> Option<P<ast::Item>> {
+    for item in items {
+        match item.node {
+            ast::ItemKind::Fn(_, _, _, _, _, ref block) => {
+                if &*item.ident.name.as_str() == name {
+                    return Some(item.clone());
+                }
+
+                let nested = block.stmts.iter()
+                    .filter_map(|stmt| {
+                        match stmt.node {
+                            ast::StmtKind::Item(ref item) => Some(item.clone()),
+                            _ => None,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                if let Some(item) = find_fn(&nested, name) {
+                    return Some(item);
+                }
+            }
+            ast::ItemKind::Mod(ref module) => {
+                if let Some(item) = find_fn(&module.items, name) {
+                    return Some(item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Expand the function the same way its state machine attribute would, or return `None` if the
+/// attribute rejects it.
+fn expand(cx: &ExtCtxt, item: &P<ast::Item>) -> Result<Option<(Mir, P<ast::Item>)>, String> {
+    let attrs = [
+        ("generator", StateMachineKind::Generator),
+        ("async", StateMachineKind::Async),
+        ("state_machine", StateMachineKind::StateMachine),
+        ("push_parser", StateMachineKind::PushParser),
+        ("actor", StateMachineKind::Actor),
+    ];
+
+    let (name, state_machine_kind, meta_item) = match attrs.iter()
+        .filter_map(|&(name, kind)| {
+            attr::find_by_name(&item.attrs, name).map(|attr| (name, kind, &attr.value))
+        })
+        .next()
+    {
+        Some(found) => found,
+        None => {
+            return Err(format!("`{}` is not a state machine function", item.ident));
+        }
+    };
+
+    let config = Config::from_meta_item(cx, meta_item);
+
+    match item.node {
+        ast::ItemKind::Fn(ref fn_decl, ref unsafety, _, ref abi, ref generics, ref block) => {
+            let fn_decl = FunctionDecl::new(
+                item.ident,
+                fn_decl.clone(),
+                *unsafety,
+                *abi,
+                generics.clone(),
+            );
+
+            if !stateful::check_config(cx, meta_item, name, state_machine_kind, &config, &fn_decl) {
+                return Ok(None);
+            }
+
+            Ok(Some(stateful::expand_fn(
+                cx,
+                state_machine_kind,
+                config,
+                item.span,
+                fn_decl,
+                block.clone())))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Add a `// state N: file:line` comment to every arm that matches on a state, pointing at the
+/// source of the block the state resumes.
+fn annotate_states(cx: &ExtCtxt, mir: &Mir, source: &str) -> String {
+    source.lines()
+        .map(|line| {
+            if let Some(block) = arm_state(mir, line) {
+                let loc = cx.codemap().lookup_char_pos(mir[block].span.lo);
+                return format!("{} // state {}: {}:{}",
+                               line,
+                               block.index(),
+                               loc.file.name,
+                               loc.line);
+            }
+
+            line.to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The block of an arm like `InternalState::State3Resume(scope1) => {`, or of a labelled state
+/// like `InternalState::ReadHeader => {`.
+fn arm_state(mir: &Mir, line: &str) -> Option<BasicBlock> {
+    let line = line.trim_left();
+    if !line.contains("=>") {
+        return None;
+    }
+
+    let rest = ["InternalState::", "ResumeState::"].iter()
+        .find(|prefix| line.starts_with(**prefix))
+        .map(|prefix| &line[prefix.len()..]);
+
+    rest.and_then(|rest| {
+        let end = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+        let variant = &rest[..end];
+
+        mir.basic_blocks().indices().find(|&block| mir.state_name(block) == variant)
+    })
+}
+
+/// Pipe the source through `rustfmt`, falling back to the unformatted source if that fails.
+fn run_rustfmt(source: String) -> String {
+    let child = Command::new("rustfmt")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "warning: could not run rustfmt: {}", err);
+            return source;
+        }
+    };
+
+    // Dropping stdin closes it, which lets rustfmt know the source is complete.
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(err) = stdin.write_all(source.as_bytes()) {
+            let _ = writeln!(io::stderr(), "warning: could not run rustfmt: {}", err);
+            return source;
+        }
+    }
+
+    match child.wait_with_output() {
+        Ok(ref output) if output.status.success() => {
+            String::from_utf8(output.stdout.clone()).unwrap_or(source)
+        }
+        _ => {
+            let _ = writeln!(io::stderr(), "warning: rustfmt failed, printing unformatted source");
+            source
+        }
+    }
+}
@@ -44,6 +44,8 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             for stmt in stmts {
                 match stmt.node {
                     StmtKind::Expr(ref expr) | StmtKind::Semi(ref expr) => {
+                        this.enter_state(expr);
+
                         let extent = this.start_new_extent();
                         unpack!(block = this.in_scope(extent, stmt.span, block, |this| {
                             this.stmt_expr(block, expr)
@@ -100,7 +102,9 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                         }
                     }
                     StmtKind::Item(..) => {
-                        span_bug!(this.cx, stmt.span, "Cannot handle item declarations yet");
+                        this.cx.span_err(
+                            stmt.span,
+                            "item declarations are not supported in state machine functions");
                     }
                 }
             }
@@ -1,8 +1,11 @@
 use aster::AstBuilder;
+use build::mac::{is_path, parse_mac};
 use build::transition::{self, Transition};
+use config::Config;
 use mir::*;
 use syntax::ast::{self, ExprKind, StmtKind};
-use syntax::codemap::respan;
+use syntax::attr;
+use syntax::codemap::{Span, respan};
 use syntax::ext::base::ExtCtxt;
 use syntax::ext::tt::quoted;
 use syntax::ext::tt::transcribe;
@@ -10,15 +13,24 @@ use syntax::fold::{self, Folder};
 use syntax::parse::parser::Parser;
 use syntax::parse::token::Token;
 use syntax::ptr::P;
+use syntax::symbol::{Symbol, keywords};
 use syntax::util::small_vector::SmallVector;
 
 pub fn desugar_block(cx: &ExtCtxt,
                      state_machine_kind: StateMachineKind,
+                     config: &Config,
                      fn_decl: FunctionDecl,
                      block: P<ast::Block>) -> (FunctionDecl, P<ast::Block>) {
     let mut desugar = Desugar {
         cx: cx,
         state_machine_kind: state_machine_kind,
+        std: config.std_crate(),
+        no_std: config.no_std,
+        fn_ident: fn_decl.ident,
+        in_closure: false,
+        unconsumed: ast::Ident::with_empty_ctxt(Symbol::gensym("parser_unconsumed")),
+        actor_reply: ast::Ident::with_empty_ctxt(Symbol::gensym("actor_reply")),
+        yield_from_item: ast::Ident::with_empty_ctxt(Symbol::gensym("yield_from_item")),
     };
 
     let mut assigner = AssignIds {
@@ -33,7 +45,40 @@ pub fn desugar_block(cx: &ExtCtxt,
         assigner.fold_generics(desugar.fold_generics(fn_decl.generics)),
     );
 
-    let block = assigner.fold_block(desugar.fold_block(block));
+    let block = desugar.fold_block(block);
+
+    // Async functions evaluate to a `Result<Item, Error>`, so that `await!` can propagate errors
+    // out of the future by returning an `Err`.
+    let block = match state_machine_kind {
+        StateMachineKind::Generator |
+        StateMachineKind::StateMachine => block,
+        StateMachineKind::Async => {
+            let std = desugar.std;
+            quote_block!(cx, { ::$std::result::Result::Ok($block) })
+        }
+        // Push parsers keep how much of the last chunk they didn't use in a local, which is handed
+        // back to the caller alongside the parsed value.
+        StateMachineKind::PushParser => {
+            let unconsumed = desugar.unconsumed;
+            quote_block!(cx, {
+                let mut $unconsumed = 0usize;
+                ($block, $unconsumed)
+            })
+        }
+        // Actors keep their pending reply in a local, which is handed back to the caller whenever
+        // the actor waits for a message or finishes.
+        StateMachineKind::Actor => {
+            let std = desugar.std;
+            let actor_reply = desugar.actor_reply;
+            quote_block!(cx, {
+                let mut $actor_reply = ::$std::option::Option::None;
+                $block;
+                moved!($actor_reply)
+            })
+        }
+    };
+
+    let block = assigner.fold_block(block);
 
     (fn_decl, block)
 }
@@ -41,11 +86,35 @@ pub fn desugar_block(cx: &ExtCtxt,
 struct Desugar<'a, 'b: 'a> {
     cx: &'a ExtCtxt<'b>,
     state_machine_kind: StateMachineKind,
+
+    /// The crate to use for language items, either `std` or `core`.
+    std: ast::Ident,
+
+    /// Whether the state machine is `no_std`, and so cannot box anything.
+    no_std: bool,
+
+    /// The name of the state machine function, to find recursive calls to it.
+    fn_ident: ast::Ident,
+
+    /// Returns inside closures belong to the closure, not the state machine.
+    in_closure: bool,
+
+    /// The hidden local that `unconsumed!(...)` assigns to in push parsers.
+    unconsumed: ast::Ident,
+
+    /// The hidden local that holds the pending reply in actors.
+    actor_reply: ast::Ident,
+
+    /// The hidden loop variable of `yield_from!(...)`.
+    yield_from_item: ast::Ident,
 }
 
 impl<'a, 'b> Desugar<'a, 'b> {
+    /// Fold the argument of a transition macro. This goes through `fold_expr`, so that a macro
+    /// passed directly as the argument, as in `yield_!(suspend!(1))`, is desugared or reported
+    /// by `expr_mac` like any other.
     fn fold_sub_expr(&mut self, expr: P<ast::Expr>) -> P<ast::Expr> {
-        expr.map(|expr| fold::noop_fold_expr(expr, self))
+        self.fold_expr(expr)
     }
 
     pub fn expr_mac(&mut self, mac: &ast::Mac) -> Option<P<ast::Expr>> {
@@ -54,41 +123,214 @@ impl<'a, 'b> Desugar<'a, 'b> {
         match (self.state_machine_kind, transition::parse_mac_transition(self.cx, mac)) {
             (StateMachineKind::Generator, Some(transition::Transition::Yield(expr))) => {
                 let expr = self.fold_sub_expr(expr);
-                Some(desugar_yield(expr))
+                Some(desugar_yield(self.std, expr))
+            }
+            (StateMachineKind::Generator, Some(transition::Transition::YieldFrom(expr))) => {
+                Some(self.desugar_yield_from(expr))
+            }
+            (_, Some(transition::Transition::Yield(_))) => {
+                self.cx.span_err(
+                    mac.span,
+                    "`yield_!(...)` can only be used in `#[generator]` functions");
+                Some(desugar_misused_mac(mac.span))
+            }
+            (_, Some(transition::Transition::YieldFrom(_))) => {
+                self.cx.span_err(
+                    mac.span,
+                    "`yield_from!(...)` can only be used in `#[generator]` functions");
+                Some(desugar_misused_mac(mac.span))
+            }
+            // A `#[state_machine]` suspends directly, but it still produces its values through
+            // an `Iterator`, so this works just like a `yield_!`.
+            (StateMachineKind::StateMachine, Some(transition::Transition::Suspend(expr))) => {
+                let expr = self.fold_sub_expr(expr);
+                Some(desugar_yield(self.std, expr))
+            }
+            (_, Some(transition::Transition::Suspend(_))) => {
+                self.cx.span_err(mac.span, "`suspend!(...)` cannot be directly called");
+                Some(desugar_misused_mac(mac.span))
+            }
+            (StateMachineKind::PushParser, Some(transition::Transition::NeedsInput)) => {
+                Some(desugar_needs_input(self.std, self.unconsumed, mac.span))
+            }
+            (_, Some(transition::Transition::NeedsInput)) => {
+                self.cx.span_err(
+                    mac.span,
+                    "`needs_input!()` can only be used in `#[push_parser]` functions");
+                Some(desugar_misused_mac(mac.span))
+            }
+            (StateMachineKind::Actor, Some(transition::Transition::Recv)) => {
+                Some(desugar_recv(self.std, self.actor_reply, mac.span))
+            }
+            (_, Some(transition::Transition::Recv)) => {
+                self.cx.span_err(mac.span, "`recv!()` can only be used in `#[actor]` functions");
+                Some(desugar_misused_mac(mac.span))
+            }
+            (StateMachineKind::Actor, Some(transition::Transition::Reply(expr))) => {
+                let expr = self.fold_sub_expr(expr);
+                Some(desugar_reply(self.std, self.actor_reply, expr))
+            }
+            (_, Some(transition::Transition::Reply(_))) => {
+                self.cx.span_err(
+                    mac.span,
+                    "`reply!(...)` can only be used in `#[actor]` functions");
+                Some(desugar_misused_mac(mac.span))
             }
             (StateMachineKind::Async, Some(transition::Transition::Await(expr))) => {
                 let expr = self.fold_sub_expr(expr);
-                Some(desugar_await(self.cx, expr))
+                Some(desugar_await(self.cx, self.std, expr))
+            }
+            (StateMachineKind::Async, Some(transition::Transition::AwaitResult(expr))) => {
+                let expr = self.fold_sub_expr(expr);
+                Some(desugar_await_result(self.cx, self.std, expr))
+            }
+            (StateMachineKind::Async, Some(transition::Transition::AwaitNext(expr))) => {
+                let expr = self.fold_sub_expr(expr);
+                Some(desugar_await_next(self.cx, self.std, expr))
+            }
+            (_, Some(transition::Transition::Await(_))) |
+            (_, Some(transition::Transition::AwaitResult(_))) |
+            (_, Some(transition::Transition::AwaitNext(_))) => {
+                let name = mac.node.path.segments.last().unwrap().identifier;
+                self.cx.span_err(
+                    mac.span,
+                    &format!("`{}!(...)` can only be used in `#[async]` functions", name));
+                Some(desugar_misused_mac(mac.span))
             }
             _ => {
                 if is_try_path(&mac.node.path) {
                     let expr = parse_mac_try(self.cx, mac);
                     let expr = self.fold_sub_expr(expr);
                     Some(desugar_try(expr))
+                } else if is_path(&mac.node.path, "unconsumed") {
+                    let expr = parse_mac(self.cx, mac);
+
+                    if self.state_machine_kind == StateMachineKind::PushParser {
+                        let expr = self.fold_sub_expr(expr);
+                        Some(desugar_unconsumed(self.unconsumed, expr))
+                    } else {
+                        self.cx.span_err(
+                            mac.span,
+                            "`unconsumed!(...)` can only be used in `#[push_parser]` functions");
+                        Some(desugar_misused_mac(mac.span))
+                    }
                 } else {
                     None
                 }
             }
         }
     }
+
+    /// Compile `yield_from!($expr)` into a loop that yields every item of `$expr`:
+    ///
+    /// ```
+    /// for yield_from_item in $expr {
+    ///     yield_!(yield_from_item);
+    /// }
+    /// ```
+    ///
+    /// If `$expr` is a recursive call to this generator, the state of the outer generator would
+    /// contain the state of the inner one, so the inner generator is boxed into a trait object to
+    /// give the state a finite size with `::stateful_runtime::box_iter($expr)`. A plain cast to
+    /// `Box<Iterator>` would require the inner generator to be `'static`.
+    fn desugar_yield_from(&mut self, expr: P<ast::Expr>) -> P<ast::Expr> {
+        let expr = if self.is_recursive_call(&expr) {
+            if self.no_std {
+                self.cx.span_err(
+                    expr.span,
+                    "recursive `yield_from!(...)` boxes the inner generator, so it cannot be used \
+                     in `no_std` functions");
+            }
+
+            quote_expr!(self.cx, ::stateful_runtime::box_iter($expr))
+        } else {
+            expr
+        };
+
+        let item = self.yield_from_item;
+        let for_loop = quote_expr!(self.cx,
+            for $item in $expr {
+                yield_!($item);
+            }
+        );
+
+        self.fold_expr(for_loop)
+    }
+
+    /// Whether `expr` calls the state machine function itself, either as `f(...)`, `Self::f(...)`
+    /// or `self.f(...)`, possibly through a chain of adapters like `f(...).filter(...)`, whose
+    /// type would contain the inner state machine just the same.
+    fn is_recursive_call(&self, expr: &ast::Expr) -> bool {
+        let name = self.fn_ident.name;
+
+        match expr.node {
+            ExprKind::Call(ref func, _) => {
+                match func.node {
+                    ExprKind::Path(None, ref path) if !path.is_global() => {
+                        match path.segments.len() {
+                            1 => path.segments[0].identifier.name == name,
+                            2 => {
+                                path.segments[0].identifier.name == keywords::SelfType.name() &&
+                                    path.segments[1].identifier.name == name
+                            }
+                            _ => false,
+                        }
+                    }
+                    _ => false,
+                }
+            }
+            ExprKind::MethodCall(ident, _, ref args) => {
+                let receiver = &args[0];
+
+                let is_self = match receiver.node {
+                    ExprKind::Path(None, ref path) => {
+                        path.segments.len() == 1 &&
+                            path.segments[0].identifier.name == keywords::SelfValue.name()
+                    }
+                    _ => false,
+                };
+
+                (is_self && ident.node.name == name) || self.is_recursive_call(receiver)
+            }
+            ExprKind::Paren(ref expr) => self.is_recursive_call(expr),
+            _ => false,
+        }
+    }
 }
 
 impl<'a, 'b: 'a> fold::Folder for Desugar<'a, 'b> {
     fn fold_expr(&mut self, expr: P<ast::Expr>) -> P<ast::Expr> {
         expr.map(|expr| {
+            let is_async_for = attr::contains_name(&expr.attrs, "async");
+
             match expr.node {
                 ExprKind::ForLoop(pat, expr, loop_block, label) => {
-                    desugar_for_loop(
-                        self.fold_pat(pat),
-                        self.fold_expr(expr),
-                        self.fold_block(loop_block),
-                        label.map(|label| {
-                            respan(
-                                self.new_span(label.span),
-                                self.fold_ident(label.node)
-                            )
-                        })
-                    ).unwrap()
+                    let pat = self.fold_pat(pat);
+                    let expr = self.fold_expr(expr);
+                    let loop_block = self.fold_block(loop_block);
+                    let label = label.map(|label| {
+                        respan(
+                            self.new_span(label.span),
+                            self.fold_ident(label.node)
+                        )
+                    });
+
+                    match (is_async_for, self.state_machine_kind) {
+                        (true, StateMachineKind::Async) => {
+                            desugar_async_for_loop(self.cx, self.std, pat, expr, loop_block, label)
+                                .unwrap()
+                        }
+                        (true, _) => {
+                            self.cx.span_err(
+                                expr.span,
+                                "`#[async] for` loops can only be used in `#[async]` functions");
+
+                            desugar_for_loop(self.std, pat, expr, loop_block, label).unwrap()
+                        }
+                        (false, _) => {
+                            desugar_for_loop(self.std, pat, expr, loop_block, label).unwrap()
+                        }
+                    }
                 }
                 ExprKind::IfLet(pat, expr, then_block, else_block) => {
                     let expr = desugar_if_let(pat, expr, then_block, else_block).unwrap();
@@ -118,6 +360,61 @@ impl<'a, 'b: 'a> fold::Folder for Desugar<'a, 'b> {
                     let node = ExprKind::Loop(body, opt_ident);
                     ast::Expr { node: node, .. expr }
                 }
+                ExprKind::Ret(ret_expr) => {
+                    let ret_expr = ret_expr.map(|ret_expr| self.fold_expr(ret_expr));
+
+                    let ret_expr = match self.state_machine_kind {
+                        // `return $expr` completes the future with `Ok($expr)`.
+                        StateMachineKind::Async if !self.in_closure => {
+                            let builder = AstBuilder::new().span(expr.span);
+                            let ret_expr = ret_expr.unwrap_or_else(|| builder.expr().unit());
+
+                            Some(builder.expr().call()
+                                .path()
+                                    .global()
+                                    .id(self.std).ids(&["result", "Result", "Ok"])
+                                    .build()
+                                .with_arg(ret_expr)
+                                .build())
+                        }
+                        // `return $expr` finishes the parser with `($expr, unconsumed)`.
+                        StateMachineKind::PushParser if !self.in_closure => {
+                            let builder = AstBuilder::new().span(expr.span);
+                            let ret_expr = ret_expr.unwrap_or_else(|| builder.expr().unit());
+
+                            Some(builder.expr().tuple()
+                                .expr().build(ret_expr)
+                                .expr().id(self.unconsumed)
+                                .build())
+                        }
+                        // `return` hands the pending reply back to the caller.
+                        StateMachineKind::Actor if !self.in_closure => {
+                            if let Some(ref ret_expr) = ret_expr {
+                                self.cx.span_err(
+                                    ret_expr.span,
+                                    "`actor` functions cannot return a value, use `reply!(...)` \
+                                     instead");
+                            }
+
+                            let builder = AstBuilder::new().span(expr.span);
+
+                            // moved!(actor_reply)
+                            Some(builder.expr().mac().path().id("moved").build()
+                                .expr().id(self.actor_reply)
+                                .build())
+                        }
+                        _ => ret_expr,
+                    };
+
+                    ast::Expr { node: ExprKind::Ret(ret_expr), .. expr }
+                }
+                ExprKind::Closure(..) => {
+                    let in_closure = self.in_closure;
+                    self.in_closure = true;
+                    let expr = fold::noop_fold_expr(expr, self);
+                    self.in_closure = in_closure;
+                    expr
+                }
                 ExprKind::Mac(mac) => {
                     if let Some(expr) = self.expr_mac(&mac) {
                         expr.unwrap()
@@ -158,17 +455,22 @@ impl<'a, 'b: 'a> fold::Folder for Desugar<'a, 'b> {
         // this macro may contain transitions. So parse it, expand the inner expression,
         // then convert it back into a macro.
         match transition::parse_mac_transition(self.cx, &mac) {
-            Some(Transition::Yield(expr)) | Some(Transition::Await(expr)) => {
+            Some(Transition::Yield(expr)) |
+            Some(Transition::YieldFrom(expr)) |
+            Some(Transition::Await(expr)) |
+            Some(Transition::AwaitResult(expr)) |
+            Some(Transition::AwaitNext(expr)) |
+            Some(Transition::Reply(expr)) => {
                 let expr = self.fold_expr(expr);
                 AstBuilder::new().span(mac.span).mac()
                     .build_path(mac.node.path.clone())
                     .with_arg(expr)
                     .build()
             }
-            Some(Transition::Suspend(_)) => {
-                self.cx.span_err(mac.span, "suspend!(...) cannot be directly called");
-                fold::noop_fold_mac(mac, self)
-            }
+            // Every `suspend!(...)` in an expression is reported by `expr_mac`.
+            Some(Transition::Suspend(_)) |
+            Some(Transition::NeedsInput) |
+            Some(Transition::Recv) |
             None => {
                 fold::noop_fold_mac(mac, self)
             }
@@ -220,7 +522,8 @@ fn is_try_path(path: &ast::Path) -> bool {
 ///     }
 /// }
 /// ```
-fn desugar_for_loop(pat: P<ast::Pat>,
+fn desugar_for_loop(std: ast::Ident,
+                    pat: P<ast::Pat>,
                     iter: P<ast::Expr>,
                     body: P<ast::Block>,
                     label: Option<ast::SpannedIdent>) -> P<ast::Expr> {
@@ -235,7 +538,7 @@ fn desugar_for_loop(pat: P<ast::Pat>,
     let into_iter = builder.expr().call()
         .path()
             .global()
-            .ids(&["std", "iter", "IntoIterator", "into_iter"])
+            .id(std).ids(&["iter", "IntoIterator", "into_iter"])
             .build()
         .with_arg(iter)
         .build();
@@ -244,7 +547,7 @@ fn desugar_for_loop(pat: P<ast::Pat>,
     let iter_next = builder.expr().call()
         .path()
             .global()
-            .ids(&["std", "iter", "Iterator", "next"])
+            .id(std).ids(&["iter", "Iterator", "next"])
             .build()
         .arg().mut_ref().id("__stateful_iter")
         .build();
@@ -256,7 +559,7 @@ fn desugar_for_loop(pat: P<ast::Pat>,
 
     // ::std::option::Option::Some($pat)
     let some_pat = builder.pat().enum_()
-        .global().ids(&["std", "option", "Option", "Some"]).build()
+        .global().id(std).ids(&["option", "Option", "Some"]).build()
         .pat().build(pat.clone())
         .build();
 
@@ -267,7 +570,7 @@ fn desugar_for_loop(pat: P<ast::Pat>,
 
     // ::std::option::Option::None
     let none_pat = builder.pat().path()
-        .global().ids(&["std", "option", "Option", "None"]).build();
+        .global().id(std).ids(&["option", "Option", "None"]).build();
 
     // $none_pat => break,
     let none_arm = builder.arm()
@@ -310,6 +613,106 @@ fn desugar_for_loop(pat: P<ast::Pat>,
         .build()
 }
 
+/// Desugar an `#[async] for` loop over a `futures::Stream` into:
+///
+/// ```
+/// {
+///     let mut __stateful_stream = $stream;
+///     'label: loop {
+///         match await_next!(__stateful_stream) {
+///             ::std::option::Option::Some(::std::result::Result::Ok($pat)) => $body,
+///             ::std::option::Option::Some(::std::result::Result::Err(err)) => {
+///                 return ::std::result::Result::Err(::std::convert::From::from(err));
+///             }
+///             ::std::option::Option::None => break,
+///         }
+///     }
+/// }
+/// ```
+fn desugar_async_for_loop(cx: &ExtCtxt,
+                          std: ast::Ident,
+                          pat: P<ast::Pat>,
+                          stream: P<ast::Expr>,
+                          body: P<ast::Block>,
+                          label: Option<ast::SpannedIdent>) -> P<ast::Expr> {
+    let builder = AstBuilder::new().span(stream.span);
+
+    // moved!($stream)
+    let stream = builder.expr().mac().path().id("moved").build()
+        .expr().build(stream)
+        .build();
+
+    let stream_next = desugar_await_next(cx, std, builder.expr().id("__stateful_stream"));
+
+    // ::std::option::Option::Some(::std::result::Result::Ok($pat))
+    let ok_pat = builder.pat().enum_()
+        .global().id(std).ids(&["option", "Option", "Some"]).build()
+        .pat().enum_()
+            .global().id(std).ids(&["result", "Result", "Ok"]).build()
+            .pat().build(pat)
+            .build()
+        .build();
+
+    // $ok_pat => $body
+    let ok_arm = builder.arm()
+        .with_pat(ok_pat)
+        .body().build_block(body);
+
+    // ::std::option::Option::Some(::std::result::Result::Err(err)) => return ...,
+    let err_pat = builder.pat().enum_()
+        .global().id(std).ids(&["option", "Option", "Some"]).build()
+        .pat().enum_()
+            .global().id(std).ids(&["result", "Result", "Err"]).build()
+            .pat().id("err")
+            .build()
+        .build();
+
+    let err_arm = builder.arm()
+        .with_pat(err_pat)
+        .body().build(quote_expr!(cx,
+            return ::$std::result::Result::Err(::$std::convert::From::from(moved!(err)))
+        ));
+
+    // ::std::option::Option::None => break,
+    let none_arm = builder.arm()
+        .pat().path()
+            .global().id(std).ids(&["option", "Option", "None"]).build()
+        .body().break_();
+
+    // match $stream_next {
+    //     Some(Ok($pat)) => $block,
+    //     Some(Err(err)) => return Err(From::from(err)),
+    //     None => break,
+    // }
+    let match_expr = builder.expr()
+        .match_().build(stream_next)
+        .with_arm(ok_arm)
+        .with_arm(err_arm)
+        .with_arm(none_arm)
+        .build();
+
+    // `'label: loop { $match_expr; };`
+    let mut loop_builder = builder.expr().loop_();
+
+    if let Some(label) = label {
+        loop_builder = loop_builder.label(label.node);
+    }
+
+    let loop_ = loop_builder.block()
+        .stmt().build_expr(match_expr)
+        .build();
+
+    // `let mut __stateful_stream = moved!($stream);`
+    let stream = builder.stmt()
+        .let_().mut_id("__stateful_stream")
+        .build_expr(stream);
+
+    builder.expr().block()
+        .with_stmt(stream)
+        .stmt().build_expr(loop_)
+        .build()
+}
+
 /// Desugar an if-let:
 ///
 /// ```rust
@@ -436,11 +839,17 @@ fn desugar_try(expr: P<ast::Expr>) -> P<ast::Expr> {
 ///     ...
 /// }
 /// ```
-fn desugar_yield(expr: P<ast::Expr>) -> P<ast::Expr> {
+fn desugar_yield(std: ast::Ident, expr: P<ast::Expr>) -> P<ast::Expr> {
     let builder = AstBuilder::new().span(expr.span);
 
-    // Some($expr)
-    let some = builder.expr().some().build(expr);
+    // ::std::option::Option::Some($expr)
+    let some = builder.expr().call()
+        .path()
+            .global()
+            .id(std).ids(&["option", "Option", "Some"])
+            .build()
+        .with_arg(expr)
+        .build();
 
     // moved!(Some($expr))
     let moved = builder.expr().mac().path().id("moved").build()
@@ -453,57 +862,190 @@ fn desugar_yield(expr: P<ast::Expr>) -> P<ast::Expr> {
         .build()
 }
 
-/// Compile `$result = await!($expr)` into:
+/// Replace a macro that can't be used in this kind of state machine, after reporting it, so that
+/// it doesn't also show up as an undefined macro.
+fn desugar_misused_mac(span: Span) -> P<ast::Expr> {
+    // unreachable!()
+    AstBuilder::new().span(span).expr().mac().path().id("unreachable").build().build()
+}
+
+/// Compile `needs_input!()` into a suspend that tells the caller of `feed` that the parser needs
+/// more input, and evaluates to the next chunk of input when the parser is resumed. Nothing of
+/// the new chunk has been left unconsumed yet:
 ///
 /// ```
-/// 'before: {
-///     ...
-///     let future = $expr;
-///     goto!('await_loop);
+/// {
+///     parser_unconsumed = 0;
+///     suspend!(None)
 /// }
+/// ```
+fn desugar_needs_input(std: ast::Ident, unconsumed: ast::Ident, span: Span) -> P<ast::Expr> {
+    let builder = AstBuilder::new().span(span);
+
+    // parser_unconsumed = 0
+    let reset = builder.expr().assign()
+        .id(unconsumed)
+        .build(builder.expr().usize(0));
+
+    // ::std::option::Option::None
+    let none = builder.expr().path()
+        .global()
+        .id(std).ids(&["option", "Option", "None"])
+        .build();
+
+    // suspend!(::std::option::Option::None)
+    let suspend = builder.expr().mac().path().id("suspend").build()
+        .expr().build(none)
+        .build();
+
+    builder.expr().block()
+        .with_stmt(builder.stmt().semi().build(reset))
+        .build_expr(suspend)
+}
+
+/// Compile `unconsumed!($expr)` into an assignment to the number of bytes at the end of the last
+/// chunk that the parser didn't use, which is reported to the caller of `feed` when it finishes:
+///
+/// ```
+/// parser_unconsumed = $expr
+/// ```
+fn desugar_unconsumed(unconsumed: ast::Ident, expr: P<ast::Expr>) -> P<ast::Expr> {
+    AstBuilder::new().span(expr.span).expr().assign()
+        .id(unconsumed)
+        .build(expr)
+}
+
+/// Compile `recv!()` into a suspend that hands the pending reply back to the caller of `handle`,
+/// and evaluates to the message the actor is resumed with:
 ///
-/// let result;
+/// ```
+/// suspend!(actor_reply.take()).expect("actor resumed without a message")
+/// ```
 ///
-/// loop {
-///     match future.poll() {
-///         Ok(Async::NotReady) => {
-///             let () = suspend!(Ok(Async::NotReady));
-///             goto!('await_loop);
-///         }
-///         Ok(Async::Ready(result)) => {
-///             $result = Ok(result);
-///             goto!('await_exit);
-///         }
-///         Err(err) => {
-///             $result = Err(err);
-///             goto!('await_exit);
+/// Only the start state is ever resumed without a message, and it never resumes a `recv!()`.
+fn desugar_recv(std: ast::Ident, actor_reply: ast::Ident, span: Span) -> P<ast::Expr> {
+    let builder = AstBuilder::new().span(span);
+
+    // actor_reply.take()
+    let reply = builder.expr().method_call("take")
+        .id(actor_reply)
+        .build();
+
+    // suspend!(actor_reply.take())
+    let suspend = builder.expr().mac().path().id("suspend").build()
+        .expr().build(reply)
+        .build();
+
+    // ::std::option::Option::expect(suspend!(...), "actor resumed without a message")
+    builder.expr().call()
+        .path()
+            .global()
+            .id(std).ids(&["option", "Option", "expect"])
+            .build()
+        .with_arg(suspend)
+        .with_arg(builder.expr().str("actor resumed without a message"))
+        .build()
+}
+
+/// Compile `reply!($expr)` into an assignment to the pending reply, which replaces any earlier
+/// reply to the same message:
+///
+/// ```
+/// actor_reply = Some($expr)
+/// ```
+fn desugar_reply(std: ast::Ident, actor_reply: ast::Ident, expr: P<ast::Expr>) -> P<ast::Expr> {
+    let builder = AstBuilder::new().span(expr.span);
+
+    // ::std::option::Option::Some($expr)
+    let some = builder.expr().call()
+        .path()
+            .global()
+            .id(std).ids(&["option", "Option", "Some"])
+            .build()
+        .with_arg(expr)
+        .build();
+
+    // actor_reply = Some($expr)
+    builder.expr().assign()
+        .id(actor_reply)
+        .build(some)
+}
+
+/// Compile `await!($expr)` into a loop that polls the future until it completes, suspending
+/// whenever it is not ready:
+///
+/// ```
+/// {
+///     let mut await_result = None;
+///
+///     loop {
+///         match ::futures::Future::poll(&mut $expr) {
+///             Ok(Async::NotReady) => {
+///                 suspend!(Async::NotReady);
+///             }
+///             Ok(Async::Ready(ok)) => {
+///                 await_result = Some(ok);
+///                 break;
+///             }
+///             Err(err) => {
+///                 return Err(::std::convert::From::from(err));
+///             }
 ///         }
 ///     }
-/// }
 ///
-/// 'await_exit: {
-///     ...
+///     await_result.unwrap()
 /// }
 /// ```
-fn desugar_await(cx: &ExtCtxt, future_expr: P<ast::Expr>) -> P<ast::Expr> {
+///
+/// Like `?`, errors are converted into the error type of the enclosing future with `From::from`.
+fn desugar_await(cx: &ExtCtxt, std: ast::Ident, future_expr: P<ast::Expr>) -> P<ast::Expr> {
     quote_expr!(cx,
         {
-            let mut await_result = ::std::option::Option::None;
+            let mut await_result = ::$std::option::Option::None;
 
             loop {
                 match ::futures::Future::poll(&mut $future_expr) {
-                    ::std::result::Result::Ok(::futures::Async::NotReady) => {
+                    ::$std::result::Result::Ok(::futures::Async::NotReady) => {
                         suspend!(::futures::Async::NotReady);
                     }
-                    ::std::result::Result::Ok(::futures::Async::Ready(ok)) => {
-                        await_result = ::std::option::Option::Some(
-                            ::std::result::Result::Ok(moved!(ok)));
+                    ::$std::result::Result::Ok(::futures::Async::Ready(ok)) => {
+                        await_result = ::$std::option::Option::Some(moved!(ok));
 
                         break;
                     }
-                    ::std::result::Result::Err(err) => {
-                        await_result = ::std::option::Option::Some(
-                            ::std::result::Result::Err(moved!(err)));
+                    ::$std::result::Result::Err(err) => {
+                        return ::$std::result::Result::Err(
+                            ::$std::convert::From::from(moved!(err)));
+                    }
+                }
+            }
+
+            moved!(await_result).unwrap()
+        }
+    )
+}
+
+/// Compile `await_result!($expr)` into the same polling loop as `await!`, but evaluate to the
+/// future's raw `Result<Item, Error>` instead of propagating the error.
+fn desugar_await_result(cx: &ExtCtxt, std: ast::Ident, future_expr: P<ast::Expr>) -> P<ast::Expr> {
+    quote_expr!(cx,
+        {
+            let mut await_result = ::$std::option::Option::None;
+
+            loop {
+                match ::futures::Future::poll(&mut $future_expr) {
+                    ::$std::result::Result::Ok(::futures::Async::NotReady) => {
+                        suspend!(::futures::Async::NotReady);
+                    }
+                    ::$std::result::Result::Ok(::futures::Async::Ready(ok)) => {
+                        await_result = ::$std::option::Option::Some(
+                            ::$std::result::Result::Ok(moved!(ok)));
+
+                        break;
+                    }
+                    ::$std::result::Result::Err(err) => {
+                        await_result = ::$std::option::Option::Some(
+                            ::$std::result::Result::Err(moved!(err)));
 
                         break;
                     }
@@ -514,3 +1056,69 @@ fn desugar_await(cx: &ExtCtxt, future_expr: P<ast::Expr>) -> P<ast::Expr> {
         }
     )
 }
+
+/// Compile `await_next!($stream)` into a loop that polls the stream until it produces the next
+/// item, suspending whenever it is not ready:
+///
+/// ```
+/// {
+///     let mut await_next_result = None;
+///
+///     loop {
+///         match ::futures::Stream::poll(&mut $stream) {
+///             Ok(Async::NotReady) => {
+///                 suspend!(Async::NotReady);
+///             }
+///             Ok(Async::Ready(Some(item))) => {
+///                 await_next_result = Some(Ok(item));
+///                 break;
+///             }
+///             Ok(Async::Ready(None)) => {
+///                 break;
+///             }
+///             Err(err) => {
+///                 await_next_result = Some(Err(err));
+///                 break;
+///             }
+///         }
+///     }
+///
+///     await_next_result
+/// }
+/// ```
+///
+/// The result is an `Option<Result<Item, Error>>`, where `None` means the stream is exhausted.
+fn desugar_await_next(cx: &ExtCtxt, std: ast::Ident, stream_expr: P<ast::Expr>) -> P<ast::Expr> {
+    quote_expr!(cx,
+        {
+            let mut await_next_result = ::$std::option::Option::None;
+
+            loop {
+                match ::futures::Stream::poll(&mut $stream_expr) {
+                    ::$std::result::Result::Ok(::futures::Async::NotReady) => {
+                        suspend!(::futures::Async::NotReady);
+                    }
+                    ::$std::result::Result::Ok(
+                        ::futures::Async::Ready(::$std::option::Option::Some(item))) => {
+                        await_next_result = ::$std::option::Option::Some(
+                            ::$std::result::Result::Ok(moved!(item)));
+
+                        break;
+                    }
+                    ::$std::result::Result::Ok(
+                        ::futures::Async::Ready(::$std::option::Option::None)) => {
+                        break;
+                    }
+                    ::$std::result::Result::Err(err) => {
+                        await_next_result = ::$std::option::Option::Some(
+                            ::$std::result::Result::Err(moved!(err)));
+
+                        break;
+                    }
+                }
+            }
+
+            moved!(await_next_result)
+        }
+    )
+}
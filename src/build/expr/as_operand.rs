@@ -26,7 +26,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             ExprKind::Path(..) => {
                 // Path operands don't need a temporary.
                 let operand = unpack!(block = this.as_lvalue(block, expr));
-                block.and(Operand::Copy(operand))
+                block.and(Operand::Copy(operand, expr.span))
             }
 
             // NOTE(stateful): As opposed to MIR, we treat all operands as copy by default, since
@@ -40,7 +40,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     ExprKind::Path(..) => {
                         // Path operands don't need a temporary.
                         let operand = unpack!(block = this.as_lvalue(block, &expr));
-                        block.and(Operand::Consume(operand))
+                        block.and(Operand::Consume(operand, expr.span))
                     }
                     _ => {
                         let category = Category::of(&expr.node).unwrap();
@@ -53,7 +53,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                             Category::Lvalue |
                             Category::Rvalue(..) => {
                                 let operand = unpack!(block = this.as_temp(block, &expr));
-                                block.and(Operand::Consume(operand))
+                                block.and(Operand::Consume(operand, expr.span))
                             }
                         }
                     }
@@ -71,7 +71,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     Category::Lvalue |
                     Category::Rvalue(..) => {
                         let operand = unpack!(block = this.as_temp(block, expr));
-                        block.and(Operand::Copy(operand))
+                        block.and(Operand::Copy(operand, expr.span))
                     }
                 }
             }
@@ -50,7 +50,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 match Category::of(&expr.node).unwrap() {
                     Category::Lvalue => {
                         let lvalue = unpack!(block = this.as_lvalue(block, expr));
-                        let rvalue = Rvalue::Use(Operand::Consume(lvalue));
+                        let rvalue = Rvalue::Use(Operand::Consume(lvalue, expr_span));
                         this.cfg.push_assign(block, source_info, &temp, rvalue);
                     }
                     _ => {
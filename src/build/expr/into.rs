@@ -219,7 +219,11 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         let this = self;
 
-        let loop_block = this.cfg.start_new_block(source_info.span, Some("Loop"));
+        // The states of a `#[state_machine]` already have their entry block.
+        let loop_block = match this.state_block(source_info.span) {
+            Some(loop_block) => loop_block,
+            None => this.cfg.start_new_block(source_info.span, Some("Loop")),
+        };
         let exit_block = this.cfg.start_new_block(source_info.span, Some("LoopExit"));
 
         // start the loop
@@ -80,13 +80,14 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 // is better for borrowck interaction with overloaded
                 // operators like x[j] = x[i].
 
+                let lhs_span = lhs.span;
                 let rhs = unpack!(block = this.as_operand(block, rhs));
                 let lhs = unpack!(block = this.as_lvalue(block, lhs));
                 let result = unpack!(
                     block = this.build_binary_op(
                         block,
                         op,
-                        Operand::Consume(lhs.clone()),
+                        Operand::Consume(lhs.clone(), lhs_span),
                         rhs));
 
                 this.cfg.push_assign(block, source_info, &lhs, result);
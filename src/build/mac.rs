@@ -1,5 +1,5 @@
 use aster::AstBuilder;
-use build::{BlockAnd, Builder, transition};
+use build::{BlockAnd, BlockAndExtension, Builder, transition};
 use mir::*;
 use syntax::ast;
 use syntax::ext::base::ExtCtxt;
@@ -19,7 +19,14 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 Some(self.expr_suspend(destination, block, expr))
             }
             _ => {
-                if is_path(&mac.node.path, "moved") {
+                if is_path(&mac.node.path, "goto") {
+                    // Nothing ever gets assigned by a `goto!`, but the destination still has to
+                    // be initialized in the unreachable code after it.
+                    let source_info = self.source_info(mac.span);
+                    let block = unpack!(self.expr_goto(block, mac));
+                    self.cfg.push_assign_unit(block, source_info, &destination);
+                    Some(block.unit())
+                } else if is_path(&mac.node.path, "moved") {
                     let expr = parse_mac(self.cx, mac);
                     Some(self.into(destination, block, &expr))
                 } else if is_path(&mac.node.path, "copied") {
@@ -49,6 +56,28 @@ pub fn parse_mac(cx: &ExtCtxt, mac: &ast::Mac) -> P<ast::Expr> {
     expr
 }
 
+/// Parse the `'state` out of a `goto!('state)`.
+pub fn parse_mac_label(cx: &ExtCtxt, mac: &ast::Mac) -> ast::Ident {
+    let tts = quoted::parse(&mac.node.tts, false, &cx.parse_sess());
+    let rdr = transcribe::transcribe(&cx.parse_sess().span_diagnostic, None, tts);
+
+    let mut parser = Parser::new(
+        cx.parse_sess(),
+        rdr.clone(),
+        None,
+        false);
+
+    if let Token::Lifetime(label) = parser.token {
+        parser.bump();
+        panictry!(parser.expect(&Token::Eof));
+
+        return label;
+    }
+
+    let token = parser.this_token_to_string();
+    panictry!(Err(parser.fatal(&format!("expected a state label, found `{}`", token))))
+}
+
 pub fn is_mac(mac: &ast::Mac, name: &str) -> bool {
     is_path(&mac.node.path, name)
 }
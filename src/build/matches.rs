@@ -18,7 +18,7 @@ use build::{BlockAnd, BlockAndExtension, Builder};
 use mir::*;
 use std::ascii::AsciiExt;
 use std::collections::HashMap;
-use syntax::ast::{self, PatKind};
+use syntax::ast::{self, PatKind, TyKind};
 use syntax::codemap::Span;
 use syntax::ptr::P;
 use syntax::visit::{self, Visitor};
@@ -144,7 +144,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 pat: irrefutable_pat.clone(),
                 ty: self.ty_indices.get(&irrefutable_pat.id).map(|ty| ty.clone()),
                 lvalues: lvalues,
-                rvalue: Rvalue::Use(Operand::Consume(initializer.clone())),
+                rvalue: Rvalue::Use(Operand::Consume(initializer.clone(), span)),
             }
         });
 
@@ -166,7 +166,17 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         match pat.node {
             // Consider only lower case identities as a variable.
-            PatKind::Ident(ast::BindingMode::ByValue(mutability), id, _) if self.is_local(id) => {
+            PatKind::Ident(binding_mode, id, _) if self.is_local(id) => {
+                let mutability = match binding_mode {
+                    ast::BindingMode::ByValue(mutability) => mutability,
+                    ast::BindingMode::ByRef(_) => {
+                        self.cx.span_err(
+                            pat.span,
+                            "`ref` bindings are not supported in state machine functions");
+                        ast::Mutability::Immutable
+                    }
+                };
+
                 if var_scope.is_none() {
                     var_scope = Some(self.new_visibility_scope(pat.span));
                 }
@@ -182,7 +192,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     pat.id,
                     ty.clone());
             }
-            PatKind::Ident(ast::BindingMode::ByValue(_), _, _) => { }
+            PatKind::Ident(..) => { }
 
             PatKind::Struct(_, ref subpatterns, _) => {
                 for field in subpatterns {
@@ -195,8 +205,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 }
             }
 
-            PatKind::TupleStruct(_, ref subpatterns, _) |
-            PatKind::Tuple(ref subpatterns, _) => {
+            PatKind::TupleStruct(_, ref subpatterns, _) => {
                 for subpattern in subpatterns {
                     var_scope = self.declare_bindings(
                         block,
@@ -207,6 +216,25 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 }
             }
 
+            PatKind::Tuple(ref subpatterns, ddpos) => {
+                // In `let (a, b): (A, B)`, each binding gets its part of the tuple type.
+                let tys = match (ddpos, ty.as_ref().map(|ty| &ty.node)) {
+                    (None, Some(&TyKind::Tup(ref tys))) if tys.len() == subpatterns.len() => {
+                        tys.iter().cloned().map(Some).collect()
+                    }
+                    _ => vec![None; subpatterns.len()],
+                };
+
+                for (subpattern, ty) in subpatterns.iter().zip(&tys) {
+                    var_scope = self.declare_bindings(
+                        block,
+                        var_scope,
+                        scope_span,
+                        subpattern,
+                        ty);
+                }
+            }
+
             PatKind::Slice(ref prefix, ref slice, ref suffix) => {
                 for subpattern in prefix.iter().chain(slice).chain(suffix) {
                     var_scope = self.declare_bindings(
@@ -224,11 +252,29 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             PatKind::Range(..) |
             PatKind::Wild => { }
 
-            PatKind::Ident(ast::BindingMode::ByRef(_), _, _) |
-            PatKind::Box(_) |
-            PatKind::Ref(_, _) |
+            PatKind::Box(ref subpattern) |
+            PatKind::Ref(ref subpattern, _) => {
+                let kind = match pat.node {
+                    PatKind::Box(_) => "`box`",
+                    _ => "`&`",
+                };
+
+                self.cx.span_err(
+                    pat.span,
+                    &format!("{} patterns are not supported in state machine functions", kind));
+
+                var_scope = self.declare_bindings(
+                    block,
+                    var_scope,
+                    scope_span,
+                    subpattern,
+                    &None);
+            }
+
             PatKind::Mac(_) => {
-                span_bug!(self.cx, pat.span, "Cannot handle pat {:?}", pat)
+                self.cx.span_err(
+                    pat.span,
+                    "macros in patterns are not supported in state machine functions");
             }
         }
 
@@ -254,7 +300,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 }
                 */
             }
-            PatKind::Ident(ast::BindingMode::ByValue(_), _, _) => { }
+            PatKind::Ident(..) => { }
 
             PatKind::Struct(_, ref subpatterns, _) => {
                 for field in subpatterns {
@@ -281,12 +327,12 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             PatKind::Range(..) |
             PatKind::Wild => { }
 
-            PatKind::Ident(ast::BindingMode::ByRef(_), _, _) |
-            PatKind::Box(_) |
-            PatKind::Ref(_, _) |
-            PatKind::Mac(_) => {
-                span_bug!(self.cx, pattern.span, "Cannot handle pat {:?}", pattern)
+            // These were already reported by `declare_bindings`.
+            PatKind::Box(ref subpattern) |
+            PatKind::Ref(ref subpattern, _) => {
+                self.storage_live_for_bindings(block, subpattern);
             }
+            PatKind::Mac(_) => { }
 
             /*
             PatternKind::Array { ref prefix, ref slice, ref suffix } |
@@ -332,7 +378,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             let shadowed_lvalue = Lvalue::Local(shadowed_local);
 
             let temp = self.temp(block, source_info.span, "shadowed");
-            let rvalue = Rvalue::Use(Operand::Consume(shadowed_lvalue));
+            let rvalue = Rvalue::Use(Operand::Consume(shadowed_lvalue, source_info.span));
             self.cfg.push_assign(block, source_info, &temp, rvalue);
         }
 
@@ -341,6 +387,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             name: name,
             ty: var_ty,
             source_info: source_info,
+            is_user_variable: true,
         });
         let extent = self.extent_of_innermost_scope();
         self.schedule_drop(source_info.span, extent, &Lvalue::Local(var));
@@ -22,6 +22,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             name: ident,
             ty: None,
             source_info: source_info,
+            is_user_variable: false,
         });
         let lvalue = Lvalue::Local(temp);
 
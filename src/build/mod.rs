@@ -1,3 +1,4 @@
+use config::Config;
 use data_structures::indexed_vec::{Idx, IndexVec};
 use mir::*;
 use std::collections::HashMap;
@@ -11,6 +12,7 @@ pub struct Builder<'a, 'b: 'a> {
     cx: &'a ExtCtxt<'b>,
     cfg: CFG,
     state_machine_kind: StateMachineKind,
+    config: Config,
 
     fn_span: Span,
 
@@ -42,6 +44,12 @@ pub struct Builder<'a, 'b: 'a> {
 
     /// cached block with the RETURN terminator
     cached_return_block: Option<BasicBlock>,
+
+    /// The labelled states of a `#[state_machine]`; see the `state_machine` module.
+    states: Vec<state_machine::State>,
+
+    /// The extent that encloses all the states, which `goto!` exits to.
+    states_extent: Option<CodeExtent>,
 }
 
 #[derive(Debug)]
@@ -135,19 +143,22 @@ macro_rules! unpack {
 
 pub fn construct_fn(cx: &ExtCtxt,
                     state_machine_kind: StateMachineKind,
+                    config: Config,
                     span: Span,
                     fn_decl: FunctionDecl,
                     ast_block: P<ast::Block>) -> Mir {
     let (fn_decl, ast_block) = desugar::desugar_block(
         cx,
         state_machine_kind,
+        &config,
         fn_decl,
         ast_block);
 
     let mut builder = Builder::new(
         cx,
         span,
-        state_machine_kind);
+        state_machine_kind,
+        config);
 
     let call_site_extent = builder.extents.push(CodeExtentData::CallSiteScope);
     let arg_extent = builder.extents.push(CodeExtentData::ParameterScope);
@@ -173,12 +184,14 @@ pub fn construct_fn(cx: &ExtCtxt,
 impl<'a, 'b: 'a> Builder<'a, 'b> {
     fn new(cx: &'a ExtCtxt<'b>,
            span: Span,
-           state_machine_kind: StateMachineKind) -> Self {
+           state_machine_kind: StateMachineKind,
+           config: Config) -> Self {
         let mut builder = Builder {
             cx: cx,
             cfg: CFG { basic_blocks: IndexVec::new() },
             fn_span: span,
             state_machine_kind: state_machine_kind,
+            config: config,
             scopes: vec![],
             scope_auxiliary: IndexVec::new(),
             visibility_scopes: IndexVec::new(),
@@ -189,6 +202,8 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             local_decls: IndexVec::new(),
             extents: IndexVec::new(),
             cached_return_block: None,
+            states: vec![],
+            states_extent: None,
         };
 
         assert_eq!(builder.cfg.start_new_block(span, Some("Start")), START_BLOCK);
@@ -233,6 +248,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         Mir::new(
             self.state_machine_kind,
+            self.config,
             self.cfg.basic_blocks,
             self.visibility_scopes,
             self.local_decls,
@@ -266,6 +282,10 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             self.visibility_scope = visibility_scope;
         }
 
+        if self.state_machine_kind == StateMachineKind::StateMachine {
+            self.declare_states(&ast_block);
+        }
+
         unpack!(block = self.ast_block(Lvalue::Local(RETURN_POINTER), block, &ast_block));
 
         block.unit()
@@ -306,5 +326,6 @@ mod matches;
 mod misc;
 mod moved;
 mod scope;
+mod state_machine;
 mod suspend;
 mod transition;
@@ -0,0 +1,185 @@
+//! A `#[state_machine]` is written as a list of labelled states:
+//!
+//! ```rust
+//! #[state_machine]
+//! fn lexer(input: Vec<u8>) -> Box<Iterator<Item=Token>> {
+//!     let mut pos = 0;
+//!
+//!     'start: loop {
+//!         ...
+//!         goto!('ident);
+//!     }
+//!
+//!     'ident: loop {
+//!         ...
+//!         suspend!(Token::Ident(ident));
+//!         goto!('start);
+//!     }
+//! }
+//! ```
+//!
+//! Any statements before the first state run once, and then the machine enters the first state.
+//! Each state is an ordinary `loop`, so `continue` restarts it, and `break` falls through into the
+//! next state (or out of the machine after the last one). `goto!('state)` jumps to the start of
+//! any state, exiting all the scopes it is in, just like a `break` would.
+//!
+//! The entry blocks of the states are created before any of them are built, so that a state can
+//! jump to a state that comes after it. Each state becomes a variant of the state enum named after
+//! its label, so `'read_header` is `ReadHeader`, and so the labels have to give distinct names.
+
+use build::{BlockAnd, BlockAndExtension, Builder};
+use build::mac::parse_mac_label;
+use mir::*;
+use syntax::ast::{self, ExprKind, StmtKind};
+use syntax::codemap::Span;
+
+pub struct State {
+    pub label: ast::Ident,
+    pub span: Span,
+    pub block: BasicBlock,
+}
+
+impl<'a, 'b: 'a> Builder<'a, 'b> {
+    /// Create the entry blocks for the states of a `#[state_machine]` body.
+    pub fn declare_states(&mut self, ast_block: &ast::Block) {
+        for stmt in &ast_block.stmts {
+            let (label, span) = match state_label(stmt) {
+                Some(state) => state,
+                None => {
+                    if !self.states.is_empty() {
+                        self.cx.span_err(
+                            stmt.span,
+                            "only labelled `'state: loop { ... }` states may follow the first \
+                             state");
+                    }
+
+                    continue;
+                }
+            };
+
+            if self.states.iter().any(|state| state.label.name == label.name) {
+                self.cx.span_err(span, &format!("state `{}` is declared twice", label));
+                continue;
+            }
+
+            // The state's variant is named after its label, so the name has to be usable.
+            let variant = camel_case(&label.name.as_str());
+
+            if variant.is_empty() {
+                self.cx.span_err(span, &format!("cannot name a state after `{}`", label));
+                continue;
+            }
+
+            if variant.starts_with("State") &&
+                variant[5..].chars().next().map_or(false, |c| c.is_digit(10))
+            {
+                self.cx.span_err(
+                    span,
+                    &format!("state `{}` would be named `{}`, which is reserved for the states \
+                              stateful generates",
+                             label,
+                             variant));
+                continue;
+            }
+
+            let collision = self.states.iter()
+                .find(|state| camel_case(&state.label.name.as_str()) == variant)
+                .map(|state| state.label);
+
+            if let Some(other) = collision {
+                self.cx.span_err(
+                    span,
+                    &format!("states `{}` and `{}` would both be named `{}`",
+                             other,
+                             label,
+                             variant));
+                continue;
+            }
+
+            let block = self.cfg.start_new_block(span, Some("Loop"));
+            self.cfg.block_data_mut(block).label = Some(label);
+
+            self.states.push(State {
+                label: label,
+                span: span,
+                block: block,
+            });
+        }
+
+        if self.states.is_empty() {
+            self.cx.span_err(
+                ast_block.span,
+                "`state_machine` functions need at least one labelled `'state: loop { ... }`");
+        }
+    }
+
+    /// Note that we are about to build a state. All the states are siblings, so the scope we are
+    /// in is the one a `goto!` needs to exit to.
+    pub fn enter_state(&mut self, expr: &ast::Expr) {
+        if self.states_extent.is_none() && self.state_block(expr.span).is_some() {
+            self.states_extent = Some(self.extent_of_innermost_scope());
+        }
+    }
+
+    /// The entry block of the state declared at `span`, if there is one.
+    pub fn state_block(&self, span: Span) -> Option<BasicBlock> {
+        self.states.iter()
+            .find(|state| state.span == span)
+            .map(|state| state.block)
+    }
+
+    /// Compile `goto!('state)` into a jump to the entry block of the state, exiting all the scopes
+    /// of the current state along the way:
+    ///
+    /// ```
+    /// 'block:
+    ///     break 'state;
+    ///
+    /// 'after_goto:
+    ///     ...
+    /// ```
+    pub fn expr_goto(&mut self, block: BasicBlock, mac: &ast::Mac) -> BlockAnd<()> {
+        let span = mac.span;
+
+        if self.state_machine_kind != StateMachineKind::StateMachine {
+            self.cx.span_err(span, "`goto!(...)` can only be used in `#[state_machine]` functions");
+            return block.unit();
+        }
+
+        let label = parse_mac_label(self.cx, mac);
+
+        let target = match self.states.iter().find(|state| state.label.name == label.name) {
+            Some(state) => state.block,
+            None => {
+                self.cx.span_err(span, &format!("use of undeclared state `{}`", label));
+                return block.unit();
+            }
+        };
+
+        let extent = match self.states_extent {
+            Some(extent) => extent,
+            None => {
+                self.cx.span_err(span, "`goto!(...)` can only be used inside of a state");
+                return block.unit();
+            }
+        };
+
+        let after_block = self.cfg.start_new_block(span, Some("AfterGoto"));
+        self.exit_scope(span, extent, block, target, after_block);
+
+        after_block.unit()
+    }
+}
+
+/// The label and span of a `'state: loop { ... }` statement.
+fn state_label(stmt: &ast::Stmt) -> Option<(ast::Ident, Span)> {
+    match stmt.node {
+        StmtKind::Expr(ref expr) | StmtKind::Semi(ref expr) => {
+            match expr.node {
+                ExprKind::Loop(_, Some(label)) => Some((label.node, expr.span)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
@@ -22,7 +22,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             arg: arg,
         });
 
-        let coroutine_args = Operand::Consume(coroutine_args);
+        let coroutine_args = Operand::Consume(coroutine_args, arg_span);
         let coroutine_args = Rvalue::Use(coroutine_args);
 
         self.cfg.push_assign(next_block, source_info, &destination, coroutine_args);
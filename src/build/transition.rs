@@ -114,17 +114,43 @@ impl visit::Visitor for ContainsTransitionVisitor {
 
 pub enum Transition {
     Yield(P<ast::Expr>),
+    YieldFrom(P<ast::Expr>),
     Await(P<ast::Expr>),
+    AwaitResult(P<ast::Expr>),
+    AwaitNext(P<ast::Expr>),
     Suspend(P<ast::Expr>),
+    NeedsInput,
+    Recv,
+    Reply(P<ast::Expr>),
 }
 
 pub fn parse_mac_transition(cx: &ExtCtxt, mac: &ast::Mac) -> Option<Transition> {
     if is_yield_path(&mac.node.path) {
         Some(Transition::Yield(parse_mac(cx, mac)))
+    } else if is_yield_from_path(&mac.node.path) {
+        Some(Transition::YieldFrom(parse_mac(cx, mac)))
     } else if is_await_path(&mac.node.path) {
         Some(Transition::Await(parse_mac(cx, mac)))
+    } else if is_await_result_path(&mac.node.path) {
+        Some(Transition::AwaitResult(parse_mac(cx, mac)))
+    } else if is_await_next_path(&mac.node.path) {
+        Some(Transition::AwaitNext(parse_mac(cx, mac)))
     } else if is_suspend_path(&mac.node.path) {
         Some(Transition::Suspend(parse_mac(cx, mac)))
+    } else if is_needs_input_path(&mac.node.path) {
+        if !mac.node.tts.is_empty() {
+            cx.span_err(mac.span, "`needs_input!()` does not take any arguments");
+        }
+
+        Some(Transition::NeedsInput)
+    } else if is_recv_path(&mac.node.path) {
+        if !mac.node.tts.is_empty() {
+            cx.span_err(mac.span, "`recv!()` does not take any arguments");
+        }
+
+        Some(Transition::Recv)
+    } else if is_reply_path(&mac.node.path) {
+        Some(Transition::Reply(parse_mac(cx, mac)))
     } else {
         None
     }
@@ -147,14 +173,38 @@ fn is_yield_path(path: &ast::Path) -> bool {
     is_path(path, "yield_")
 }
 
+fn is_yield_from_path(path: &ast::Path) -> bool {
+    is_path(path, "yield_from")
+}
+
 fn is_await_path(path: &ast::Path) -> bool {
     is_path(path, "await")
 }
 
+fn is_await_result_path(path: &ast::Path) -> bool {
+    is_path(path, "await_result")
+}
+
+fn is_await_next_path(path: &ast::Path) -> bool {
+    is_path(path, "await_next")
+}
+
 fn is_suspend_path(path: &ast::Path) -> bool {
     is_path(path, "suspend")
 }
 
+fn is_needs_input_path(path: &ast::Path) -> bool {
+    is_path(path, "needs_input")
+}
+
+fn is_recv_path(path: &ast::Path) -> bool {
+    is_path(path, "recv")
+}
+
+fn is_reply_path(path: &ast::Path) -> bool {
+    is_path(path, "reply")
+}
+
 /*
 fn is_moved_path(path: &ast::Path) -> bool {
     is_path(path, "moved")
@@ -0,0 +1,317 @@
+//! Per-function options given as arguments to the state machine attributes, as in
+//! `#[generator(no_std)]`.
+
+use aster::ident::ToIdent;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use syntax::ast;
+use syntax::codemap::Span;
+use syntax::ext::base::ExtCtxt;
+use transform;
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Generate code that only depends on `core` and never allocates.
+    pub no_std: bool,
+
+    /// Expose a generator of byte chunks as a `std::io::Read`, as in `#[generator(read)]`.
+    pub read: bool,
+
+    /// Expose a push parser as a `std::io::Write` that feeds it every written slice, as in
+    /// `#[push_parser(write)]`.
+    pub write: bool,
+
+    /// Which diagnostics to write out, as in `dump(mir, source)`.
+    pub dump: Dump,
+
+    /// The directory the dumps are written into. Defaults to the current directory.
+    pub dump_dir: Option<PathBuf>,
+
+    /// The line of the attribute, which tells apart the dumps of functions with the same name.
+    pub dump_line: Option<usize>,
+
+    /// Only dump MIR around the passes whose name or disambiguator contains one of these
+    /// strings, as in `dump_passes(SimplifyCfg, initial)`. Dumps every pass if empty.
+    pub dump_passes: Vec<String>,
+
+    /// How much to optimize the MIR before translating it, as in `opt = 1`.
+    pub opt_level: OptLevel,
+
+    /// Skip the passes with these names, as in `disable_passes(ConstProp)`. Useful to track down
+    /// a misbehaving pass.
+    pub disable_passes: Vec<String>,
+
+    /// Interpret the MIR with these arguments around every pass, and report any pass that
+    /// changes what it suspends with or returns, as in `interpret(3, true)`.
+    pub interpret: Option<Vec<ast::Lit>>,
+}
+
+/// The optimization levels, from `opt = 0` to `opt = 2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Translate the MIR exactly as it was built.
+    None,
+
+    /// Clean up the CFG and the temporaries the builder leaves behind.
+    Basic,
+
+    /// Also fold constant branches and remove the code they make unreachable.
+    Full,
+}
+
+impl Default for OptLevel {
+    fn default() -> Self {
+        OptLevel::Full
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Dump {
+    /// Dump the MIR in the `.mar` text format before and after every pass.
+    pub mir: bool,
+
+    /// Dump the MIR as JSON before and after every pass.
+    pub json: bool,
+
+    /// Dump the MIR as a graphviz graph before and after every pass.
+    pub graphviz: bool,
+
+    /// Dump a diagram of the generated states, both as graphviz and as Mermaid.
+    pub states: bool,
+
+    /// Dump the translated Rust source.
+    pub source: bool,
+
+    /// Dump graphviz renderings of the dataflow analyses before and after propagation.
+    pub dataflow: bool,
+}
+
+impl Config {
+    pub fn from_meta_item(cx: &ExtCtxt, meta_item: &ast::MetaItem) -> Self {
+        let mut config = Config::default();
+        config.dump_line = Some(cx.codemap().lookup_char_pos(meta_item.span.lo).line);
+
+        let items = match meta_item.meta_item_list() {
+            Some(items) => items,
+            None => { return config; }
+        };
+
+        for item in items {
+            let meta_item = match item.meta_item() {
+                Some(meta_item) => meta_item,
+                None => {
+                    cx.span_err(item.span, "expected an option, found a literal");
+                    continue;
+                }
+            };
+
+            match &*meta_item.name().as_str() {
+                "no_std" if meta_item.is_word() => {
+                    config.no_std = true;
+                }
+                "read" if meta_item.is_word() => {
+                    config.read = true;
+                }
+                "write" if meta_item.is_word() => {
+                    config.write = true;
+                }
+                "dump" => {
+                    config.dump = Dump::from_meta_item(cx, meta_item);
+                }
+                "dump_passes" => {
+                    for pass in option_list(cx, meta_item) {
+                        match pass.meta_item() {
+                            Some(pass) if pass.is_word() => {
+                                config.dump_passes.push(pass.name().to_string());
+                            }
+                            _ => {
+                                cx.span_err(pass.span, "expected a pass name");
+                            }
+                        }
+                    }
+                }
+                "disable_passes" => {
+                    for pass in option_list(cx, meta_item) {
+                        match pass.meta_item() {
+                            Some(meta_item) if meta_item.is_word() => {
+                                let name = meta_item.name().to_string();
+                                if transform::is_known_pass(&name) {
+                                    config.disable_passes.push(name);
+                                } else {
+                                    cx.span_err(pass.span, &format!("unknown pass `{}`", name));
+                                }
+                            }
+                            _ => {
+                                cx.span_err(pass.span, "expected a pass name");
+                            }
+                        }
+                    }
+                }
+                "interpret" => {
+                    let mut args = vec![];
+                    for arg in meta_item.meta_item_list().unwrap_or(&[]) {
+                        match arg.literal() {
+                            Some(lit) => { args.push(lit.clone()); }
+                            None => {
+                                cx.span_err(arg.span, "expected a literal argument");
+                            }
+                        }
+                    }
+                    config.interpret = Some(args);
+                }
+                "opt" => {
+                    match OptLevel::from_meta_item(meta_item) {
+                        Some(opt_level) => {
+                            config.opt_level = opt_level;
+                        }
+                        None => {
+                            cx.span_err(
+                                meta_item.span,
+                                "expected `opt = 0`, `opt = 1` or `opt = 2`");
+                        }
+                    }
+                }
+                "dump_dir" => {
+                    match meta_item.value_str() {
+                        Some(dump_dir) => {
+                            config.dump_dir = Some(PathBuf::from(&*dump_dir.as_str()));
+                        }
+                        None => {
+                            cx.span_err(meta_item.span, "expected `dump_dir = \"...\"`");
+                        }
+                    }
+                }
+                name => {
+                    cx.span_err(
+                        meta_item.span,
+                        &format!("unknown state machine option `{}`", name));
+                }
+            }
+        }
+
+        config
+    }
+
+    /// Should the MIR be dumped around this pass?
+    pub fn dump_pass(&self, pass_name: &str, disambiguator: &str) -> bool {
+        self.dump_passes.is_empty() ||
+            self.dump_passes.iter().any(|filter| {
+                pass_name.contains(&**filter) || disambiguator.contains(&**filter)
+            })
+    }
+
+    /// Should this pass run at all?
+    pub fn pass_enabled(&self, pass_name: &str) -> bool {
+        !self.disable_passes.iter().any(|name| name == pass_name)
+    }
+
+    /// The path of a dump file for the function `ident`, such as `stateful.gen.12.rs` for a `gen`
+    /// declared on line 12.
+    pub fn dump_path(&self, ident: ast::Ident, suffix: &str) -> PathBuf {
+        let file_name = match self.dump_line {
+            Some(line) => format!("stateful.{}.{}.{}", ident, line, suffix),
+            None => format!("stateful.{}.{}", ident, suffix),
+        };
+
+        match self.dump_dir {
+            Some(ref dump_dir) => dump_dir.join(file_name),
+            None => PathBuf::from(file_name),
+        }
+    }
+
+    /// Create the dump file at `path` and fill it in with `write`. A dump that can't be written,
+    /// say because `dump_dir` doesn't exist, is reported as an error at `span`.
+    pub fn write_dump<F>(&self, cx: &ExtCtxt, span: Span, path: &Path, write: F)
+        where F: FnOnce(&mut fs::File) -> io::Result<()>
+    {
+        let result = fs::File::create(path).and_then(|mut file| write(&mut file));
+
+        if let Err(err) = result {
+            cx.span_err(span, &format!("could not write `{}`: {}", path.display(), err));
+        }
+    }
+
+    /// The crate generated code should use for language items like `Option`.
+    pub fn std_crate(&self) -> ast::Ident {
+        if self.no_std {
+            "core".to_ident()
+        } else {
+            "std".to_ident()
+        }
+    }
+}
+
+/// The arguments of an option that takes a list, as in `dump_passes(SimplifyCfg, initial)`.
+fn option_list<'a>(cx: &ExtCtxt, meta_item: &'a ast::MetaItem) -> &'a [ast::NestedMetaItem] {
+    match meta_item.meta_item_list() {
+        Some(items) => items,
+        None => {
+            cx.span_err(
+                meta_item.span,
+                &format!("expected `{}(...)`", meta_item.name()));
+            &[]
+        }
+    }
+}
+
+impl OptLevel {
+    /// Parse `opt = 1`, or `opt = "1"` for crates that don't enable `attr_literals`.
+    fn from_meta_item(meta_item: &ast::MetaItem) -> Option<Self> {
+        let level = match meta_item.node {
+            ast::MetaItemKind::NameValue(ref lit) => {
+                match lit.node {
+                    ast::LitKind::Int(level, _) => level.to_string(),
+                    ast::LitKind::Str(level, _) => level.to_string(),
+                    _ => { return None; }
+                }
+            }
+            _ => { return None; }
+        };
+
+        match &*level {
+            "0" => Some(OptLevel::None),
+            "1" => Some(OptLevel::Basic),
+            "2" => Some(OptLevel::Full),
+            _ => None,
+        }
+    }
+}
+
+impl Dump {
+    fn from_meta_item(cx: &ExtCtxt, meta_item: &ast::MetaItem) -> Self {
+        let mut dump = Dump::default();
+
+        let items = match meta_item.meta_item_list() {
+            Some(items) => items,
+            None => {
+                cx.span_err(meta_item.span, "expected `dump(...)`");
+                return dump;
+            }
+        };
+
+        for item in items {
+            let name = match item.meta_item() {
+                Some(meta_item) if meta_item.is_word() => meta_item.name().as_str(),
+                _ => {
+                    cx.span_err(item.span, "expected a dump kind");
+                    continue;
+                }
+            };
+
+            match &*name {
+                "mir" => { dump.mir = true; }
+                "json" => { dump.json = true; }
+                "graphviz" => { dump.graphviz = true; }
+                "states" => { dump.states = true; }
+                "source" => { dump.source = true; }
+                "dataflow" => { dump.dataflow = true; }
+                name => {
+                    cx.span_err(item.span, &format!("unknown dump kind `{}`", name));
+                }
+            }
+        }
+
+        dump
+    }
+}
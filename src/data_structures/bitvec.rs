@@ -28,12 +28,12 @@ impl BitVector {
             *p = 0;
         }
     }
+    */
 
     pub fn contains(&self, bit: usize) -> bool {
         let (word, mask) = word_mask(bit);
         (self.data[word] & mask) != 0
     }
-    */
 
     /// Returns true if the bit has changed.
     pub fn insert(&mut self, bit: usize) -> bool {
@@ -67,7 +67,6 @@ impl BitVector {
         }
     }
 
-    /*
     /// Iterates over indexes of set bits in a sorted order
     pub fn iter<'a>(&'a self) -> BitVectorIter<'a> {
         BitVectorIter {
@@ -76,10 +75,8 @@ impl BitVector {
             idx: 0,
         }
     }
-    */
 }
 
-/*
 pub struct BitVectorIter<'a> {
     iter: ::std::slice::Iter<'a, u64>,
     current: u64,
@@ -109,7 +106,6 @@ impl<'a> Iterator for BitVectorIter<'a> {
         return Some(self.idx - 1);
     }
 }
-*/
 
 impl FromIterator<bool> for BitVector {
     fn from_iter<I>(iter: I) -> BitVector where I: IntoIterator<Item=bool> {
@@ -10,17 +10,26 @@
 
 use dot;
 use mir::*;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 use std::io::{self, Write};
+use std::iter;
+use syntax::ast;
+use syntax::print::pprust;
+use syntax::ptr::P;
+use ty::TyCtxt;
 
 use data_structures::indexed_vec::Idx;
 
-/*
-/// Write a graphviz DOT graph of a list of MIRs.
-pub fn write_mir_graphviz<'a, 'b, 'tcx, W>(tcx: TyCtxt<'b, 'tcx>,
-                                           mir: &Mir,
-                                           w: &mut W)
-                                           -> io::Result<()>
+/// Write a graphviz DOT graph of a MIR.
+///
+/// Blocks are clustered by the visibility scope of their terminator. Blocks that can be resumed
+/// into are drawn with a double border, and edges out of a `Suspend` are dashed and labelled with
+/// the suspended value.
+pub fn write_mir_graphviz<'a, 'tcx, W>(tcx: TyCtxt<'a, 'tcx>,
+                                       mir: &Mir,
+                                       w: &mut W)
+                                       -> io::Result<()>
     where W: Write
 {
     writeln!(w, "digraph Mir {{")?;
@@ -33,18 +42,68 @@ pub fn write_mir_graphviz<'a, 'b, 'tcx, W>(tcx: TyCtxt<'b, 'tcx>,
     // Graph label
     write_graph_label(tcx, mir, w)?;
 
-    // Nodes
-    for (block, _) in mir.basic_blocks().iter_enumerated() {
-        write_node(block, mir, w)?;
+    // Nodes, nested in their scopes.
+    let resume_blocks = mir.resume_blocks();
+    let mut scope_blocks = BTreeMap::new();
+    let mut scope_children = BTreeMap::new();
+
+    for (block, data) in mir.basic_blocks().iter_enumerated() {
+        let scope = data.terminator.as_ref()
+            .map_or(ARGUMENT_VISIBILITY_SCOPE, |terminator| terminator.source_info.scope);
+
+        scope_blocks.entry(scope)
+            .or_insert_with(Vec::new)
+            .push(block);
+    }
+
+    for (scope, data) in mir.visibility_scopes.iter_enumerated() {
+        if let Some(parent) = data.parent_scope {
+            scope_children.entry(parent)
+                .or_insert_with(Vec::new)
+                .push(scope);
+        }
     }
 
+    write_scope_cluster(ARGUMENT_VISIBILITY_SCOPE,
+                        mir,
+                        &resume_blocks,
+                        &scope_blocks,
+                        &scope_children,
+                        1,
+                        w)?;
+
     // Edges
     for (source, _) in mir.basic_blocks().iter_enumerated() {
         write_edges(source, mir, w)?;
     }
     writeln!(w, "}}")
 }
-*/
+
+/// Write a visibility scope as a graphviz cluster containing its blocks and child scopes.
+fn write_scope_cluster<W: Write>(scope: VisibilityScope,
+                                 mir: &Mir,
+                                 resume_blocks: &BTreeSet<BasicBlock>,
+                                 scope_blocks: &BTreeMap<VisibilityScope, Vec<BasicBlock>>,
+                                 scope_children: &BTreeMap<VisibilityScope, Vec<VisibilityScope>>,
+                                 depth: usize,
+                                 w: &mut W) -> io::Result<()> {
+    let indent = iter::repeat("    ").take(depth).collect::<String>();
+
+    writeln!(w, "{}subgraph cluster_scope{} {{", indent, scope.index())?;
+    writeln!(w, r#"{}    label="scope {}";"#, indent, scope.index())?;
+    writeln!(w, r#"{}    style="dotted";"#, indent)?;
+
+    for &block in scope_blocks.get(&scope).into_iter().flat_map(|blocks| blocks) {
+        write!(w, "{}    ", indent)?;
+        write_node(block, mir, resume_blocks.contains(&block), w)?;
+    }
+
+    for &child in scope_children.get(&scope).into_iter().flat_map(|children| children) {
+        write_scope_cluster(child, mir, resume_blocks, scope_blocks, scope_children, depth + 1, w)?;
+    }
+
+    writeln!(w, "{}}}", indent)
+}
 
 /// Write a graphviz HTML-styled label for the given basic block, with
 /// all necessary escaping already performed. (This is suitable for
@@ -84,9 +143,13 @@ pub fn write_node_label<W: Write, INIT, FINI>(block: BasicBlock,
     }
 
     // Terminator head at the bottom, not including the list of successor blocks. Those will be
-    // displayed as labels on the edges between blocks.
+    // displayed as labels on the edges between blocks. A pass can leave a block without one, which
+    // is exactly when its dump is needed.
     let mut terminator_head = String::new();
-    data.terminator().kind.fmt_head(&mut terminator_head).unwrap();
+    match data.terminator {
+        Some(ref terminator) => terminator.kind.fmt_head(&mut terminator_head).unwrap(),
+        None => terminator_head.push_str("<no terminator>"),
+    }
     write!(w, r#"<tr><td align="left">{}</td></tr>"#, dot::escape_html(&terminator_head))?;
 
     fini(w)?;
@@ -95,11 +158,20 @@ pub fn write_node_label<W: Write, INIT, FINI>(block: BasicBlock,
     writeln!(w, "</table>")
 }
 
-/*
 /// Write a graphviz DOT node for the given basic block.
-fn write_node<W: Write>(block: BasicBlock, mir: &Mir, w: &mut W) -> io::Result<()> {
+fn write_node<W: Write>(block: BasicBlock,
+                        mir: &Mir,
+                        is_resume: bool,
+                        w: &mut W) -> io::Result<()> {
+    // Resume blocks are the entry points of the state machine, so give them a double border.
+    let shape = if is_resume {
+        r#"shape="box", peripheries="2", margin="0""#
+    } else {
+        r#"shape="none""#
+    };
+
     // Start a new node with the label to follow, in one of DOT's pseudo-HTML tables.
-    write!(w, r#"    {} [shape="none", label=<"#, node(block))?;
+    write!(w, r#"{} [{}, label=<"#, node(block), shape)?;
     write_node_label(block, mir, w, 1, |_| Ok(()), |_| Ok(()))?;
     // Close the node label and the node itself.
     writeln!(w, ">];")
@@ -107,11 +179,22 @@ fn write_node<W: Write>(block: BasicBlock, mir: &Mir, w: &mut W) -> io::Result<(
 
 /// Write graphviz DOT edges with labels between the given basic block and all of its successors.
 fn write_edges<W: Write>(source: BasicBlock, mir: &Mir, w: &mut W) -> io::Result<()> {
-    let terminator = mir[source].terminator();
+    let terminator = match mir[source].terminator {
+        Some(ref terminator) => terminator,
+        None => { return Ok(()); }
+    };
+
+    if let TerminatorKind::Suspend { destination: (_, target), ref arg } = terminator.kind {
+        let label = dot::escape_html(&format!("suspend({:?})", arg));
+        return writeln!(w, r#"    {} -> {} [style="dashed", label="{}"];"#,
+                        node(source), node(target), label);
+    }
+
     let labels = terminator.kind.fmt_successor_labels();
 
     for (&target, label) in terminator.successors().iter().zip(labels) {
-        writeln!(w, r#"    {} -> {} [label="{}"];"#, node(source), node(target), label)?;
+        writeln!(w, r#"    {} -> {} [label="{}"];"#,
+                 node(source), node(target), dot::escape_html(&label))?;
     }
 
     Ok(())
@@ -131,22 +214,22 @@ fn write_graph_label<'a, 'tcx, W: Write>(_tcx: TyCtxt<'a, 'tcx>,
         if i > 0 {
             write!(w, ", ")?;
         }
-        write!(w, "{:?}: {}", Lvalue::Local(arg), escape(&mir.local_decls[arg].ty))?;
+        write!(w, "{:?}: {}", Lvalue::Local(arg), escape_ty(&mir.local_decls[arg].ty))?;
     }
 
-    write!(w, ") -&gt; {}", escape(&mir.fn_decl.return_ty()))?;
+    write!(w, ") -&gt; {}", dot::escape_html(&pprust::ty_to_string(&mir.fn_decl.return_ty())))?;
     write!(w, r#"<br align="left"/>"#)?;
 
-    for local in mir.vars_and_temps_iter() {
+    for local in mir.vars_iter() {
         let decl = &mir.local_decls[local];
 
         write!(w, "let ")?;
-        if decl.mutability == Mutability::Mutable {
+        if decl.mutability == ast::Mutability::Mutable {
             write!(w, "mut ")?;
         }
 
         write!(w, r#"{:?}: {}; // {}<br align="left"/>"#,
-               Lvalue::Local(local), escape(&decl.ty), decl.name)?;
+               Lvalue::Local(local), escape_ty(&decl.ty), decl.name)?;
     }
 
     writeln!(w, ">;")
@@ -155,8 +238,38 @@ fn write_graph_label<'a, 'tcx, W: Write>(_tcx: TyCtxt<'a, 'tcx>,
 fn node(block: BasicBlock) -> String {
     format!("bb{}", block.index())
 }
-*/
+
+fn escape_ty(ty: &Option<P<ast::Ty>>) -> String {
+    match *ty {
+        Some(ref ty) => dot::escape_html(&pprust::ty_to_string(ty)),
+        None => "_".to_string(),
+    }
+}
 
 fn escape<T: Debug>(t: &T) -> String {
     dot::escape_html(&format!("{:?}", t))
 }
+
+/// Escape text for a quoted DOT string.
+pub fn escape_dot(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Escape text for a Mermaid label, which runs until the end of the line or a `;`.
+pub fn escape_mermaid(s: &str) -> String {
+    s.replace('\r', "")
+        .replace('\n', " ")
+        .replace(';', "#59;")
+}
@@ -0,0 +1,528 @@
+//! An interpreter for the stateful MIR, so passes can be checked without compiling the generated
+//! code. It only understands a small subset of Rust: integers, bools, tuples and `Option`, plus
+//! calls to the functions registered with `register_host_function`. Everything else, like method
+//! calls, references and the opaque AST statements, is reported as unsupported.
+//!
+//! Running a state machine produces a `Trace` of every value passed to `Suspend`, along with the
+//! returned value. Every suspend is resumed with `()`. A pass preserves the behavior of a state
+//! machine as long as the trace before and after it is the same, which is what the
+//! `transform::check_trace::CheckTrace` hook checks when a function is annotated with
+//! `interpret(...)`.
+
+use data_structures::indexed_vec::{Idx, IndexVec};
+use mir::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use syntax::ast::{self, BinOpKind, LitKind, PatKind, UnOp};
+use syntax::codemap::Span;
+use syntax::print::pprust;
+
+/// How many statements and terminators to execute before giving up on a state machine that
+/// doesn't terminate.
+const FUEL: usize = 1_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Tuple(Vec<Value>),
+    Option(Option<Box<Value>>),
+}
+
+impl Value {
+    pub fn unit() -> Self {
+        Value::Tuple(vec![])
+    }
+}
+
+/// What the state machine did when run to completion.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace {
+    /// The arguments of every `Suspend`, in order.
+    pub suspends: Vec<Value>,
+
+    /// The value of the return pointer when the state machine returned.
+    pub returned: Value,
+}
+
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// The MIR uses something the interpreter doesn't model.
+    Unsupported(Span, String),
+
+    /// The MIR is ill-typed, like branching on an integer.
+    Type(Span, String),
+
+    /// A local was read before it was written, or after its storage died.
+    Uninitialized(Span, Local),
+
+    /// No arm of a match matched the discriminant.
+    NoMatchingArm(Span, Value),
+
+    /// The state machine ran for too long.
+    OutOfFuel,
+}
+
+impl Error {
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            Error::Unsupported(span, _) |
+            Error::Type(span, _) |
+            Error::Uninitialized(span, _) |
+            Error::NoMatchingArm(span, _) => Some(span),
+            Error::OutOfFuel => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Unsupported(_, ref what) => write!(f, "unsupported {}", what),
+            Error::Type(_, ref message) => write!(f, "type error: {}", message),
+            Error::Uninitialized(_, local) => write!(f, "use of uninitialized {:?}", local),
+            Error::NoMatchingArm(_, ref value) => write!(f, "no arm matched {:?}", value),
+            Error::OutOfFuel => write!(f, "ran out of fuel after {} steps", FUEL),
+        }
+    }
+}
+
+pub type HostFunction = Rc<Fn(&[Value]) -> Option<Value>>;
+
+thread_local! {
+    static HOST_FUNCTIONS: RefCell<HashMap<String, HostFunction>> = RefCell::new(HashMap::new());
+}
+
+/// Make a function callable from interpreted code. `path` is the function's path as written in
+/// the source, like `foo` or `bar::baz`. The function returns `None` if it doesn't accept its
+/// arguments.
+pub fn register_host_function<F>(path: &str, function: F)
+    where F: Fn(&[Value]) -> Option<Value> + 'static,
+{
+    HOST_FUNCTIONS.with(|functions| {
+        functions.borrow_mut().insert(path.to_string(), Rc::new(function));
+    });
+}
+
+/// Run the state machine with these arguments until it returns.
+pub fn run(mir: &Mir, args: Vec<Value>) -> Result<Trace, Error> {
+    if args.len() != mir.arg_count {
+        return Err(Error::Type(
+            mir.span,
+            format!("expected {} arguments, got {}", mir.arg_count, args.len())));
+    }
+
+    let mut locals = IndexVec::from_elem_n(None, mir.local_decls.len());
+    locals[COROUTINE_ARGS] = Some(Value::unit());
+
+    for (index, arg) in args.into_iter().enumerate() {
+        locals[Local::new(index + 2)] = Some(arg);
+    }
+
+    let mut interpreter = Interpreter {
+        mir: mir,
+        locals: locals,
+        suspends: vec![],
+        fuel: FUEL,
+    };
+
+    interpreter.run()
+}
+
+/// Convert a literal into a value.
+pub fn lit_value(lit: &ast::Lit) -> Result<Value, Error> {
+    match lit.node {
+        LitKind::Bool(value) => Ok(Value::Bool(value)),
+        LitKind::Int(value, _) => Ok(Value::Int(value as i64)),
+        _ => {
+            let text = pprust::lit_to_string(lit);
+            Err(Error::Unsupported(lit.span, format!("literal `{}`", text)))
+        }
+    }
+}
+
+struct Interpreter<'a> {
+    mir: &'a Mir,
+    locals: IndexVec<Local, Option<Value>>,
+    suspends: Vec<Value>,
+    fuel: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    fn run(&mut self) -> Result<Trace, Error> {
+        let mir = self.mir;
+        let mut block = START_BLOCK;
+
+        loop {
+            let data = &mir[block];
+
+            for statement in &data.statements {
+                self.step()?;
+                self.statement(statement)?;
+            }
+
+            self.step()?;
+
+            let terminator = data.terminator();
+            let span = terminator.source_info.span;
+
+            block = match terminator.kind {
+                TerminatorKind::Goto { target } |
+                TerminatorKind::Break { target, .. } => target,
+                TerminatorKind::If { ref cond, targets: (then, else_) } => {
+                    match self.operand(span, cond)? {
+                        Value::Bool(true) => then,
+                        Value::Bool(false) => else_,
+                        value => {
+                            return Err(Error::Type(span, format!("branch on {:?}", value)));
+                        }
+                    }
+                }
+                TerminatorKind::Match { ref discr, ref arms } => {
+                    let value = self.operand(span, discr)?;
+                    self.match_arms(span, value, arms)?
+                }
+                TerminatorKind::Return => {
+                    let returned = self.read_local(span, RETURN_POINTER)?;
+
+                    return Ok(Trace {
+                        suspends: self.suspends.clone(),
+                        returned: returned,
+                    });
+                }
+                TerminatorKind::Suspend { destination: (ref lvalue, target), ref arg } => {
+                    let value = self.operand(span, arg)?;
+                    self.suspends.push(value);
+                    self.write(span, lvalue, Value::unit())?;
+                    target
+                }
+            };
+        }
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        if self.fuel == 0 {
+            return Err(Error::OutOfFuel);
+        }
+
+        self.fuel -= 1;
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), Error> {
+        let span = statement.source_info.span;
+
+        match statement.kind {
+            StatementKind::Stmt(ref stmt) => {
+                let stmt = pprust::stmt_to_string(stmt);
+                Err(Error::Unsupported(span, format!("statement `{}`", stmt)))
+            }
+            StatementKind::Let { ref pat, ref lvalues, ref rvalue, .. } => {
+                let value = self.rvalue(span, rvalue)?;
+
+                match bind_pat(span, pat, &value)? {
+                    Some(bindings) => self.bind(span, lvalues, bindings),
+                    None => {
+                        Err(Error::Type(span, format!("`let {}` doesn't match {:?}",
+                                                      pprust::pat_to_string(pat),
+                                                      value)))
+                    }
+                }
+            }
+            StatementKind::Assign(ref lvalue, ref rvalue) => {
+                let value = self.rvalue(span, rvalue)?;
+                self.write(span, lvalue, value)
+            }
+            StatementKind::Call { ref destination, ref func, ref args } => {
+                let path = match *func {
+                    Operand::Consume(Lvalue::Static(ref path), _) |
+                    Operand::Copy(Lvalue::Static(ref path), _) => pprust::expr_to_string(path),
+                    _ => {
+                        return Err(Error::Unsupported(span, format!("call of {:?}", func)));
+                    }
+                };
+
+                let mut values = vec![];
+                for arg in args {
+                    values.push(self.rvalue(span, arg)?);
+                }
+
+                let value = call(span, &path, values)?;
+                self.write(span, destination, value)
+            }
+            StatementKind::MethodCall { ref ident, .. } => {
+                Err(Error::Unsupported(span, format!("method call `{}`", ident.node)))
+            }
+            StatementKind::StorageLive(_) => Ok(()),
+            StatementKind::StorageDead(Lvalue::Local(local)) => {
+                self.locals[local] = None;
+                Ok(())
+            }
+            StatementKind::StorageDead(_) => Ok(()),
+        }
+    }
+
+    fn bind(&mut self, span: Span, lvalues: &[Lvalue], bindings: Vec<Value>) -> Result<(), Error> {
+        if lvalues.len() != bindings.len() {
+            return Err(Error::Unsupported(
+                span,
+                format!("pattern binding {} values into {} lvalues",
+                        bindings.len(),
+                        lvalues.len())));
+        }
+
+        for (lvalue, value) in lvalues.iter().zip(bindings) {
+            self.write(span, lvalue, value)?;
+        }
+
+        Ok(())
+    }
+
+    fn match_arms(&mut self, span: Span, value: Value, arms: &[Arm]) -> Result<BasicBlock, Error> {
+        for arm in arms {
+            for pat in &arm.pats {
+                if let Some(bindings) = bind_pat(span, pat, &value)? {
+                    if arm.guard.is_some() {
+                        return Err(Error::Unsupported(span, "match guard".to_string()));
+                    }
+
+                    self.bind(span, &arm.lvalues, bindings)?;
+                    return Ok(arm.block);
+                }
+            }
+        }
+
+        Err(Error::NoMatchingArm(span, value))
+    }
+
+    fn read_local(&self, span: Span, local: Local) -> Result<Value, Error> {
+        match self.locals[local] {
+            Some(ref value) => Ok(value.clone()),
+            None => Err(Error::Uninitialized(span, local)),
+        }
+    }
+
+    fn read(&self, span: Span, lvalue: &Lvalue) -> Result<Value, Error> {
+        match *lvalue {
+            Lvalue::Local(local) => self.read_local(span, local),
+            Lvalue::Static(ref path) => {
+                let path = pprust::expr_to_string(path);
+
+                if is_option_variant(&path, "None") {
+                    Ok(Value::Option(None))
+                } else {
+                    Err(Error::Unsupported(span, format!("path `{}`", path)))
+                }
+            }
+            Lvalue::Projection(_) => {
+                Err(Error::Unsupported(span, format!("projection `{:?}`", lvalue)))
+            }
+        }
+    }
+
+    fn write(&mut self, span: Span, lvalue: &Lvalue, value: Value) -> Result<(), Error> {
+        match *lvalue {
+            Lvalue::Local(local) => {
+                self.locals[local] = Some(value);
+                Ok(())
+            }
+            _ => Err(Error::Unsupported(span, format!("assignment to `{:?}`", lvalue))),
+        }
+    }
+
+    fn operand(&self, span: Span, operand: &Operand) -> Result<Value, Error> {
+        match *operand {
+            Operand::Consume(ref lvalue, _) |
+            Operand::Copy(ref lvalue, _) => self.read(span, lvalue),
+            Operand::Constant(ref constant) => lit_value(&constant.literal),
+        }
+    }
+
+    fn rvalue(&self, span: Span, rvalue: &Rvalue) -> Result<Value, Error> {
+        match *rvalue {
+            Rvalue::Use(ref operand) => self.operand(span, operand),
+            Rvalue::Tuple(ref operands) => {
+                let mut values = vec![];
+                for operand in operands {
+                    values.push(self.operand(span, operand)?);
+                }
+                Ok(Value::Tuple(values))
+            }
+            Rvalue::BinaryOp(op, ref lhs, ref rhs) => {
+                let lhs = self.operand(span, lhs)?;
+                let rhs = self.operand(span, rhs)?;
+                binary_op(span, op.node, lhs, rhs)
+            }
+            Rvalue::UnaryOp(op, ref operand) => {
+                match (op, self.operand(span, operand)?) {
+                    (UnOp::Not, Value::Bool(value)) => Ok(Value::Bool(!value)),
+                    (UnOp::Not, Value::Int(value)) => Ok(Value::Int(!value)),
+                    (UnOp::Neg, Value::Int(value)) => Ok(Value::Int(value.wrapping_neg())),
+                    (op, value) => {
+                        Err(Error::Type(span, format!("`{}` applied to {:?}",
+                                                      ast::UnOp::to_string(op),
+                                                      value)))
+                    }
+                }
+            }
+            Rvalue::Mac(ref mac) => {
+                Err(Error::Unsupported(span, format!("macro `{}!`", mac.node.path)))
+            }
+            Rvalue::Ref(..) | Rvalue::Struct(..) | Rvalue::Range(..) => {
+                Err(Error::Unsupported(span, format!("rvalue `{:?}`", rvalue)))
+            }
+        }
+    }
+}
+
+fn call(span: Span, path: &str, args: Vec<Value>) -> Result<Value, Error> {
+    if is_option_variant(path, "Some") && args.len() == 1 {
+        return Ok(Value::Option(Some(Box::new(args.into_iter().next().unwrap()))));
+    }
+
+    let function = HOST_FUNCTIONS.with(|functions| functions.borrow().get(path).cloned());
+
+    match function {
+        Some(function) => {
+            match function(&args) {
+                Some(value) => Ok(value),
+                None => Err(Error::Type(span, format!("`{}` doesn't accept {:?}", path, args))),
+            }
+        }
+        None => Err(Error::Unsupported(span, format!("call of `{}`", path))),
+    }
+}
+
+/// Is this a path to a variant of `Option`, like `Some` or `::std::option::Option::Some`?
+fn is_option_variant(path: &str, variant: &str) -> bool {
+    path == variant || path.ends_with(&format!("Option::{}", variant))
+}
+
+fn binary_op(span: Span, op: BinOpKind, lhs: Value, rhs: Value) -> Result<Value, Error> {
+    let value = match (lhs, rhs) {
+        (Value::Int(lhs), Value::Int(rhs)) => {
+            match op {
+                BinOpKind::Add => Value::Int(lhs.wrapping_add(rhs)),
+                BinOpKind::Sub => Value::Int(lhs.wrapping_sub(rhs)),
+                BinOpKind::Mul => Value::Int(lhs.wrapping_mul(rhs)),
+                BinOpKind::Div | BinOpKind::Rem if rhs == 0 => {
+                    return Err(Error::Type(span, "division by zero".to_string()));
+                }
+                BinOpKind::Div => Value::Int(lhs.wrapping_div(rhs)),
+                BinOpKind::Rem => Value::Int(lhs.wrapping_rem(rhs)),
+                BinOpKind::BitAnd => Value::Int(lhs & rhs),
+                BinOpKind::BitOr => Value::Int(lhs | rhs),
+                BinOpKind::BitXor => Value::Int(lhs ^ rhs),
+                BinOpKind::Shl => Value::Int(lhs.wrapping_shl(rhs as u32)),
+                BinOpKind::Shr => Value::Int(lhs.wrapping_shr(rhs as u32)),
+                BinOpKind::Eq => Value::Bool(lhs == rhs),
+                BinOpKind::Ne => Value::Bool(lhs != rhs),
+                BinOpKind::Lt => Value::Bool(lhs < rhs),
+                BinOpKind::Le => Value::Bool(lhs <= rhs),
+                BinOpKind::Gt => Value::Bool(lhs > rhs),
+                BinOpKind::Ge => Value::Bool(lhs >= rhs),
+                BinOpKind::And | BinOpKind::Or => {
+                    return Err(Error::Type(span, format!("`{}` applied to integers",
+                                                         op.to_string())));
+                }
+            }
+        }
+        (Value::Bool(lhs), Value::Bool(rhs)) => {
+            match op {
+                BinOpKind::And | BinOpKind::BitAnd => Value::Bool(lhs && rhs),
+                BinOpKind::Or | BinOpKind::BitOr => Value::Bool(lhs || rhs),
+                BinOpKind::BitXor | BinOpKind::Ne => Value::Bool(lhs != rhs),
+                BinOpKind::Eq => Value::Bool(lhs == rhs),
+                _ => {
+                    return Err(Error::Type(span, format!("`{}` applied to bools", op.to_string())));
+                }
+            }
+        }
+        (lhs, rhs) => {
+            match op {
+                BinOpKind::Eq => Value::Bool(lhs == rhs),
+                BinOpKind::Ne => Value::Bool(lhs != rhs),
+                _ => {
+                    return Err(Error::Type(span, format!("`{}` applied to {:?} and {:?}",
+                                                         op.to_string(),
+                                                         lhs,
+                                                         rhs)));
+                }
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+/// Match a value against a pattern, returning the values of its bindings in the order
+/// `locals_from_pat` lists them, or `None` if the pattern doesn't match.
+fn bind_pat(span: Span, pat: &ast::Pat, value: &Value) -> Result<Option<Vec<Value>>, Error> {
+    let mut bindings = vec![];
+
+    if match_pat(span, pat, value, &mut bindings)? {
+        Ok(Some(bindings))
+    } else {
+        Ok(None)
+    }
+}
+
+fn match_pat(span: Span,
+             pat: &ast::Pat,
+             value: &Value,
+             bindings: &mut Vec<Value>) -> Result<bool, Error> {
+    match pat.node {
+        PatKind::Wild => Ok(true),
+        PatKind::Ident(_, ident, ref subpattern) => {
+            let name = ident.node.name.as_str();
+
+            // Like the builder, only lower case identifiers are bindings.
+            if name.chars().next().map_or(false, char::is_lowercase) {
+                bindings.push(value.clone());
+
+                match *subpattern {
+                    Some(ref subpattern) => match_pat(span, subpattern, value, bindings),
+                    None => Ok(true),
+                }
+            } else if &*name == "None" {
+                Ok(*value == Value::Option(None))
+            } else {
+                Err(Error::Unsupported(span, format!("pattern `{}`", pprust::pat_to_string(pat))))
+            }
+        }
+        PatKind::Path(None, ref path) if is_option_variant(&path.to_string(), "None") => {
+            Ok(*value == Value::Option(None))
+        }
+        PatKind::TupleStruct(ref path, ref pats, None) if pats.len() == 1 &&
+            is_option_variant(&path.to_string(), "Some") => {
+            match *value {
+                Value::Option(Some(ref value)) => match_pat(span, &pats[0], value, bindings),
+                _ => Ok(false),
+            }
+        }
+        PatKind::Tuple(ref pats, None) => {
+            match *value {
+                Value::Tuple(ref values) if values.len() == pats.len() => {
+                    for (pat, value) in pats.iter().zip(values) {
+                        if !match_pat(span, pat, value, bindings)? {
+                            return Ok(false);
+                        }
+                    }
+
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }
+        PatKind::Lit(ref expr) => {
+            match expr.node {
+                ast::ExprKind::Lit(ref lit) => Ok(lit_value(lit)? == *value),
+                _ => Err(Error::Unsupported(span, format!("pattern `{}`",
+                                                         pprust::pat_to_string(pat)))),
+            }
+        }
+        _ => Err(Error::Unsupported(span, format!("pattern `{}`", pprust::pat_to_string(pat)))),
+    }
+}
@@ -0,0 +1,349 @@
+//! A machine-readable JSON serialization of the MIR, meant for tools that diff or visualize
+//! state machines. The layout is:
+//!
+//! ```text
+//! {
+//!   "name": "gen",
+//!   "kind": "generator",
+//!   "span": "src/lib.rs:10:1",
+//!   "locals": [{ "local": "_0", "name": "return_pointer", "kind": "ReturnPointer", ... }],
+//!   "scopes": [{ "scope": 0, "parent": null, "span": "..." }],
+//!   "blocks": [{ "block": "bb0", "name": "Start", "statements": [...], "terminator": {...} }]
+//! }
+//! ```
+//!
+//! Statements, terminators, lvalues, operands and rvalues are objects tagged with a `"kind"`, such
+//! as `{ "kind": "Assign", "lvalue": { "kind": "Local", "local": "_1" }, "rvalue": {...} }`.
+
+use data_structures::indexed_vec::Idx;
+use mir::*;
+use std::io::{self, Write};
+use syntax::ast;
+use syntax::codemap::Span;
+use syntax::print::pprust;
+use ty::TyCtxt;
+
+/// Write out the MIR of a function as a JSON document.
+pub fn write_mir_json(tcx: TyCtxt, mir: &Mir, w: &mut Write) -> io::Result<()> {
+    writeln!(w, "{{")?;
+    writeln!(w, "  \"name\": {},", string(&mir.fn_decl.ident.name.as_str()))?;
+    writeln!(w, "  \"kind\": {},", string(&mir.state_machine_kind.to_string()))?;
+    writeln!(w, "  \"span\": {},", span(tcx, mir.span))?;
+
+    writeln!(w, "  \"locals\": [")?;
+    for (local, decl) in mir.local_decls.iter_enumerated() {
+        let ty = match decl.ty {
+            Some(ref ty) => string(&pprust::ty_to_string(ty)),
+            None => "null".to_string(),
+        };
+
+        write!(w, "    {{ \"local\": {}, \"name\": {}, \"kind\": {}, \"mutable\": {}, \
+                   \"ty\": {}, \"scope\": {}, \"span\": {} }}",
+               string(&format!("{:?}", local)),
+               string(&decl.name.name.as_str()),
+               string(&format!("{:?}", mir.local_kind(local))),
+               decl.mutability == ast::Mutability::Mutable,
+               ty,
+               decl.source_info.scope.index(),
+               span(tcx, decl.source_info.span))?;
+        separator(w, local.index(), mir.local_decls.len())?;
+    }
+    writeln!(w, "  ],")?;
+
+    writeln!(w, "  \"scopes\": [")?;
+    for (scope, data) in mir.visibility_scopes.iter_enumerated() {
+        let parent = match data.parent_scope {
+            Some(parent) => parent.index().to_string(),
+            None => "null".to_string(),
+        };
+
+        write!(w, "    {{ \"scope\": {}, \"parent\": {}, \"span\": {} }}",
+               scope.index(),
+               parent,
+               span(tcx, data.span))?;
+        separator(w, scope.index(), mir.visibility_scopes.len())?;
+    }
+    writeln!(w, "  ],")?;
+
+    writeln!(w, "  \"blocks\": [")?;
+    for (block, data) in mir.basic_blocks().iter_enumerated() {
+        write_block_json(tcx, block, data, w)?;
+        separator(w, block.index(), mir.basic_blocks().len())?;
+    }
+    writeln!(w, "  ]")?;
+
+    writeln!(w, "}}")
+}
+
+fn write_block_json(tcx: TyCtxt,
+                    block: BasicBlock,
+                    data: &BasicBlockData,
+                    w: &mut Write) -> io::Result<()> {
+    let name = match data.name {
+        Some(name) => string(name),
+        None => "null".to_string(),
+    };
+
+    writeln!(w, "    {{")?;
+    writeln!(w, "      \"block\": {},", string(&format!("{:?}", block)))?;
+    writeln!(w, "      \"name\": {},", name)?;
+    writeln!(w, "      \"span\": {},", span(tcx, data.span))?;
+
+    writeln!(w, "      \"statements\": [")?;
+    for (index, statement) in data.statements.iter().enumerate() {
+        write!(w, "        {{ {}, \"scope\": {}, \"span\": {} }}",
+               statement_json(&statement.kind),
+               statement.source_info.scope.index(),
+               span(tcx, statement.source_info.span))?;
+        separator(w, index, data.statements.len())?;
+    }
+    writeln!(w, "      ],")?;
+
+    match data.terminator {
+        Some(ref terminator) => {
+            let successors = terminator.successors().iter()
+                .zip(terminator.kind.fmt_successor_labels())
+                .map(|(target, label)| {
+                    format!("{{ \"label\": {}, \"target\": {} }}",
+                            string(&label),
+                            block_json(*target))
+                });
+
+            writeln!(w, "      \"terminator\": {{ {}, \"successors\": {}, \"scope\": {}, \
+                             \"span\": {} }}",
+                     terminator_json(&terminator.kind),
+                     array(successors),
+                     terminator.source_info.scope.index(),
+                     span(tcx, terminator.source_info.span))?;
+        }
+        None => {
+            writeln!(w, "      \"terminator\": null")?;
+        }
+    }
+
+    write!(w, "    }}")
+}
+
+/// The fields of a statement, starting with its `"kind"`. Pieces of Rust syntax that the MIR
+/// keeps as they are, like patterns and types, are written out as source strings.
+fn statement_json(kind: &StatementKind) -> String {
+    match *kind {
+        StatementKind::Stmt(ref stmt) => {
+            format!("\"kind\": \"Stmt\", \"source\": {}", string(&pprust::stmt_to_string(stmt)))
+        }
+        StatementKind::Let { ref pat, ref ty, ref lvalues, ref rvalue } => {
+            format!("\"kind\": \"Let\", \"pat\": {}, \"ty\": {}, \"lvalues\": {}, \"rvalue\": {}",
+                    string(&pprust::pat_to_string(pat)),
+                    optional(ty.as_ref().map(|ty| string(&pprust::ty_to_string(ty)))),
+                    array(lvalues.iter().map(lvalue_json)),
+                    rvalue_json(rvalue))
+        }
+        StatementKind::Assign(ref lv, ref rv) => {
+            format!("\"kind\": \"Assign\", \"lvalue\": {}, \"rvalue\": {}",
+                    lvalue_json(lv),
+                    rvalue_json(rv))
+        }
+        StatementKind::Call { ref destination, ref func, ref args } => {
+            format!("\"kind\": \"Call\", \"destination\": {}, \"func\": {}, \"args\": {}",
+                    lvalue_json(destination),
+                    operand_json(func),
+                    array(args.iter().map(rvalue_json)))
+        }
+        StatementKind::MethodCall { ref destination, ref ident, ref tys, ref self_, ref args } => {
+            format!("\"kind\": \"MethodCall\", \"destination\": {}, \"method\": {}, \
+                     \"tys\": {}, \"self\": {}, \"args\": {}",
+                    lvalue_json(destination),
+                    string(&ident.node.name.as_str()),
+                    array(tys.iter().map(|ty| string(&pprust::ty_to_string(ty)))),
+                    operand_json(self_),
+                    array(args.iter().map(rvalue_json)))
+        }
+        StatementKind::StorageLive(ref lv) => {
+            format!("\"kind\": \"StorageLive\", \"lvalue\": {}", lvalue_json(lv))
+        }
+        StatementKind::StorageDead(ref lv) => {
+            format!("\"kind\": \"StorageDead\", \"lvalue\": {}", lvalue_json(lv))
+        }
+    }
+}
+
+/// The fields of a terminator, starting with its `"kind"`.
+fn terminator_json(kind: &TerminatorKind) -> String {
+    match *kind {
+        TerminatorKind::Goto { target } => {
+            format!("\"kind\": \"Goto\", \"target\": {}", block_json(target))
+        }
+        TerminatorKind::Break { target, after_target } => {
+            format!("\"kind\": \"Break\", \"target\": {}, \"after_target\": {}",
+                    block_json(target),
+                    block_json(after_target))
+        }
+        TerminatorKind::If { ref cond, targets: (then_target, else_target) } => {
+            format!("\"kind\": \"If\", \"cond\": {}, \"then\": {}, \"else\": {}",
+                    operand_json(cond),
+                    block_json(then_target),
+                    block_json(else_target))
+        }
+        TerminatorKind::Match { ref discr, ref arms } => {
+            let arms = arms.iter().map(|arm| {
+                format!("{{ \"pats\": {}, \"guard\": {}, \"lvalues\": {}, \"target\": {} }}",
+                        array(arm.pats.iter().map(|pat| string(&pprust::pat_to_string(pat)))),
+                        optional(arm.guard.as_ref().map(|guard| {
+                            string(&pprust::expr_to_string(guard))
+                        })),
+                        array(arm.lvalues.iter().map(lvalue_json)),
+                        block_json(arm.block))
+            });
+
+            format!("\"kind\": \"Match\", \"discr\": {}, \"arms\": {}",
+                    operand_json(discr),
+                    array(arms))
+        }
+        TerminatorKind::Return => "\"kind\": \"Return\"".to_string(),
+        TerminatorKind::Suspend { destination: (ref destination, target), ref arg } => {
+            format!("\"kind\": \"Suspend\", \"destination\": {}, \"target\": {}, \"arg\": {}",
+                    lvalue_json(destination),
+                    block_json(target),
+                    operand_json(arg))
+        }
+    }
+}
+
+fn lvalue_json(lvalue: &Lvalue) -> String {
+    match *lvalue {
+        Lvalue::Local(local) => {
+            format!("{{ \"kind\": \"Local\", \"local\": {} }}", string(&format!("{:?}", local)))
+        }
+        Lvalue::Static(ref expr) => {
+            format!("{{ \"kind\": \"Static\", \"source\": {} }}",
+                    string(&pprust::expr_to_string(expr)))
+        }
+        Lvalue::Projection(ref projection) => {
+            match projection.elem {
+                ProjectionElem::Deref => {
+                    format!("{{ \"kind\": \"Deref\", \"base\": {} }}",
+                            lvalue_json(&projection.base))
+                }
+            }
+        }
+    }
+}
+
+fn operand_json(operand: &Operand) -> String {
+    match *operand {
+        Operand::Consume(ref lv, _) => {
+            format!("{{ \"kind\": \"Consume\", \"lvalue\": {} }}", lvalue_json(lv))
+        }
+        Operand::Copy(ref lv, _) => {
+            format!("{{ \"kind\": \"Copy\", \"lvalue\": {} }}", lvalue_json(lv))
+        }
+        Operand::Constant(ref constant) => {
+            format!("{{ \"kind\": \"Constant\", \"literal\": {} }}",
+                    string(&pprust::lit_to_string(&constant.literal)))
+        }
+    }
+}
+
+fn rvalue_json(rvalue: &Rvalue) -> String {
+    match *rvalue {
+        Rvalue::Use(ref op) => {
+            format!("{{ \"kind\": \"Use\", \"operand\": {} }}", operand_json(op))
+        }
+        Rvalue::Mac(ref mac) => {
+            format!("{{ \"kind\": \"Mac\", \"source\": {} }}", string(&pprust::mac_to_string(mac)))
+        }
+        Rvalue::Ref(mutability, ref lv) => {
+            format!("{{ \"kind\": \"Ref\", \"mutable\": {}, \"lvalue\": {} }}",
+                    mutability == ast::Mutability::Mutable,
+                    lvalue_json(lv))
+        }
+        Rvalue::BinaryOp(op, ref lhs, ref rhs) => {
+            format!("{{ \"kind\": \"BinaryOp\", \"op\": {}, \"lhs\": {}, \"rhs\": {} }}",
+                    string(op.node.to_string()),
+                    operand_json(lhs),
+                    operand_json(rhs))
+        }
+        Rvalue::UnaryOp(op, ref value) => {
+            format!("{{ \"kind\": \"UnaryOp\", \"op\": {}, \"operand\": {} }}",
+                    string(ast::UnOp::to_string(op)),
+                    operand_json(value))
+        }
+        Rvalue::Tuple(ref operands) => {
+            format!("{{ \"kind\": \"Tuple\", \"operands\": {} }}",
+                    array(operands.iter().map(operand_json)))
+        }
+        Rvalue::Struct(ref path, ref fields, ref operands, ref base) => {
+            let fields = fields.iter().zip(operands).map(|(field, op)| {
+                format!("{{ \"field\": {}, \"operand\": {} }}",
+                        string(&field.ident.node.name.as_str()),
+                        operand_json(op))
+            });
+
+            format!("{{ \"kind\": \"Struct\", \"path\": {}, \"fields\": {}, \"base\": {} }}",
+                    string(&pprust::path_to_string(path)),
+                    array(fields),
+                    optional(base.as_ref().map(operand_json)))
+        }
+        Rvalue::Range(ref start, ref end, limits) => {
+            let limits = match limits {
+                ast::RangeLimits::HalfOpen => "HalfOpen",
+                ast::RangeLimits::Closed => "Closed",
+            };
+
+            format!("{{ \"kind\": \"Range\", \"start\": {}, \"end\": {}, \"limits\": {} }}",
+                    optional(start.as_ref().map(operand_json)),
+                    optional(end.as_ref().map(operand_json)),
+                    string(limits))
+        }
+    }
+}
+
+fn block_json(block: BasicBlock) -> String {
+    string(&format!("{:?}", block))
+}
+
+/// Render a list of JSON values as an array.
+fn array<I: Iterator<Item=String>>(values: I) -> String {
+    format!("[{}]", values.collect::<Vec<_>>().join(", "))
+}
+
+/// Render an optional JSON value, as `null` if it is missing.
+fn optional(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "null".to_string())
+}
+
+/// Close a JSON array element, leaving off the comma after the last one.
+fn separator(w: &mut Write, index: usize, len: usize) -> io::Result<()> {
+    if index + 1 == len {
+        writeln!(w, "")
+    } else {
+        writeln!(w, ",")
+    }
+}
+
+/// Render a span as a `"file:line:col"` string.
+fn span(tcx: TyCtxt, span: Span) -> String {
+    let loc = tcx.sess.codemap().lookup_char_pos(span.lo);
+    string(&format!("{}:{}:{}", loc.file.name, loc.line, loc.col.0 + 1))
+}
+
+/// Quote and escape a JSON string.
+fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
@@ -15,17 +15,19 @@ extern crate syntax_pos;
 
 mod analysis;
 mod build;
-mod data_structures;
-mod graphviz;
-mod mir;
-mod pretty;
-mod transform;
+pub mod config;
+pub mod data_structures;
+pub mod graphviz;
+pub mod interpret;
+pub mod json;
+pub mod mir;
+pub mod parse_mir;
+pub mod pretty;
+pub mod transform;
 mod translate;
-mod ty;
-//mod traversal;
+pub mod ty;
+mod traversal;
 
-use std::env;
-use std::fs;
 use std::io::Write;
 use syntax::ast;
 use syntax::codemap::Span;
@@ -33,7 +35,10 @@ use syntax::ext::base::{Annotatable, ExtCtxt, MultiModifier};
 use syntax::fold;
 use syntax::print::pprust;
 use syntax::ptr::P;
-use mir::{FunctionDecl, StateMachineKind};
+use config::{Config, OptLevel};
+use mir::{FunctionDecl, Mir, StateMachineKind};
+
+pub use transform::register_mir_pass;
 
 fn expand_state_machine<'a, 'ecx>(cx: &'a ExtCtxt<'ecx>,
                                   _sp: Span,
@@ -78,30 +83,160 @@ fn expand_state_machine<'a, 'ecx>(cx: &'a ExtCtxt<'ecx>,
         }
     };
 
+    let config = Config::from_meta_item(cx, meta_item);
+
+    if !check_config(cx, meta_item, name, state_machine_kind, &config, &fn_decl) {
+        return reject_fn(cx, item);
+    }
+
+    let (_, item) = expand_fn(cx, state_machine_kind, config, item.span, fn_decl, ast_block);
+
+    Annotatable::Item(item)
+}
+
+/// Check the options of a state machine attribute like `#[generator(...)]` against the kind of
+/// state machine and its signature, reporting every combination that isn't supported. Returns
+/// false if the function shouldn't be expanded.
+pub fn check_config(cx: &ExtCtxt,
+                    meta_item: &ast::MetaItem,
+                    name: &str,
+                    state_machine_kind: StateMachineKind,
+                    config: &Config,
+                    fn_decl: &FunctionDecl) -> bool {
+    // Push parsers copy every chunk of input they are fed into a `Vec`, and both push parsers and
+    // actors implement traits from `stateful_runtime`, which is linked against `std`.
+    let needs_std = match state_machine_kind {
+        StateMachineKind::PushParser | StateMachineKind::Actor => true,
+        _ => false,
+    };
+
+    if config.no_std && needs_std {
+        cx.span_err(meta_item.span, &format!("`{}` functions cannot be `no_std`", name));
+
+        return false;
+    }
+
+    if config.no_std && (config.read || config.write) {
+        cx.span_err(
+            meta_item.span,
+            "the `read` and `write` options need `std::io`, so they cannot be `no_std`");
+
+        return false;
+    }
+
+    // The `std::io` adapters only make sense for the kinds that produce or consume bytes.
+    let unsupported_option = if config.read && state_machine_kind != StateMachineKind::Generator {
+        Some("read")
+    } else if config.write && state_machine_kind != StateMachineKind::PushParser {
+        Some("write")
+    } else {
+        None
+    };
+
+    if let Some(option) = unsupported_option {
+        cx.span_err(
+            meta_item.span,
+            &format!("`{}` functions do not support the `{}` option", name, option));
+
+        return false;
+    }
+
+    // Without an allocator, the only way to name the state machine is through `impl Trait`.
+    if config.no_std {
+        let return_ty = fn_decl.return_ty();
+
+        match return_ty.node {
+            ast::TyKind::ImplTrait(_) => {}
+            _ => {
+                cx.span_err(
+                    return_ty.span,
+                    &format!("`{}(no_std)` functions must return an `impl Trait` type", name));
+
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// Replace the body of a function whose options were rejected with `unimplemented!()`, so that the
+/// state machine macros in it aren't reported again as undefined macros.
+fn reject_fn(cx: &ExtCtxt, item: P<ast::Item>) -> Annotatable {
+    let item = item.map(|mut item| {
+        if let ast::ItemKind::Fn(_, _, _, _, _, ref mut block) = item.node {
+            *block = quote_block!(cx, { unimplemented!() });
+        }
+
+        item
+    });
+
+    Annotatable::Item(item)
+}
+
+/// Lower a state machine function into MIR, run the passes over it, and translate it back into
+/// Rust. Returns the final MIR along with the translated function.
+pub fn expand_fn(cx: &ExtCtxt,
+                 state_machine_kind: StateMachineKind,
+                 config: Config,
+                 span: Span,
+                 fn_decl: FunctionDecl,
+                 ast_block: P<ast::Block>) -> (Mir, P<ast::Item>) {
     let tcx = ty::TyCtxt::new(cx);
 
     let mut mir = build::construct_fn(
         cx,
         state_machine_kind,
-        item.span,
+        config,
+        span,
         fn_decl,
         ast_block);
 
+    let mut passes = pipeline(&mir.config);
+    passes.run_passes(tcx, &mut mir);
+
+    let item = translate::translate(tcx, &mir);
+
+    if mir.config.dump.source {
+        let path = mir.config.dump_path(mir.fn_decl.ident, "rs");
+        mir.config.write_dump(cx, span, &path, |file| {
+            file.write_all(pprust::item_to_string(&item).as_bytes())
+        });
+    }
+
+    (mir, strip_node_ids(item))
+}
+
+/// The passes to run over the MIR of a state machine, as picked by its `opt` level. Passes can be
+/// individually turned off with `disable_passes(...)`.
+fn pipeline(config: &Config) -> transform::Passes {
     let mut passes = transform::Passes::new();
     passes.push_hook(Box::new(transform::dump_mir::DumpMir));
+    passes.push_hook(Box::new(transform::dump_graphviz::DumpGraphviz));
     passes.push_hook(Box::new(transform::validate::Validate));
+
+    if config.interpret.is_some() {
+        passes.push_hook(Box::new(transform::check_trace::CheckTrace::new()));
+    }
+
     passes.push_pass(Box::new(transform::NopPass::new("initial")));
-    //passes.push_pass(Box::new(transform::simplify::SimplifyCfg::new("initial")));
-    passes.run_passes(tcx, &mut mir);
 
-    let item = translate::translate(tcx, &mir);
+    if config.opt_level >= OptLevel::Basic {
+        passes.push_pass(Box::new(transform::simplify::SimplifyCfg::new("initial")));
+    }
+
+    if config.opt_level >= OptLevel::Full {
+        passes.push_pass(Box::new(transform::const_prop::ConstProp));
+        passes.push_pass(Box::new(transform::simplify::SimplifyCfg::new("after-const-prop")));
+    }
+
+    passes.push_registered_passes();
 
-    if let Some(path) = env::var("STATEFUL_DUMP_SOURCE").ok() {
-        let mut file = fs::File::create(path).unwrap();
-        file.write_all(pprust::item_to_string(&item).as_bytes()).unwrap();
+    if config.opt_level >= OptLevel::Basic {
+        passes.push_pass(Box::new(transform::simplify::SimplifyLocals));
     }
 
-    Annotatable::Item(strip_node_ids(item))
+    passes
 }
 
 /// Syntax extensions are not allowed to have `ast::NodeId`s, so this just strips them out.
@@ -149,6 +284,45 @@ fn expand_async(cx: &mut ExtCtxt,
         StateMachineKind::Async)
 }
 
+fn expand_state_machine_attr(cx: &mut ExtCtxt,
+                             span: Span,
+                             meta_item: &ast::MetaItem,
+                             annotatable: Annotatable) -> Annotatable {
+    expand_state_machine(
+        cx,
+        span,
+        meta_item,
+        annotatable,
+        "state_machine",
+        StateMachineKind::StateMachine)
+}
+
+fn expand_push_parser(cx: &mut ExtCtxt,
+                      span: Span,
+                      meta_item: &ast::MetaItem,
+                      annotatable: Annotatable) -> Annotatable {
+    expand_state_machine(
+        cx,
+        span,
+        meta_item,
+        annotatable,
+        "push_parser",
+        StateMachineKind::PushParser)
+}
+
+fn expand_actor(cx: &mut ExtCtxt,
+                span: Span,
+                meta_item: &ast::MetaItem,
+                annotatable: Annotatable) -> Annotatable {
+    expand_state_machine(
+        cx,
+        span,
+        meta_item,
+        annotatable,
+        "actor",
+        StateMachineKind::Actor)
+}
+
 #[plugin_registrar]
 #[doc(hidden)]
 pub fn plugin_registrar(registry: &mut rustc_plugin::Registry) {
@@ -159,4 +333,13 @@ pub fn plugin_registrar(registry: &mut rustc_plugin::Registry) {
 
     registry.register_syntax_extension(builder.symbol("async"),
                                        MultiModifier(Box::new(expand_async)));
+
+    registry.register_syntax_extension(builder.symbol("state_machine"),
+                                       MultiModifier(Box::new(expand_state_machine_attr)));
+
+    registry.register_syntax_extension(builder.symbol("push_parser"),
+                                       MultiModifier(Box::new(expand_push_parser)));
+
+    registry.register_syntax_extension(builder.symbol("actor"),
+                                       MultiModifier(Box::new(expand_actor)));
 }
@@ -1,7 +1,9 @@
 use aster::AstBuilder;
 use aster::ident::ToIdent;
+use config::Config;
 use data_structures::indexed_vec::{Idx, IndexVec};
 use std::borrow::Cow;
+use std::collections::BTreeSet;
 use std::fmt::{self, Debug, Formatter, Write};
 use std::ops::{Index, IndexMut};
 use std::u32;
@@ -11,7 +13,7 @@ use syntax::codemap::Span;
 use syntax::print::pprust;
 use syntax::ptr::P;
 
-//pub mod visit;
+pub mod visit;
 
 macro_rules! newtype_index {
     ($name:ident, $debug_name:expr) => (
@@ -83,10 +85,13 @@ impl FunctionDecl {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum StateMachineKind {
     Generator,
     Async,
+    StateMachine,
+    PushParser,
+    Actor,
 }
 
 impl fmt::Display for StateMachineKind {
@@ -94,6 +99,9 @@ impl fmt::Display for StateMachineKind {
         match *self {
             StateMachineKind::Generator => write!(f, "generator"),
             StateMachineKind::Async => write!(f, "async"),
+            StateMachineKind::StateMachine => write!(f, "state_machine"),
+            StateMachineKind::PushParser => write!(f, "push_parser"),
+            StateMachineKind::Actor => write!(f, "actor"),
         }
     }
 }
@@ -103,6 +111,9 @@ impl fmt::Display for StateMachineKind {
 pub struct Mir {
     pub state_machine_kind: StateMachineKind,
 
+    /// Options passed to the state machine attribute.
+    pub config: Config,
+
     /// List of basic blocks. References to basic block use a newtyped index type `BasicBlock`
     /// that indexes into this vector.
     basic_blocks: IndexVec<BasicBlock, BasicBlockData>,
@@ -140,6 +151,7 @@ pub const START_BLOCK: BasicBlock = BasicBlock(0);
 
 impl Mir {
     pub fn new(state_machine_kind: StateMachineKind,
+               config: Config,
                basic_blocks: IndexVec<BasicBlock, BasicBlockData>,
                visibility_scopes: IndexVec<VisibilityScope, VisibilityScopeData>,
                local_decls: IndexVec<Local, LocalDecl>,
@@ -155,6 +167,7 @@ impl Mir {
 
         Mir {
             state_machine_kind: state_machine_kind,
+            config: config,
             basic_blocks: basic_blocks,
             visibility_scopes: visibility_scopes,
             return_ty: fn_decl.return_ty(),
@@ -179,7 +192,6 @@ impl Mir {
         &self.local_decls[local]
     }
 
-    /*
     #[inline]
     pub fn local_kind(&self, local: Local) -> LocalKind {
         let index = local.0 as usize;
@@ -193,24 +205,17 @@ impl Mir {
                           "coroutine arguments should be immutable");
 
             LocalKind::CoroutineArgs
-
         } else if index < self.arg_count + 2 {
             LocalKind::Arg
-        /*
-        } else if self.local_decls[local].name.is_some() {
+        } else if self.local_decls[local].is_user_variable {
             LocalKind::Var
         } else {
-            debug_assert!(self.local_decls[local].mutability == Mutability::Mut,
+            debug_assert!(self.local_decls[local].mutability == ast::Mutability::Mutable,
                           "temp should be mutable");
 
             LocalKind::Temp
         }
-        */
-        } else {
-            LocalKind::Var
-        }
     }
-    */
 
     /// Returns an iterator over all user-declared locals.
     #[inline]
@@ -225,6 +230,78 @@ impl Mir {
         Box::new((1 .. self.arg_count + 2).map(Local::new))
     }
 
+    /// Find all the blocks that could be resumed into.
+    ///
+    /// In order properly lift any resume arguments into the state machine, we need to first
+    /// identify all the blocks that can be resumed into. A suspend in dead code is never run, so
+    /// its target isn't one of them.
+    pub fn resume_blocks(&self) -> BTreeSet<BasicBlock> {
+        let live_blocks = self.live_blocks();
+
+        self.basic_blocks().iter_enumerated()
+            .filter(|&(block, _)| live_blocks.contains(&block))
+            .filter_map(|(_, block_data)| {
+                if let Some(ref terminator) = block_data.terminator {
+                    match terminator.kind {
+                        TerminatorKind::Suspend { destination: (_, block), .. } => {
+                            Some(block)
+                        }
+                        _ => None
+                    }
+                } else {
+                    None
+                }
+            })
+            .chain(Some(START_BLOCK))
+            .collect()
+    }
+
+    /// Find all the blocks that can run, by following every jump and resume from the start block.
+    /// The `after_target` of a `Break` is never jumped to, so the code after a `break`, or in a
+    /// branch that `ConstProp` folded away, is dead. It is still translated so that it gets type
+    /// checked, but never becomes a state.
+    pub fn live_blocks(&self) -> BTreeSet<BasicBlock> {
+        let mut live_blocks = BTreeSet::new();
+        let mut worklist = vec![START_BLOCK];
+
+        while let Some(block) = worklist.pop() {
+            if !live_blocks.insert(block) {
+                continue;
+            }
+
+            match self[block].terminator().kind {
+                TerminatorKind::Break { target, .. } => { worklist.push(target); }
+                ref kind => { worklist.extend(kind.successors()); }
+            }
+        }
+
+        live_blocks
+    }
+
+    /// Find all the blocks that enter a labelled state of a `#[state_machine]`.
+    pub fn labelled_blocks(&self) -> BTreeSet<BasicBlock> {
+        self.basic_blocks().iter_enumerated()
+            .filter(|&(_, block_data)| block_data.label.is_some())
+            .map(|(block, _)| block)
+            .collect()
+    }
+
+    /// The name of the state variant for a block, like `State3Resume`. The labelled states of a
+    /// `#[state_machine]` are named after their label instead, so `'read_header` becomes
+    /// `ReadHeader`.
+    pub fn state_name(&self, block: BasicBlock) -> String {
+        let data = &self[block];
+
+        if let Some(label) = data.label {
+            return camel_case(&label.name.as_str());
+        }
+
+        match data.name {
+            Some(name) => format!("State{}{}", block.index(), name),
+            None => format!("State{}", block.index()),
+        }
+    }
+
     /*
     /// Returns an iterator over all user-defined variables and compiler-generated temporaries (all
     /// locals that are neither arguments nor the return pointer).
@@ -236,6 +313,19 @@ impl Mir {
     */
 }
 
+/// Turn a label like `'read_header` into a variant name like `ReadHeader`.
+pub fn camel_case(label: &str) -> String {
+    label.trim_left_matches('\'')
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().into_iter().flat_map(|c| c.to_uppercase());
+            first.chain(chars).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
 impl Index<BasicBlock> for Mir {
     type Output = BasicBlockData;
 
@@ -270,16 +360,13 @@ newtype_index!(Local, "_");
 pub const RETURN_POINTER: Local = Local(0);
 pub const COROUTINE_ARGS: Local = Local(1);
 
-/*
 /// Classifies locals into categories. See `Mir::local_kind`.
 #[derive(PartialEq, Eq, Debug)]
 pub enum LocalKind {
     /// User-declared variable binding
     Var,
-    /*
     /// Compiler-introduced temporary
     Temp,
-    */
     /// Function argument
     Arg,
     /// Location of function's return value
@@ -287,7 +374,6 @@ pub enum LocalKind {
     /// Coroutine arguments
     CoroutineArgs,
 }
-*/
 
 #[derive(Debug, PartialEq)]
 pub struct LocalDecl {
@@ -312,6 +398,9 @@ pub struct LocalDecl {
     /// This is the primary way to differentiate between user-declared
     /// variables and compiler-generated temporaries.
     pub source_info: SourceInfo,
+
+    /// True if this was declared by a binding in the source, as opposed to a temporary.
+    pub is_user_variable: bool,
 }
 
 impl LocalDecl {
@@ -326,6 +415,7 @@ impl LocalDecl {
             ty: return_ty,
             source_info: source_info,
             name: "return_pointer".to_ident(),
+            is_user_variable: false,
         }
     }
 
@@ -340,6 +430,7 @@ impl LocalDecl {
             ty: return_ty,
             source_info: source_info,
             name: "coroutine_args".to_ident(),
+            is_user_variable: false,
         }
     }
 }
@@ -353,6 +444,11 @@ newtype_index!(BasicBlock, "bb");
 pub struct BasicBlockData {
     pub span: Span,
     pub name: Option<&'static str>,
+
+    /// The label of the `'state: loop { ... }` this block enters in a `#[state_machine]`. These
+    /// blocks always become states, and their variants are named after the label.
+    pub label: Option<ast::Ident>,
+
     pub statements: Vec<Statement>,
     pub terminator: Option<Terminator>,
 }
@@ -363,6 +459,7 @@ impl BasicBlockData {
         BasicBlockData {
             span: span,
             name: name,
+            label: None,
             statements: vec![],
             terminator: None,
         }
@@ -376,11 +473,9 @@ impl BasicBlockData {
         self.terminator.as_ref().expect("invalid terminator state")
     }
 
-    /*
     pub fn terminator_mut(&mut self) -> &mut Terminator {
         self.terminator.as_mut().expect("invalid terminator state")
     }
-    */
 }
 
 pub struct Terminator {
@@ -437,11 +532,9 @@ impl Terminator {
         self.kind.successors()
     }
 
-    /*
     pub fn successors_mut(&mut self) -> Vec<&mut BasicBlock> {
         self.kind.successors_mut()
     }
-    */
 }
 
 impl Debug for TerminatorKind {
@@ -454,7 +547,7 @@ impl Debug for TerminatorKind {
         match successors.len() {
             0 => Ok(()),
 
-            1 => write!(fmt, " -> {:?}", successors[0]),
+            1 if labels[0].is_empty() => write!(fmt, " -> {:?}", successors[0]),
 
             _ => {
                 write!(fmt, " -> [")?;
@@ -491,7 +584,6 @@ impl TerminatorKind {
         }
     }
 
-    /*
     pub fn successors_mut(&mut self) -> Vec<&mut BasicBlock> {
         match *self {
             TerminatorKind::Goto { ref mut target } => {
@@ -513,7 +605,6 @@ impl TerminatorKind {
             TerminatorKind::Return => vec![],
         }
     }
-    */
 
     /// Write the "head" part of the terminator; that is, its name and the data it uses to pick the
     /// successor basic block, if any. The only information not inlcuded is the list of possible
@@ -553,7 +644,15 @@ impl TerminatorKind {
                             .collect::<Vec<_>>()
                             .join(",");
 
-                        Cow::from(format!("{}: [{}]", pats, lvalues))
+                        match arm.guard {
+                            Some(ref guard) => {
+                                Cow::from(format!("{} if ({}): [{}]",
+                                                  pats,
+                                                  pprust::expr_to_string(guard),
+                                                  lvalues))
+                            }
+                            None => Cow::from(format!("{}: [{}]", pats, lvalues)),
+                        }
                     })
                     .collect()
             }
@@ -659,38 +758,42 @@ impl Lvalue {
     }
 }
 
+/// Raise a piece of MIR back into an expression. `span` is the span of the code that uses it,
+/// so that errors in the translated expression point at the code the user wrote, rather than at
+/// where a local was declared. Operands and constants keep their own span, so that an error in
+/// one of them points at that sub-expression rather than at the whole statement.
 pub trait ToExpr {
-    fn to_expr(&self, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr>;
+    fn to_expr(&self, span: Span, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr>;
 }
 
 impl ToExpr for Lvalue {
-    fn to_expr(&self, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
+    fn to_expr(&self, span: Span, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
         match *self {
             Lvalue::Local(ref local) => {
                 let local_decl = &local_decls[*local];
-                AstBuilder::new().span(local_decl.source_info.span).expr().id(local_decl.name)
+                AstBuilder::new().span(span).expr().id(local_decl.name)
             }
             Lvalue::Static(ref expr) => {
                 expr.clone()
             }
             Lvalue::Projection(ref projection) => {
-                projection.to_expr(local_decls)
+                projection.to_expr(span, local_decls)
             }
         }
     }
 }
 
 impl<B> ToExpr for Projection<B> where B: ToExpr {
-    fn to_expr(&self, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
-        let base = self.base.to_expr(local_decls);
+    fn to_expr(&self, span: Span, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
+        let base = self.base.to_expr(span, local_decls);
 
         match self.elem {
             ProjectionElem::Deref => {
-                AstBuilder::new().span(base.span).expr().deref().build(base)
+                AstBuilder::new().span(span).expr().deref().build(base)
             }
             /*
             ProjectionElem::Index(ref idx) => {
-                idx.to_expr(local_decls)
+                idx.to_expr(span, local_decls)
             }
             */
         }
@@ -740,17 +843,18 @@ pub struct VisibilityScopeData {
 /// being nested in one another.
 #[derive(Clone, PartialEq)]
 pub enum Operand {
-    Consume(Lvalue),
-    Copy(Lvalue),
+    /// A use of an lvalue, with the span of the expression it was built from.
+    Consume(Lvalue, Span),
+    Copy(Lvalue, Span),
     Constant(Constant),
 }
 
 impl ToExpr for Operand {
-    fn to_expr(&self, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
+    fn to_expr(&self, span: Span, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
         match *self {
-            Operand::Consume(ref lvalue) => lvalue.to_expr(local_decls),
-            Operand::Copy(ref lvalue) => lvalue.to_expr(local_decls),
-            Operand::Constant(ref constant) => constant.to_expr(local_decls),
+            Operand::Consume(ref lvalue, span) |
+            Operand::Copy(ref lvalue, span) => lvalue.to_expr(span, local_decls),
+            Operand::Constant(ref constant) => constant.to_expr(span, local_decls),
         }
     }
 }
@@ -760,8 +864,8 @@ impl Debug for Operand {
         use self::Operand::*;
         match *self {
             Constant(ref a) => write!(fmt, "const {:?}", a),
-            Copy(ref a) => write!(fmt, "copy {:?}", a),
-            Consume(ref lv) => write!(fmt, "consume {:?}", lv),
+            Copy(ref a, _) => write!(fmt, "copy {:?}", a),
+            Consume(ref lv, _) => write!(fmt, "consume {:?}", lv),
         }
     }
 }
@@ -785,43 +889,43 @@ pub enum Rvalue {
 }
 
 impl ToExpr for Rvalue {
-    fn to_expr(&self, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
-        let builder = AstBuilder::new();
+    fn to_expr(&self, span: Span, local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
+        let builder = AstBuilder::new().span(span);
 
         match *self {
             Rvalue::Use(ref lvalue) => {
-                lvalue.to_expr(local_decls)
+                lvalue.to_expr(span, local_decls)
             }
             Rvalue::Mac(ref mac) => {
                 builder.expr().build_mac(mac.clone())
             }
             Rvalue::Ref(ast::Mutability::Immutable, ref arg) => {
-                builder.expr().ref_().build(arg.to_expr(local_decls))
+                builder.expr().ref_().build(arg.to_expr(span, local_decls))
             }
             Rvalue::Ref(ast::Mutability::Mutable, ref arg) => {
-                builder.expr().mut_ref().build(arg.to_expr(local_decls))
+                builder.expr().mut_ref().build(arg.to_expr(span, local_decls))
             }
             Rvalue::BinaryOp(op, ref lhs, ref rhs) => {
                 builder.expr().build_binary(
                     op.node,
-                    lhs.to_expr(local_decls),
-                    rhs.to_expr(local_decls))
+                    lhs.to_expr(span, local_decls),
+                    rhs.to_expr(span, local_decls))
             }
             Rvalue::UnaryOp(op, ref expr) => {
                 builder.expr().build_unary(
                     op,
-                    expr.to_expr(local_decls))
+                    expr.to_expr(span, local_decls))
             }
             Rvalue::Tuple(ref items) => {
                 builder.expr().tuple()
-                    .with_exprs(items.iter().map(|item| item.to_expr(local_decls)))
+                    .with_exprs(items.iter().map(|item| item.to_expr(span, local_decls)))
                     .build()
             }
             Rvalue::Struct(ref path, ref fields, ref items, ref wth) => {
                 let fields = fields.iter()
                     .zip(items)
                     .map(|(field, item)| {
-                        let item = item.to_expr(local_decls);
+                        let item = item.to_expr(span, local_decls);
                         ast::Field { expr: item, .. field.clone() }
                     });
 
@@ -829,15 +933,15 @@ impl ToExpr for Rvalue {
                     .with_fields(fields);
 
                 if let Some(ref wth) = *wth {
-                    let wth = wth.to_expr(local_decls);
+                    let wth = wth.to_expr(span, local_decls);
                     struct_builder.build_with().build(wth)
                 } else {
                     struct_builder.build()
                 }
             }
             Rvalue::Range(ref from, ref to, ref limits) => {
-                let from = from.as_ref().map(|from| from.to_expr(local_decls));
-                let to = to.as_ref().map(|to| to.to_expr(local_decls));
+                let from = from.as_ref().map(|from| from.to_expr(span, local_decls));
+                let to = to.as_ref().map(|to| to.to_expr(span, local_decls));
 
                 builder.expr().range()
                     .from_opt(from)
@@ -886,27 +990,23 @@ impl Debug for Rvalue {
                 }
             }
             Struct(ref path, ref fields, ref items, ref wth) => {
-                write!(fmt, "{:?} {{", path)?;
+                write!(fmt, "{} {{ ", pprust::path_to_string(path))?;
 
-                let mut first = true;
-                for (field, item) in fields.iter().zip(items.iter()) {
-                    if first {
+                for (i, (field, item)) in fields.iter().zip(items.iter()).enumerate() {
+                    if i != 0 {
                         write!(fmt, ", ")?;
-                    } else {
-                        first = false;
-                    }
-                    if field.is_shorthand {
-                        write!(fmt, "{:?}", item)?;
-                    } else {
-                        write!(fmt, "{:?}: {:?}", field.ident, item)?;
                     }
+                    write!(fmt, "{}: {:?}", field.ident.node, item)?;
                 }
 
                 if let Some(ref wth) = *wth {
-                    write!(fmt, " .. {:?}", wth)?;
+                    if !fields.is_empty() {
+                        write!(fmt, ", ")?;
+                    }
+                    write!(fmt, ".. {:?}", wth)?;
                 }
 
-                write!(fmt, "}}")
+                write!(fmt, " }}")
             }
             Range(ref from, ref to, limits) => {
                 if let Some(ref from) = *from {
@@ -944,12 +1044,13 @@ pub struct Constant {
 
 impl Debug for Constant {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        write!(fmt, "const {}", pprust::lit_to_string(&self.literal))
+        write!(fmt, "{}", pprust::lit_to_string(&self.literal))
     }
 }
 
 impl ToExpr for Constant {
-    fn to_expr(&self, _local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
+    fn to_expr(&self, _span: Span, _local_decls: &IndexVec<Local, LocalDecl>) -> P<ast::Expr> {
+        // Constants keep their own span, since they are always written out where they are used.
         AstBuilder::new().span(self.span)
             .expr()
             .build_lit(self.literal.clone())
@@ -1015,11 +1116,21 @@ impl Debug for Statement {
             Stmt(ref stmt) => {
                 write!(fmt, "stmt {}", pprust::stmt_to_string(stmt))
             }
-            Let { ref pat, ty: None, ref rvalue, .. } => {
-                write!(fmt, "let {:?} = {:?}", pat, rvalue)
-            }
-            Let { ref pat, ty: Some(ref ty), ref rvalue, .. } => {
-                write!(fmt, "let {:?}: {:?} = {:?}", pat, ty, rvalue)
+            Let { ref pat, ref ty, ref lvalues, ref rvalue } => {
+                write!(fmt, "let {}", pprust::pat_to_string(pat))?;
+
+                if let Some(ref ty) = *ty {
+                    write!(fmt, ": {}", pprust::ty_to_string(ty))?;
+                }
+
+                write!(fmt, " = {:?} -> [", rvalue)?;
+                for (i, lvalue) in lvalues.iter().enumerate() {
+                    if i != 0 {
+                        write!(fmt, ",")?;
+                    }
+                    write!(fmt, "{:?}", lvalue)?;
+                }
+                write!(fmt, "]")
             }
             Assign(ref lvalue, ref rvalue) => {
                 write!(fmt, "{:?} = {:?}", lvalue, rvalue)
@@ -1047,7 +1158,7 @@ impl Debug for Statement {
                         if i != 0 {
                             write!(fmt, ", ")?;
                         }
-                        write!(fmt, "{:?}", ty)?;
+                        write!(fmt, "{}", pprust::ty_to_string(ty))?;
                     }
                     write!(fmt, ">")?;
                 }
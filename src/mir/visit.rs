@@ -286,6 +286,7 @@ macro_rules! make_mir_visitor {
                 let BasicBlockData {
                     ref $($mutability)* span,
                     name: _,
+                    label: _,
                     ref $($mutability)* statements,
                     ref $($mutability)* terminator,
                 } = *data;
@@ -329,7 +330,7 @@ macro_rules! make_mir_visitor {
 
                 self.visit_source_info(source_info);
                 match *kind {
-                    StatementKind::Stmt(ref stmt) => {}
+                    StatementKind::Stmt(_) => {}
                     StatementKind::Let {
                         pat: _,
                         ref $($mutability)* ty,
@@ -432,10 +433,13 @@ macro_rules! make_mir_visitor {
                     }
 
                     TerminatorKind::Match { ref $($mutability)* discr,
-                                            ref targets } => {
+                                            ref $($mutability)* arms } => {
                         self.visit_operand(discr, source_location);
-                        for target in targets {
-                            self.visit_branch(block, target.block);
+                        for arm in arms {
+                            for lvalue in & $($mutability)* arm.lvalues {
+                                self.visit_lvalue(lvalue, LvalueContext::Store, source_location);
+                            }
+                            self.visit_branch(block, arm.block);
                         }
                     }
 
@@ -520,11 +524,11 @@ macro_rules! make_mir_visitor {
 
                     TerminatorKind::Suspend {
                         destination: (ref $($mutability)* destination, target),
-                        ref $($mutability)* rvalue,
+                        ref $($mutability)* arg,
                     } => {
+                        self.visit_operand(arg, source_location);
                         self.visit_lvalue(destination, LvalueContext::Call, source_location);
                         self.visit_branch(block, target);
-                        self.visit_rvalue(rvalue, source_location);
                     }
                 }
             }
@@ -674,9 +678,12 @@ macro_rules! make_mir_visitor {
                              operand: & $($mutability)* Operand,
                              location: Location) {
                 match *operand {
-                    Operand::Consume(ref $($mutability)* lvalue) => {
+                    Operand::Consume(ref $($mutability)* lvalue, _) => {
                         self.visit_lvalue(lvalue, LvalueContext::Consume, location);
                     }
+                    Operand::Copy(ref $($mutability)* lvalue, _) => {
+                        self.visit_lvalue(lvalue, LvalueContext::Inspect, location);
+                    }
                     Operand::Constant(ref $($mutability)* constant) => {
                         self.visit_constant(constant, location);
                     }
@@ -723,7 +730,7 @@ macro_rules! make_mir_visitor {
             fn super_projection_elem(&mut self,
                                      proj: & $($mutability)* LvalueElem,
                                      _context: LvalueContext,
-                                     location: Location) {
+                                     _location: Location) {
                 match *proj {
                     ProjectionElem::Deref => {
                     }
@@ -753,7 +760,7 @@ macro_rules! make_mir_visitor {
                     ref $($mutability)* ty,
                     name: _,
                     ref $($mutability)* source_info,
-                    shadowed_decl: _,
+                    is_user_variable: _,
                 } = *local_decl;
 
                 if let Some(ref $($mutability)* ty) = *ty {
@@ -803,8 +810,8 @@ macro_rules! make_mir_visitor {
             */
 
             fn super_literal(&mut self,
-                             literal: & $($mutability)* P<ast::Lit>,
-                             location: Location) {
+                             _literal: & $($mutability)* P<ast::Lit>,
+                             _location: Location) {
                 /*
                 match *literal {
                     Literal::Item { ref $($mutability)* def_id,
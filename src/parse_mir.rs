@@ -0,0 +1,1018 @@
+//! Read the `.mar` text format written by `pretty::write_mir_fn` back into a `Mir`, so that
+//! passes can be tested on small hand-written functions.
+//!
+//! The MIR syntax is parsed with the libsyntax parser, which also takes care of the patterns,
+//! types, literals, paths and macros embedded in it. The lexer skips comments, so the trailing
+//! `// ...` comments that carry the scopes, local names and block names are first rewritten into
+//! `@ "..."` string tokens.
+//!
+//! Spans aren't part of the text format, so everything in the parsed MIR points at `DUMMY_SP`,
+//! and struct shorthand fields come back as `field: operand`. Otherwise printing a parsed `Mir`
+//! gives back the text it was parsed from.
+
+use aster::AstBuilder;
+use config::Config;
+use data_structures::indexed_vec::{Idx, IndexVec};
+use mir::*;
+use std::collections::BTreeMap;
+use syntax::ast;
+use syntax::codemap::respan;
+use syntax::parse::{self, ParseSess, PResult};
+use syntax::parse::parser::{Parser, PathStyle};
+use syntax::parse::token::{BinOpToken, DelimToken, Token};
+use syntax::print::pprust;
+use syntax::ptr::P;
+use syntax::util::parser::AssocOp;
+use syntax_pos::DUMMY_SP;
+
+/// The names the builder gives its blocks. `BasicBlockData` only holds static names, so a block
+/// name has to be one of these.
+const BLOCK_NAMES: &'static [&'static str] = &[
+    "AfterBreak",
+    "AfterContinue",
+    "AfterGoto",
+    "AfterReturn",
+    "Arm",
+    "Else",
+    "End",
+    "IfJoin",
+    "Loop",
+    "LoopBody",
+    "LoopExit",
+    "MatchEnd",
+    "Resume",
+    "Start",
+    "Then",
+    "Unreachable",
+];
+
+/// Parse the MIR of a single function. Any comment lines before the signature, like the header of
+/// a MIR dump, are skipped.
+pub fn parse_mir<'a>(sess: &'a ParseSess, config: Config, source: &str) -> PResult<'a, Mir> {
+    let (sig, kind, body) = match split_signature(source) {
+        Some(parts) => parts,
+        None => {
+            return Err(sess.span_diagnostic.struct_err("expected a function signature"));
+        }
+    };
+
+    let fn_decl = parse_signature(sess, sig)?;
+
+    let state_machine_kind = match &*kind {
+        "generator" => StateMachineKind::Generator,
+        "async" => StateMachineKind::Async,
+        "state_machine" => StateMachineKind::StateMachine,
+        "push_parser" => StateMachineKind::PushParser,
+        "actor" => StateMachineKind::Actor,
+        _ => {
+            return Err(sess.span_diagnostic.struct_err(
+                &format!("unknown state machine kind `{}`", kind)));
+        }
+    };
+
+    let mut parser = MirParser {
+        parser: parse::new_parser_from_source_str(sess, "<mir>".to_string(), preprocess(&body)),
+        scopes: BTreeMap::new(),
+        local_decls: BTreeMap::new(),
+    };
+
+    parser.scopes.insert(ARGUMENT_VISIBILITY_SCOPE.index(), None);
+    parser.parse_scope(ARGUMENT_VISIBILITY_SCOPE)?;
+    let basic_blocks = parser.parse_blocks()?;
+    parser.parser.expect(&Token::CloseDelim(DelimToken::Brace))?;
+    parser.parser.expect(&Token::Eof)?;
+
+    let scopes = parser.scopes.into_iter()
+        .map(|(index, parent_scope)| {
+            (index, VisibilityScopeData { span: DUMMY_SP, parent_scope: parent_scope })
+        })
+        .collect();
+    let visibility_scopes = contiguous(sess, "scope", scopes)?;
+    let local_decls = contiguous(sess, "local", parser.local_decls)?;
+
+    if local_decls.len() < fn_decl.inputs().len() + 2 {
+        return Err(sess.span_diagnostic.struct_err(
+            &format!("expected at least {} locals, found {}",
+                     fn_decl.inputs().len() + 2,
+                     local_decls.len())));
+    }
+
+    Ok(Mir::new(
+        state_machine_kind,
+        config,
+        basic_blocks,
+        visibility_scopes,
+        local_decls,
+        DUMMY_SP,
+        fn_decl))
+}
+
+/// Split a line into its code and its trailing comment.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    if line.trim_left().starts_with("//") {
+        ("", Some(line.trim_left()[2..].trim_left()))
+    } else {
+        match line.rfind(" // ") {
+            Some(index) => (&line[..index], Some(&line[index + 4..])),
+            None => (line, None),
+        }
+    }
+}
+
+/// Split the text into the function signature, the state machine kind written in the comment
+/// following it, and the rest of the function.
+fn split_signature(source: &str) -> Option<(String, String, String)> {
+    let mut sig = String::new();
+    let mut lines = source.lines();
+
+    while let Some(line) = lines.next() {
+        let (code, comment) = split_comment(line);
+        if code.trim().is_empty() {
+            continue;
+        }
+
+        sig.push_str(code);
+        sig.push('\n');
+
+        if code.trim_right().ends_with('{') {
+            let kind = comment.unwrap_or("").trim().to_string();
+            let body = lines.collect::<Vec<_>>().join("\n");
+            return Some((sig, kind, body));
+        }
+    }
+
+    None
+}
+
+/// Parse the signature by completing it into an empty function.
+fn parse_signature<'a>(sess: &'a ParseSess, sig: String) -> PResult<'a, FunctionDecl> {
+    let item = parse::parse_item_from_source_str(
+        "<mir signature>".to_string(),
+        format!("{}}}", sig),
+        sess)?;
+
+    if let Some(item) = item {
+        if let ast::ItemKind::Fn(ref fn_decl, ref unsafety, _, ref abi, ref generics, _) =
+            item.node
+        {
+            return Ok(FunctionDecl::new(
+                item.ident,
+                fn_decl.clone(),
+                *unsafety,
+                *abi,
+                generics.clone()));
+        }
+    }
+
+    Err(sess.span_diagnostic.struct_err("expected a function signature"))
+}
+
+/// Turn every trailing comment into an `@ "comment"` token, so that the parser gets to see it.
+fn preprocess(body: &str) -> String {
+    body.lines()
+        .map(|line| {
+            match split_comment(line) {
+                (code, Some(comment)) if !code.trim().is_empty() => {
+                    format!("{} @ {:?}", code, comment)
+                }
+                _ => line.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Parse the `N` out of a `scope N` or `scope N at <span>` comment.
+fn scope_comment(comment: &str) -> Option<usize> {
+    if !comment.starts_with("scope ") {
+        return None;
+    }
+
+    let rest = &comment["scope ".len()..];
+    let end = rest.find(' ').unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+/// Parse a `"name" in scope N` comment of a local, which starts with `temp` for temporaries.
+/// Returns whether it's a user variable, its name and its scope.
+fn local_comment(comment: &str) -> Option<(bool, String, usize)> {
+    let (is_user_variable, comment) = if comment.starts_with("temp ") {
+        (false, &comment["temp ".len()..])
+    } else {
+        (true, comment)
+    };
+
+    if !comment.starts_with('"') {
+        return None;
+    }
+
+    let comment = &comment[1..];
+    let end = match comment.find('"') {
+        Some(end) => end,
+        None => { return None; }
+    };
+
+    let name = &comment[..end];
+    let rest = &comment[end + 1..];
+
+    if !rest.starts_with(" in ") {
+        return None;
+    }
+
+    scope_comment(&rest[" in ".len()..]).map(|scope| (is_user_variable, name.to_string(), scope))
+}
+
+/// Check that a map holds every index from zero up, and turn it into an `IndexVec`.
+fn contiguous<'a, I, T>(sess: &'a ParseSess,
+                        what: &str,
+                        map: BTreeMap<usize, T>) -> PResult<'a, IndexVec<I, T>>
+    where I: Idx,
+{
+    let mut vec = IndexVec::new();
+
+    for (index, value) in map {
+        if index != vec.len() {
+            return Err(sess.span_diagnostic.struct_err(
+                &format!("{} {} is never declared", what, vec.len())));
+        }
+
+        vec.push(value);
+    }
+
+    Ok(vec)
+}
+
+/// `Rvalue::Struct` keeps the fields of the original expression around for their names. Their
+/// expressions are replaced by the operands when the MIR is translated, so any expression will do.
+fn struct_fields(path: &ast::Path, idents: Vec<ast::Ident>) -> Vec<ast::Field> {
+    let builder = AstBuilder::new();
+    let mut struct_builder = builder.expr().struct_path(path.clone());
+
+    for ident in idents {
+        struct_builder = struct_builder.field(ident).unit();
+    }
+
+    match struct_builder.build().node {
+        ast::ExprKind::Struct(_, ref fields, _) => fields.clone(),
+        _ => unreachable!(),
+    }
+}
+
+struct MirParser<'a> {
+    parser: Parser<'a>,
+    scopes: BTreeMap<usize, Option<VisibilityScope>>,
+    local_decls: BTreeMap<usize, LocalDecl>,
+}
+
+impl<'a> MirParser<'a> {
+    fn err<T>(&self, msg: &str) -> PResult<'a, T> {
+        Err(self.parser.fatal(msg))
+    }
+
+    fn expected<T>(&self, what: &str) -> PResult<'a, T> {
+        let found = self.parser.this_token_to_string();
+        self.err(&format!("expected {}, found `{}`", what, found))
+    }
+
+    fn is_word_at(&mut self, dist: usize, word: &str) -> bool {
+        self.parser.look_ahead(dist, |token| {
+            match *token {
+                Token::Ident(ident) => &*ident.name.as_str() == word,
+                _ => false,
+            }
+        })
+    }
+
+    fn is_word(&mut self, word: &str) -> bool {
+        self.is_word_at(0, word)
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        if self.is_word(word) {
+            self.parser.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> PResult<'a, ()> {
+        if self.eat_word(word) {
+            Ok(())
+        } else {
+            self.expected(&format!("`{}`", word))
+        }
+    }
+
+    fn is_token_at(&mut self, dist: usize, expected: &Token) -> bool {
+        self.parser.look_ahead(dist, |token| token == expected)
+    }
+
+    /// The index of an identifier like `bb3` or `_3`.
+    fn index_at(&mut self, dist: usize, prefix: &str) -> Option<usize> {
+        self.parser.look_ahead(dist, |token| {
+            match *token {
+                Token::Ident(ident) => {
+                    let name = ident.name.as_str();
+                    if name.starts_with(prefix) {
+                        name[prefix.len()..].parse().ok()
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }
+        })
+    }
+
+    fn parse_index(&mut self, prefix: &str) -> PResult<'a, usize> {
+        match self.index_at(0, prefix) {
+            Some(index) => {
+                self.parser.bump();
+                Ok(index)
+            }
+            None => self.expected(&format!("`{}N`", prefix)),
+        }
+    }
+
+    fn parse_usize(&mut self) -> PResult<'a, usize> {
+        match self.parser.parse_lit()?.node {
+            ast::LitKind::Int(value, _) => Ok(value as usize),
+            _ => self.err("expected an integer"),
+        }
+    }
+
+    /// Parse a `@ "..."` token that was a trailing comment.
+    fn parse_comment(&mut self) -> PResult<'a, String> {
+        self.parser.expect(&Token::At)?;
+
+        match self.parser.parse_lit()?.node {
+            ast::LitKind::Str(comment, _) => Ok((&*comment.as_str()).to_string()),
+            _ => self.err("expected a comment"),
+        }
+    }
+
+    fn parse_source_info(&mut self) -> PResult<'a, SourceInfo> {
+        let comment = self.parse_comment()?;
+
+        match scope_comment(&comment) {
+            Some(scope) => {
+                Ok(SourceInfo {
+                    span: DUMMY_SP,
+                    scope: VisibilityScope::new(scope),
+                })
+            }
+            None => self.err(&format!("expected a `scope N` comment, found `{}`", comment)),
+        }
+    }
+
+    /// Parse the locals declared in a scope, followed by its child scopes.
+    fn parse_scope(&mut self, scope: VisibilityScope) -> PResult<'a, ()> {
+        loop {
+            if self.is_word("let") {
+                self.parse_local_decl(scope)?;
+            } else if self.eat_word("scope") {
+                let child = self.parse_usize()?;
+
+                if self.scopes.insert(child, Some(scope)).is_some() {
+                    return self.err(&format!("scope {} is declared twice", child));
+                }
+
+                self.parser.expect(&Token::OpenDelim(DelimToken::Brace))?;
+                self.parse_scope(VisibilityScope::new(child))?;
+                self.parser.expect(&Token::CloseDelim(DelimToken::Brace))?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_local_decl(&mut self, scope: VisibilityScope) -> PResult<'a, ()> {
+        self.expect_word("let")?;
+
+        let mutability = if self.eat_word("mut") {
+            ast::Mutability::Mutable
+        } else {
+            ast::Mutability::Immutable
+        };
+
+        let local = self.parse_index("_")?;
+
+        let ty = if self.parser.eat(&Token::Colon) {
+            let ty = self.parser.parse_ty()?;
+
+            // Locals without a type are printed as `_`.
+            if ty.node == ast::TyKind::Infer {
+                None
+            } else {
+                Some(ty)
+            }
+        } else {
+            None
+        };
+
+        self.parser.expect(&Token::Semi)?;
+        let comment = self.parse_comment()?;
+
+        let decl = match local {
+            0 => {
+                let source_info = SourceInfo { span: DUMMY_SP, scope: RETURN_PTR_VISIBILITY_SCOPE };
+                LocalDecl::new_return_pointer(source_info, None)
+            }
+            1 => {
+                let source_info = SourceInfo {
+                    span: DUMMY_SP,
+                    scope: COROUTINE_ARGS_VISIBILITY_SCOPE,
+                };
+                LocalDecl::new_coroutine_args(source_info, None)
+            }
+            _ => {
+                let (is_user_variable, name, decl_scope) = match local_comment(&comment) {
+                    Some(parts) => parts,
+                    None => {
+                        return self.err(&format!(
+                            "expected a `\"name\" in scope N` comment, found `{}`",
+                            comment));
+                    }
+                };
+
+                if decl_scope != scope.index() {
+                    return self.err(&format!(
+                        "_{} is declared in scope {}, but is written in scope {}",
+                        local,
+                        decl_scope,
+                        scope.index()));
+                }
+
+                LocalDecl {
+                    mutability: mutability,
+                    ty: ty,
+                    name: ast::Ident::from_str(&name),
+                    source_info: SourceInfo { span: DUMMY_SP, scope: scope },
+                    is_user_variable: is_user_variable,
+                }
+            }
+        };
+
+        if self.local_decls.insert(local, decl).is_some() {
+            return self.err(&format!("_{} is declared twice", local));
+        }
+
+        Ok(())
+    }
+
+    fn parse_blocks(&mut self) -> PResult<'a, IndexVec<BasicBlock, BasicBlockData>> {
+        let mut basic_blocks = IndexVec::new();
+
+        while self.index_at(0, "bb").is_some() {
+            let block = self.parse_block()?;
+            if block.index() != basic_blocks.len() {
+                return self.err(&format!("expected bb{}, found {:?}", basic_blocks.len(), block));
+            }
+
+            self.parser.expect(&Token::Colon)?;
+            self.parser.expect(&Token::OpenDelim(DelimToken::Brace))?;
+
+            // The comment holds the block name, followed by the label of a state, like
+            // `// Loop 'start`.
+            let (name, label) = if self.parser.check(&Token::At) {
+                let comment = self.parse_comment()?;
+                let mut words = comment.split_whitespace();
+
+                let name = words.next().unwrap_or("");
+                let name = match BLOCK_NAMES.iter().find(|block_name| **block_name == name) {
+                    Some(&name) => name,
+                    None => { return self.err(&format!("unknown block name `{}`", name)); }
+                };
+
+                let label = match words.next() {
+                    Some(label) if label.starts_with('\'') => Some(ast::Ident::from_str(label)),
+                    Some(label) => {
+                        return self.err(&format!("expected a label, found `{}`", label));
+                    }
+                    None => None,
+                };
+
+                (Some(name), label)
+            } else {
+                (None, None)
+            };
+
+            let mut data = BasicBlockData::new(DUMMY_SP, name);
+            data.label = label;
+
+            while !self.is_terminator() {
+                let statement = self.parse_statement()?;
+                data.statements.push(statement);
+            }
+
+            data.terminator = Some(self.parse_terminator()?);
+            self.parser.expect(&Token::CloseDelim(DelimToken::Brace))?;
+
+            basic_blocks.push(data);
+        }
+
+        Ok(basic_blocks)
+    }
+
+    fn parse_block(&mut self) -> PResult<'a, BasicBlock> {
+        Ok(BasicBlock::new(self.parse_index("bb")?))
+    }
+
+    fn parse_target(&mut self) -> PResult<'a, BasicBlock> {
+        self.parser.expect(&Token::RArrow)?;
+        self.parse_block()
+    }
+
+    fn is_terminator(&mut self) -> bool {
+        if self.is_word("goto") ||
+            self.is_word("break") ||
+            self.is_word("if") ||
+            self.is_word("match") ||
+            self.is_word("return")
+        {
+            return true;
+        }
+
+        // `_1 = suspend(...)`
+        self.index_at(0, "_").is_some() &&
+            self.is_token_at(1, &Token::Eq) &&
+            self.is_word_at(2, "suspend") &&
+            self.is_token_at(3, &Token::OpenDelim(DelimToken::Paren))
+    }
+
+    fn parse_statement(&mut self) -> PResult<'a, Statement> {
+        let kind = if self.eat_word("stmt") {
+            StatementKind::Stmt(self.parse_stmt()?)
+        } else if self.eat_word("let") {
+            let pat = self.parser.parse_pat()?;
+
+            let ty = if self.parser.eat(&Token::Colon) {
+                Some(self.parser.parse_ty()?)
+            } else {
+                None
+            };
+
+            self.parser.expect(&Token::Eq)?;
+            let rvalue = self.parse_rvalue()?;
+            self.parser.expect(&Token::RArrow)?;
+            let lvalues = self.parse_lvalues()?;
+
+            StatementKind::Let {
+                pat: pat,
+                ty: ty,
+                lvalues: lvalues,
+                rvalue: rvalue,
+            }
+        } else if self.eat_word("StorageLive") {
+            StatementKind::StorageLive(self.parse_parenthesized_lvalue()?)
+        } else if self.eat_word("StorageDead") {
+            StatementKind::StorageDead(self.parse_parenthesized_lvalue()?)
+        } else {
+            let destination = self.parse_lvalue()?;
+            self.parser.expect(&Token::Eq)?;
+            let rvalue = self.parse_rvalue()?;
+
+            if self.parser.check(&Token::OpenDelim(DelimToken::Paren)) {
+                let func = self.into_operand(rvalue)?;
+                let args = self.parse_args()?;
+
+                StatementKind::Call {
+                    destination: destination,
+                    func: func,
+                    args: args,
+                }
+            } else if self.parser.check(&Token::Dot) {
+                let self_ = self.into_operand(rvalue)?;
+                self.parser.bump();
+
+                let span = self.parser.span;
+                let ident = self.parser.parse_ident()?;
+
+                let mut tys = vec![];
+                if self.parser.eat(&Token::ModSep) {
+                    self.parser.expect(&Token::Lt)?;
+                    loop {
+                        tys.push(self.parser.parse_ty()?);
+                        if !self.parser.eat(&Token::Comma) {
+                            break;
+                        }
+                    }
+                    self.parser.expect_gt()?;
+                }
+
+                let args = self.parse_args()?;
+
+                StatementKind::MethodCall {
+                    destination: destination,
+                    ident: respan(span, ident),
+                    tys: tys,
+                    self_: self_,
+                    args: args,
+                }
+            } else {
+                StatementKind::Assign(destination, rvalue)
+            }
+        };
+
+        self.parser.expect(&Token::Semi)?;
+        let source_info = self.parse_source_info()?;
+
+        Ok(Statement {
+            source_info: source_info,
+            kind: kind,
+        })
+    }
+
+    fn parse_stmt(&mut self) -> PResult<'a, ast::Stmt> {
+        let mut stmt = match self.parser.parse_stmt()? {
+            Some(stmt) => stmt,
+            None => { return self.expected("a statement"); }
+        };
+
+        // A statement's own semicolon is followed by the one every MIR statement ends with.
+        if self.parser.check(&Token::Semi) && self.is_token_at(1, &Token::Semi) {
+            self.parser.bump();
+            stmt = stmt.add_trailing_semicolon();
+        }
+
+        Ok(stmt)
+    }
+
+    fn parse_terminator(&mut self) -> PResult<'a, Terminator> {
+        let kind = if self.eat_word("goto") {
+            TerminatorKind::Goto { target: self.parse_target()? }
+        } else if self.eat_word("break") {
+            self.parser.expect(&Token::RArrow)?;
+            self.parser.expect(&Token::OpenDelim(DelimToken::Bracket))?;
+            let target = self.parse_edge("target")?;
+            self.parser.expect(&Token::Comma)?;
+            let after_target = self.parse_edge("after_target")?;
+            self.parser.expect(&Token::CloseDelim(DelimToken::Bracket))?;
+
+            TerminatorKind::Break {
+                target: target,
+                after_target: after_target,
+            }
+        } else if self.eat_word("if") {
+            let cond = self.parse_parenthesized_operand()?;
+            self.parser.expect(&Token::RArrow)?;
+            self.parser.expect(&Token::OpenDelim(DelimToken::Bracket))?;
+            let then = self.parse_edge("true")?;
+            self.parser.expect(&Token::Comma)?;
+            let else_ = self.parse_edge("false")?;
+            self.parser.expect(&Token::CloseDelim(DelimToken::Bracket))?;
+
+            TerminatorKind::If {
+                cond: cond,
+                targets: (then, else_),
+            }
+        } else if self.eat_word("match") {
+            let discr = self.parse_parenthesized_operand()?;
+
+            // A match without any arms has no successors to print.
+            let arms = if self.parser.eat(&Token::RArrow) {
+                self.parse_arms()?
+            } else {
+                vec![]
+            };
+
+            TerminatorKind::Match {
+                discr: discr,
+                arms: arms,
+            }
+        } else if self.eat_word("return") {
+            TerminatorKind::Return
+        } else {
+            let destination = self.parse_lvalue()?;
+            self.parser.expect(&Token::Eq)?;
+            self.expect_word("suspend")?;
+            let arg = self.parse_parenthesized_operand()?;
+            let target = self.parse_target()?;
+
+            TerminatorKind::Suspend {
+                destination: (destination, target),
+                arg: arg,
+            }
+        };
+
+        self.parser.expect(&Token::Semi)?;
+        let source_info = self.parse_source_info()?;
+
+        Ok(Terminator {
+            source_info: source_info,
+            kind: kind,
+        })
+    }
+
+    /// Parse a labelled edge, like the `true: bb1` of an `if`.
+    fn parse_edge(&mut self, label: &str) -> PResult<'a, BasicBlock> {
+        self.expect_word(label)?;
+        self.parser.expect(&Token::Colon)?;
+        self.parse_block()
+    }
+
+    /// Parse the arms of a `match`, written as `pat | pat if (guard): [lvalues]: bbN`.
+    fn parse_arms(&mut self) -> PResult<'a, Vec<Arm>> {
+        self.parser.expect(&Token::OpenDelim(DelimToken::Bracket))?;
+
+        let mut arms = vec![];
+
+        loop {
+            let mut pats = vec![self.parser.parse_pat()?];
+            while self.parser.eat(&Token::BinOp(BinOpToken::Or)) {
+                pats.push(self.parser.parse_pat()?);
+            }
+
+            let guard = if self.eat_word("if") {
+                self.parser.expect(&Token::OpenDelim(DelimToken::Paren))?;
+                let guard = self.parser.parse_expr()?;
+                self.parser.expect(&Token::CloseDelim(DelimToken::Paren))?;
+                Some(guard)
+            } else {
+                None
+            };
+
+            self.parser.expect(&Token::Colon)?;
+            let lvalues = self.parse_lvalues()?;
+            self.parser.expect(&Token::Colon)?;
+            let block = self.parse_block()?;
+
+            arms.push(Arm {
+                pats: pats,
+                guard: guard,
+                lvalues: lvalues,
+                block: block,
+            });
+
+            if !self.parser.eat(&Token::Comma) {
+                break;
+            }
+        }
+
+        self.parser.expect(&Token::CloseDelim(DelimToken::Bracket))?;
+
+        Ok(arms)
+    }
+
+    /// Parse a `[_2,_3]` list of lvalues.
+    fn parse_lvalues(&mut self) -> PResult<'a, Vec<Lvalue>> {
+        self.parser.expect(&Token::OpenDelim(DelimToken::Bracket))?;
+
+        let mut lvalues = vec![];
+
+        while !self.parser.eat(&Token::CloseDelim(DelimToken::Bracket)) {
+            lvalues.push(self.parse_lvalue()?);
+
+            if !self.parser.eat(&Token::Comma) {
+                self.parser.expect(&Token::CloseDelim(DelimToken::Bracket))?;
+                break;
+            }
+        }
+
+        Ok(lvalues)
+    }
+
+    fn parse_args(&mut self) -> PResult<'a, Vec<Rvalue>> {
+        self.parser.expect(&Token::OpenDelim(DelimToken::Paren))?;
+
+        let mut args = vec![];
+
+        while !self.parser.eat(&Token::CloseDelim(DelimToken::Paren)) {
+            args.push(self.parse_rvalue()?);
+
+            if !self.parser.eat(&Token::Comma) {
+                self.parser.expect(&Token::CloseDelim(DelimToken::Paren))?;
+                break;
+            }
+        }
+
+        Ok(args)
+    }
+
+    fn parse_lvalue(&mut self) -> PResult<'a, Lvalue> {
+        if self.parser.eat(&Token::OpenDelim(DelimToken::Paren)) {
+            self.parser.expect(&Token::BinOp(BinOpToken::Star))?;
+            let lvalue = self.parse_lvalue()?;
+            self.parser.expect(&Token::CloseDelim(DelimToken::Paren))?;
+
+            Ok(lvalue.deref())
+        } else if self.index_at(0, "_").is_some() {
+            Ok(Lvalue::Local(Local::new(self.parse_index("_")?)))
+        } else {
+            let span = self.parser.span;
+            let path = self.parser.parse_path(PathStyle::Expr)?;
+
+            Ok(Lvalue::Static(AstBuilder::new().span(span).expr().build_path(path)))
+        }
+    }
+
+    fn parse_parenthesized_lvalue(&mut self) -> PResult<'a, Lvalue> {
+        self.parser.expect(&Token::OpenDelim(DelimToken::Paren))?;
+        let lvalue = self.parse_lvalue()?;
+        self.parser.expect(&Token::CloseDelim(DelimToken::Paren))?;
+
+        Ok(lvalue)
+    }
+
+    fn is_operand_at(&mut self, dist: usize) -> bool {
+        self.is_word_at(dist, "copy") ||
+            self.is_word_at(dist, "consume") ||
+            self.is_word_at(dist, "const")
+    }
+
+    fn parse_operand(&mut self) -> PResult<'a, Operand> {
+        if self.eat_word("copy") {
+            Ok(Operand::Copy(self.parse_lvalue()?, DUMMY_SP))
+        } else if self.eat_word("consume") {
+            Ok(Operand::Consume(self.parse_lvalue()?, DUMMY_SP))
+        } else if self.eat_word("const") {
+            let literal = self.parser.parse_lit()?;
+
+            Ok(Operand::Constant(Constant {
+                span: DUMMY_SP,
+                literal: P(literal),
+            }))
+        } else {
+            self.expected("an operand")
+        }
+    }
+
+    fn parse_parenthesized_operand(&mut self) -> PResult<'a, Operand> {
+        self.parser.expect(&Token::OpenDelim(DelimToken::Paren))?;
+        let operand = self.parse_operand()?;
+        self.parser.expect(&Token::CloseDelim(DelimToken::Paren))?;
+
+        Ok(operand)
+    }
+
+    fn into_operand(&self, rvalue: Rvalue) -> PResult<'a, Operand> {
+        match rvalue {
+            Rvalue::Use(operand) => Ok(operand),
+            rvalue => self.err(&format!("expected an operand, found `{:?}`", rvalue)),
+        }
+    }
+
+    fn parse_rvalue(&mut self) -> PResult<'a, Rvalue> {
+        if self.is_operand_at(0) {
+            let operand = self.parse_operand()?;
+
+            if self.parser.check(&Token::DotDot) || self.parser.check(&Token::DotDotDot) {
+                return self.parse_range(Some(operand));
+            }
+
+            return Ok(Rvalue::Use(operand));
+        }
+
+        if self.parser.check(&Token::DotDot) || self.parser.check(&Token::DotDotDot) {
+            return self.parse_range(None);
+        }
+
+        // Operators are written like calls, as in `==(copy _2, const 1)`.
+        if self.is_token_at(1, &Token::OpenDelim(DelimToken::Paren)) && self.is_operand_at(2) {
+            return self.parse_operator();
+        }
+
+        if self.parser.eat(&Token::OpenDelim(DelimToken::Paren)) {
+            let mut operands = vec![];
+
+            while !self.parser.eat(&Token::CloseDelim(DelimToken::Paren)) {
+                operands.push(self.parse_operand()?);
+
+                if !self.parser.eat(&Token::Comma) {
+                    self.parser.expect(&Token::CloseDelim(DelimToken::Paren))?;
+                    break;
+                }
+            }
+
+            return Ok(Rvalue::Tuple(operands));
+        }
+
+        if self.parser.eat(&Token::BinOp(BinOpToken::And)) {
+            let mutability = if self.eat_word("mut") {
+                ast::Mutability::Mutable
+            } else {
+                ast::Mutability::Immutable
+            };
+
+            return Ok(Rvalue::Ref(mutability, self.parse_lvalue()?));
+        }
+
+        if self.is_mac() {
+            let expr = self.parser.parse_expr()?;
+
+            return match expr.node {
+                ast::ExprKind::Mac(ref mac) => Ok(Rvalue::Mac(mac.clone())),
+                _ => self.err(&format!("expected a macro, found `{}`",
+                                       pprust::expr_to_string(&expr))),
+            };
+        }
+
+        self.parse_struct()
+    }
+
+    /// Whether the next tokens are a path followed by a `!`.
+    fn is_mac(&mut self) -> bool {
+        let mut dist = 0;
+
+        loop {
+            let is_path = self.parser.look_ahead(dist, |token| {
+                match *token {
+                    Token::Ident(..) | Token::ModSep => true,
+                    _ => false,
+                }
+            });
+
+            if !is_path {
+                return dist > 0 && self.is_token_at(dist, &Token::Not);
+            }
+
+            dist += 1;
+        }
+    }
+
+    fn parse_operator(&mut self) -> PResult<'a, Rvalue> {
+        let span = self.parser.span;
+        let token = self.parser.token.clone();
+        self.parser.bump();
+
+        self.parser.expect(&Token::OpenDelim(DelimToken::Paren))?;
+        let operand = self.parse_operand()?;
+
+        let rvalue = if self.parser.eat(&Token::Comma) {
+            let rhs = self.parse_operand()?;
+
+            match AssocOp::from_token(&token).and_then(|op| op.to_ast_binop()) {
+                Some(op) => Rvalue::BinaryOp(respan(span, op), operand, rhs),
+                None => {
+                    return self.err(&format!("`{}` is not a binary operator",
+                                             pprust::token_to_string(&token)));
+                }
+            }
+        } else {
+            let op = match token {
+                Token::Not => ast::UnOp::Not,
+                Token::BinOp(BinOpToken::Minus) => ast::UnOp::Neg,
+                Token::BinOp(BinOpToken::Star) => ast::UnOp::Deref,
+                _ => {
+                    return self.err(&format!("`{}` is not a unary operator",
+                                             pprust::token_to_string(&token)));
+                }
+            };
+
+            Rvalue::UnaryOp(op, operand)
+        };
+
+        self.parser.expect(&Token::CloseDelim(DelimToken::Paren))?;
+
+        Ok(rvalue)
+    }
+
+    fn parse_range(&mut self, start: Option<Operand>) -> PResult<'a, Rvalue> {
+        let limits = if self.parser.eat(&Token::DotDot) {
+            ast::RangeLimits::HalfOpen
+        } else {
+            self.parser.expect(&Token::DotDotDot)?;
+            ast::RangeLimits::Closed
+        };
+
+        let end = if self.is_operand_at(0) {
+            Some(self.parse_operand()?)
+        } else {
+            None
+        };
+
+        Ok(Rvalue::Range(start, end, limits))
+    }
+
+    /// Parse a `Path { field: operand, .. operand }` struct expression.
+    fn parse_struct(&mut self) -> PResult<'a, Rvalue> {
+        let path = self.parser.parse_path(PathStyle::Expr)?;
+        self.parser.expect(&Token::OpenDelim(DelimToken::Brace))?;
+
+        let mut idents = vec![];
+        let mut operands = vec![];
+        let mut wth = None;
+
+        while !self.parser.eat(&Token::CloseDelim(DelimToken::Brace)) {
+            if self.parser.eat(&Token::DotDot) {
+                wth = Some(self.parse_operand()?);
+                self.parser.expect(&Token::CloseDelim(DelimToken::Brace))?;
+                break;
+            }
+
+            idents.push(self.parser.parse_ident()?);
+            self.parser.expect(&Token::Colon)?;
+            operands.push(self.parse_operand()?);
+
+            if !self.parser.eat(&Token::Comma) {
+                self.parser.expect(&Token::CloseDelim(DelimToken::Brace))?;
+                break;
+            }
+        }
+
+        let fields = struct_fields(&path, idents);
+
+        Ok(Rvalue::Struct(path, fields, operands, wth))
+    }
+}
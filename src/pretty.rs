@@ -10,74 +10,61 @@
 
 use aster::AstBuilder;
 use data_structures::indexed_vec::{Idx};
+use json;
 use mir::*;
 use std::collections::HashMap;
-use std::env;
 use std::fmt::Display;
-use std::fs;
 use std::io::{self, Write};
-use std::path::{PathBuf, Path};
-use syntax::ast::Mutability;
+use syntax::ast::{self, Mutability};
 use syntax::print::pprust;
+use syntax_pos::DUMMY_SP;
 use ty::TyCtxt;
 
 const INDENT: &'static str = "    ";
 /// Alignment for lining up comments following MIR statements
 const ALIGN: usize = 40;
 
-/// If the session is properly configured, dumps a human-readable
+/// If the function was annotated with `dump(mir)`, dumps a human-readable
 /// representation of the mir into:
 ///
 /// ```text
-/// rustc.node<node_id>.<pass_name>.<disambiguator>
+/// <dump_dir>/stateful.<fn>.<pass_name>.<disambiguator>.mar
 /// ```
 ///
-/// Output from this function is controlled by passing `-Z dump-mir=<filter>`,
-/// where `<filter>` takes the following forms:
-///
-/// - `all` -- dump MIR for all fns, all passes, all everything
-/// - `substring1&substring2,...` -- `&`-separated list of substrings
-///   that can appear in the pass-name or the `item_path_str` for the given
-///   node-id. If any one of the substrings match, the data is dumped out.
+/// With `dump(json)`, the same MIR is also written as JSON into a `.json` file next to it.
 pub fn dump_mir<'a, 'tcx>(tcx: TyCtxt<'a, 'tcx>,
                           pass_name: &str,
                           disambiguator: &Display,
                           mir: &Mir) {
-    let filters = match env::var_os("STATEFUL_DUMP_MIR") {
-        None => return,
-        Some(filters) => filters.into_string().unwrap(),
-    };
-    let node_path = format!("{}", mir.fn_decl.ident);
-
-    let is_matched =
-        filters.split("&")
-               .any(|filter| {
-                   filter == "all" ||
-                       pass_name.contains(filter) ||
-                       node_path.contains(filter)
-               });
-    if !is_matched {
+    if !mir.config.dump_pass(pass_name, &disambiguator.to_string()) {
         return;
     }
 
-    let mut file_path = PathBuf::new();
+    if mir.config.dump.mir {
+        let node_path = format!("{}", mir.fn_decl.ident);
+        let file_path = mir.config.dump_path(
+            mir.fn_decl.ident,
+            &format!("{}.{}.mar", pass_name, disambiguator));
+
+        mir.config.write_dump(&tcx, mir.span, &file_path, |file| {
+            writeln!(file, "// MIR for `{}`", node_path)?;
+            writeln!(file, "// pass_name = {}", pass_name)?;
+            writeln!(file, "// disambiguator = {}", disambiguator)?;
+            writeln!(file, "")?;
+            write_mir_fn(tcx, mir, file)?;
+            Ok(())
+        });
+    }
 
-    if let Some(ref file_dir) = env::var_os("STATEFUL_DUMP_MIR_DIR") {
-        let p = Path::new(file_dir);
-        file_path.push(p);
-    };
+    if mir.config.dump.json {
+        let file_path = mir.config.dump_path(
+            mir.fn_decl.ident,
+            &format!("{}.{}.json", pass_name, disambiguator));
 
-    let file_name = format!("stateful.{}.{}.{}.mar",
-                            node_path, pass_name, disambiguator);
-    file_path.push(&file_name);
-    let _ = fs::File::create(&file_path).and_then(|mut file| {
-        writeln!(file, "// MIR for `{}`", node_path)?;
-        writeln!(file, "// pass_name = {}", pass_name)?;
-        writeln!(file, "// disambiguator = {}", disambiguator)?;
-        writeln!(file, "")?;
-        write_mir_fn(tcx, mir, &mut file)?;
-        Ok(())
-    });
+        mir.config.write_dump(&tcx, mir.span, &file_path, |file| {
+            json::write_mir_json(tcx, mir, file)
+        });
+    }
 }
 
 /*
@@ -139,7 +126,13 @@ fn write_basic_block(tcx: TyCtxt,
     let data = &mir[block];
 
     // Basic block label at the top.
-    writeln!(w, "{}{:?}: {{", INDENT, block)?;
+    match (data.name, data.label) {
+        (Some(name), Some(label)) => {
+            writeln!(w, "{}{:?}: {{ // {} {}", INDENT, block, name, label)?
+        }
+        (Some(name), None) => writeln!(w, "{}{:?}: {{ // {}", INDENT, block, name)?,
+        (None, _) => writeln!(w, "{}{:?}: {{", INDENT, block)?,
+    }
 
     // List of statements in the middle.
     let mut current_location = Location { block: block, statement_index: 0 };
@@ -164,12 +157,16 @@ fn write_basic_block(tcx: TyCtxt,
 }
 
 fn comment(tcx: TyCtxt, SourceInfo { span, scope }: SourceInfo) -> String {
-    format!("scope {} at {}", scope.index(), tcx.sess.codemap().span_to_string(span))
+    // MIR parsed from text has no spans.
+    if span == DUMMY_SP {
+        format!("scope {}", scope.index())
+    } else {
+        format!("scope {} at {}", scope.index(), tcx.sess.codemap().span_to_string(span))
+    }
 }
 
-/// Prints user-defined variables in a scope tree.
-///
-/// Returns the total number of variables printed.
+/// Prints the arguments, variables and temporaries declared in `parent`, followed by its child
+/// scopes.
 fn write_scope_tree(tcx: TyCtxt,
                     mir: &Mir,
                     scope_tree: &HashMap<VisibilityScope, Vec<VisibilityScope>>,
@@ -178,54 +175,57 @@ fn write_scope_tree(tcx: TyCtxt,
                     depth: usize)
                     -> io::Result<()> {
     let indent = depth * INDENT.len();
+    let infer_ty = AstBuilder::new().ty().infer();
+
+    // Local types (including the user's name in a comment). The return pointer and the coroutine
+    // arguments were already printed by `write_mir_intro`.
+    for local in mir.local_decls.indices().skip(2) {
+        let var = &mir.local_decls[local];
+        if var.source_info.scope != parent {
+            continue;
+        }
+
+        let mut_str = if var.mutability == Mutability::Mutable {
+            "mut "
+        } else {
+            ""
+        };
+
+        let temp_str = if var.is_user_variable {
+            ""
+        } else {
+            "temp "
+        };
+
+        let indented_var = format!("{0:1$}let {2}{3:?}: {4};",
+                                   "",
+                                   indent,
+                                   mut_str,
+                                   local,
+                                   pprust::ty_to_string(
+                                       var.ty.as_ref().unwrap_or(&infer_ty)
+                                    ));
+        writeln!(w, "{0:1$} // {2}\"{3}\" in {4}",
+                 indented_var,
+                 ALIGN,
+                 temp_str,
+                 var.name,
+                 comment(tcx, var.source_info))?;
+    }
 
     let children = match scope_tree.get(&parent) {
         Some(childs) => childs,
         None => return Ok(()),
     };
 
-    let infer_ty = AstBuilder::new().ty().infer();
-
     for &child in children {
         let data = &mir.visibility_scopes[child];
         assert_eq!(data.parent_scope, Some(parent));
         writeln!(w, "{0:1$}scope {2} {{", "", indent, child.index())?;
 
-        // User variable types (including the user's name in a comment).
-        for local in mir.vars_iter() {
-            let var = &mir.local_decls[local];
-            let (name, source_info) = if var.source_info.scope == child {
-                (var.name, var.source_info)
-            } else {
-                // Not a variable or not declared in this scope.
-                continue;
-            };
-
-            let mut_str = if var.mutability == Mutability::Mutable {
-                "mut "
-            } else {
-                ""
-            };
-
-            let indent = indent + INDENT.len();
-            let indented_var = format!("{0:1$}let {2}{3:?}: {4};",
-                                       INDENT,
-                                       indent,
-                                       mut_str,
-                                       local,
-                                       pprust::ty_to_string(
-                                           var.ty.as_ref().unwrap_or(&infer_ty)
-                                        ));
-            writeln!(w, "{0:1$} // \"{2}\" in {3}",
-                     indented_var,
-                     ALIGN,
-                     name,
-                     comment(tcx, source_info))?;
-        }
-
         write_scope_tree(tcx, mir, scope_tree, w, child, depth + 1)?;
 
-        writeln!(w, "{0:1$}}}", "", depth * INDENT.len())?;
+        writeln!(w, "{0:1$}}}", "", indent)?;
     }
 
     Ok(())
@@ -238,7 +238,7 @@ fn write_mir_intro<'a, 'tcx>(tcx: TyCtxt<'a, 'tcx>,
                              w: &mut Write)
                              -> io::Result<()> {
     write_mir_sig(mir, w)?;
-    writeln!(w, " {{")?;
+    writeln!(w, "")?;
 
     // construct a scope tree and write it out
     let mut scope_tree: HashMap<VisibilityScope, Vec<VisibilityScope>> = HashMap::new();
@@ -280,21 +280,18 @@ fn write_mir_intro<'a, 'tcx>(tcx: TyCtxt<'a, 'tcx>,
     Ok(())
 }
 
+/// Write out the signature of the state machine function, followed by the kind of state machine
+/// in a comment.
 fn write_mir_sig(mir: &Mir, w: &mut Write)
                  -> io::Result<()>
 {
-    write!(w, "fn {}", mir.fn_decl.ident)?;
-    write!(w, "(")?;
-
-    // fn argument types.
-    for (i, arg) in mir.args_iter().enumerate() {
-        if i != 0 {
-            write!(w, ", ")?;
-        }
-        write!(w, "{:?}: {:?}", Lvalue::Local(arg), mir.local_decls[arg].ty)?;
-    }
+    let sig = pprust::fun_to_string(&mir.fn_decl.fn_decl,
+                                    mir.fn_decl.unsafety,
+                                    ast::Constness::NotConst,
+                                    mir.fn_decl.ident,
+                                    &mir.fn_decl.generics);
 
-    write!(w, ") -> {}", pprust::ty_to_string(&mir.fn_decl.return_ty()))
+    write!(w, "{} {{ // {}", sig, mir.state_machine_kind)
 }
 
 /*
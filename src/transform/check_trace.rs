@@ -0,0 +1,114 @@
+//! This hook runs the MIR through the interpreter around every pass, and reports the passes that
+//! change the trace of values the state machine suspends with and returns. It's only enabled for
+//! functions annotated with `interpret(...)`.
+
+use interpret::{self, Trace};
+use mir::Mir;
+use std::borrow::Cow;
+use super::{MirPassHook, Pass};
+use ty::TyCtxt;
+
+pub struct CheckTrace {
+    /// The trace of the MIR as it was before the first pass, once it has been computed.
+    expected: Option<Trace>,
+
+    /// Set once there's nothing left to check, because either the interpreter gave up on the
+    /// original MIR or a pass was already reported.
+    finished: bool,
+}
+
+impl CheckTrace {
+    pub fn new() -> Self {
+        CheckTrace {
+            expected: None,
+            finished: false,
+        }
+    }
+}
+
+impl MirPassHook for CheckTrace {
+    fn on_mir_pass<'a, 'tcx>(
+        &mut self,
+        tcx: TyCtxt<'a, 'tcx>,
+        mir: &Mir,
+        pass: &Pass,
+        is_after: bool)
+    {
+        if self.finished {
+            return;
+        }
+
+        let args = match mir.config.interpret {
+            Some(ref args) => args,
+            None => { return; }
+        };
+
+        // The MIR before a pass is the same as after the previous one, so only the first pass
+        // needs to be looked at beforehand.
+        if self.expected.is_some() && !is_after {
+            return;
+        }
+
+        let args = match args.iter().map(interpret::lit_value).collect::<Result<Vec<_>, _>>() {
+            Ok(args) => args,
+            Err(err) => {
+                tcx.span_err(err.span().unwrap_or(mir.span), &format!("cannot interpret: {}", err));
+                self.finished = true;
+                return;
+            }
+        };
+
+        let result = interpret::run(mir, args);
+
+        let expected = match self.expected.take() {
+            Some(expected) => expected,
+            None => {
+                match result {
+                    Ok(trace) => { self.expected = Some(trace); }
+                    Err(err) => {
+                        tcx.span_warn(
+                            err.span().unwrap_or(mir.span),
+                            &format!("cannot interpret `{}`: {}", mir.fn_decl.ident, err));
+                        self.finished = true;
+                    }
+                }
+
+                return;
+            }
+        };
+
+        match result {
+            Ok(ref trace) if *trace == expected => { }
+            Ok(trace) => {
+                tcx.span_err(
+                    mir.span,
+                    &format!("pass `{}` changed the behavior of `{}`: it now suspends with {:?} \
+                              and returns {:?}, instead of {:?} and {:?}",
+                             pass.name(),
+                             mir.fn_decl.ident,
+                             trace.suspends,
+                             trace.returned,
+                             expected.suspends,
+                             expected.returned));
+                self.finished = true;
+            }
+            Err(err) => {
+                tcx.span_err(
+                    err.span().unwrap_or(mir.span),
+                    &format!("cannot interpret `{}` after pass `{}`: {}",
+                             mir.fn_decl.ident,
+                             pass.name(),
+                             err));
+                self.finished = true;
+            }
+        }
+
+        self.expected = Some(expected);
+    }
+}
+
+impl Pass for CheckTrace {
+    fn name(&self) -> Cow<'static, str> {
+        Cow::from("CheckTrace")
+    }
+}
@@ -0,0 +1,268 @@
+//! Fold branches whose outcome is known at expansion time.
+//!
+//! Macros commonly expand to things like `if true { .. }`, `while false { .. }` or
+//! `match 1 { 1 => .., _ => .. }`. Left alone, every arm of such a branch survives into the
+//! state machine, and a dead `yield_!` in one of them still gets its own `ResumeState` variant.
+//! This pass looks at the condition of every `If` and the discriminant of every `Match`. When it
+//! is a literal, or a temporary that is assigned exactly once from a literal (possibly through
+//! `!`, `==` or `!=`), the branch is replaced with a jump to the arm that's always taken. Paths,
+//! such as a `const DEBUG: bool` used as a feature flag, aren't resolved at expansion time, so
+//! branches on them are left alone.
+//!
+//! Like rustc, we still type check the code in a constant-false branch. The arms that are never
+//! taken are kept as the `after_target` of a `Break`, just like the code following a `break`, so
+//! `SimplifyCfg` doesn't remove them. The relooper emits them under an `if false`, so they never
+//! become states, and a `yield_!` in them isn't a resume point. A `Match` is only folded if none
+//! of its arms have bindings or guards, since the bindings of an arm that's never taken would
+//! otherwise be used without ever being initialized.
+//!
+//! This pass should be followed by `SimplifyCfg`, which cleans up the chains of gotos left
+//! behind.
+
+use data_structures::indexed_vec::IndexVec;
+use mir::*;
+use mir::visit::{LvalueContext, Visitor};
+use std::borrow::Cow;
+use super::{MirPass, Pass};
+use syntax::ast::{self, BinOpKind, LitKind, PatKind};
+use ty::TyCtxt;
+
+pub struct ConstProp;
+
+impl Pass for ConstProp {
+    fn name(&self) -> Cow<'static, str> { "ConstProp".into() }
+}
+
+impl MirPass for ConstProp {
+    fn run_pass<'a, 'tcx>(&mut self, _tcx: TyCtxt<'a, 'tcx>, mir: &mut Mir) {
+        let folded = {
+            let constants = Constants::new(mir);
+
+            mir.basic_blocks().iter_enumerated()
+                .filter_map(|(block, data)| {
+                    constants.fold(&data.terminator().kind).map(|target| (block, target))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (block, target) in folded {
+            debug!("ConstProp: folding {:?} to a jump to {:?}", block, target);
+
+            let mut dead = vec![];
+            for successor in mir[block].terminator().successors() {
+                if successor != target && !dead.contains(&successor) {
+                    dead.push(successor);
+                }
+            }
+
+            let kind = keep_dead_blocks(mir, block, target, dead);
+            mir[block].terminator_mut().kind = kind;
+        }
+    }
+}
+
+/// A jump from `block` to `target` that still keeps every block in `dead` alive for type
+/// checking. A `Break` only has room for one `after_target`, so any others hang off a chain of
+/// new empty blocks, which are themselves only reachable through an `after_target`.
+fn keep_dead_blocks(mir: &mut Mir,
+                    block: BasicBlock,
+                    target: BasicBlock,
+                    mut dead: Vec<BasicBlock>) -> TerminatorKind {
+    let source_info = mir[block].terminator().source_info;
+    let span = mir[block].span;
+
+    let mut after_target = match dead.pop() {
+        Some(after_target) => after_target,
+        None => { return TerminatorKind::Goto { target: target }; }
+    };
+
+    while let Some(dead_target) = dead.pop() {
+        let mut data = BasicBlockData::new(span, Some("Unreachable"));
+        data.terminator = Some(Terminator {
+            source_info: source_info,
+            kind: TerminatorKind::Break {
+                target: dead_target,
+                after_target: after_target,
+            },
+        });
+        after_target = mir.basic_blocks_mut().push(data);
+    }
+
+    TerminatorKind::Break {
+        target: target,
+        after_target: after_target,
+    }
+}
+
+/// The temporaries that hold a single value for their whole lifetime.
+struct Constants<'a> {
+    mir: &'a Mir,
+    definitions: IndexVec<Local, Option<&'a Rvalue>>,
+}
+
+impl<'a> Constants<'a> {
+    fn new(mir: &'a Mir) -> Self {
+        let mut counter = AssignmentCounter {
+            assignments: IndexVec::from_elem_n(0, mir.local_decls.len()),
+        };
+        counter.visit_mir(mir);
+
+        let mut definitions = IndexVec::from_elem_n(None, mir.local_decls.len());
+
+        for data in mir.basic_blocks().iter() {
+            for statement in &data.statements {
+                if let StatementKind::Assign(Lvalue::Local(local), ref rvalue) = statement.kind {
+                    if mir.local_kind(local) == LocalKind::Temp &&
+                        counter.assignments[local] == 1
+                    {
+                        definitions[local] = Some(rvalue);
+                    }
+                }
+            }
+        }
+
+        Constants {
+            mir: mir,
+            definitions: definitions,
+        }
+    }
+
+    /// The block a terminator always branches to, if there is one.
+    fn fold(&self, kind: &TerminatorKind) -> Option<BasicBlock> {
+        match *kind {
+            TerminatorKind::If { ref cond, targets: (then, else_) } => {
+                match self.eval_operand(cond, 0) {
+                    Some(LitKind::Bool(true)) => Some(then),
+                    Some(LitKind::Bool(false)) => Some(else_),
+                    _ => None,
+                }
+            }
+            TerminatorKind::Match { ref discr, ref arms } => {
+                let value = match self.eval_operand(discr, 0) {
+                    Some(value) => value,
+                    None => { return None; }
+                };
+
+                // We can't drop the bindings and guards along with the match.
+                if arms.iter().any(|arm| arm.guard.is_some() || !arm.lvalues.is_empty()) {
+                    return None;
+                }
+
+                for arm in arms {
+                    match arm_matches(arm, &value) {
+                        Some(false) => { }
+                        Some(true) => { return Some(arm.block); }
+                        None => { return None; }
+                    }
+                }
+
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn eval_operand(&self, operand: &Operand, depth: usize) -> Option<LitKind> {
+        match *operand {
+            Operand::Constant(ref constant) => Some(constant.literal.node.clone()),
+            Operand::Consume(Lvalue::Local(local), _) |
+            Operand::Copy(Lvalue::Local(local), _) => {
+                // The builder never assigns a temporary in terms of itself, but don't loop
+                // forever if some later pass does.
+                if depth > self.mir.local_decls.len() {
+                    return None;
+                }
+
+                self.definitions[local].and_then(|rvalue| self.eval_rvalue(rvalue, depth + 1))
+            }
+            _ => None,
+        }
+    }
+
+    fn eval_rvalue(&self, rvalue: &Rvalue, depth: usize) -> Option<LitKind> {
+        match *rvalue {
+            Rvalue::Use(ref operand) => self.eval_operand(operand, depth),
+            Rvalue::UnaryOp(ast::UnOp::Not, ref operand) => {
+                match self.eval_operand(operand, depth) {
+                    Some(LitKind::Bool(value)) => Some(LitKind::Bool(!value)),
+                    _ => None,
+                }
+            }
+            Rvalue::BinaryOp(op, ref lhs, ref rhs) => {
+                let lhs = match self.eval_operand(lhs, depth) {
+                    Some(lhs) => lhs,
+                    None => { return None; }
+                };
+                let rhs = match self.eval_operand(rhs, depth) {
+                    Some(rhs) => rhs,
+                    None => { return None; }
+                };
+
+                match op.node {
+                    BinOpKind::Eq => lit_eq(&lhs, &rhs).map(LitKind::Bool),
+                    BinOpKind::Ne => lit_eq(&lhs, &rhs).map(|eq| LitKind::Bool(!eq)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Whether an arm matches a constant discriminant, or `None` if we can't tell.
+fn arm_matches(arm: &Arm, value: &LitKind) -> Option<bool> {
+    let mut result = Some(false);
+
+    for pat in &arm.pats {
+        match pat_matches(pat, value) {
+            Some(true) => { return Some(true); }
+            Some(false) => { }
+            None => { result = None; }
+        }
+    }
+
+    result
+}
+
+fn pat_matches(pat: &ast::Pat, value: &LitKind) -> Option<bool> {
+    match pat.node {
+        PatKind::Wild => Some(true),
+        PatKind::Lit(ref expr) => {
+            match expr.node {
+                ast::ExprKind::Lit(ref lit) => lit_eq(&lit.node, value),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Compare two literals, or `None` if they aren't comparable without knowing their types.
+fn lit_eq(lhs: &LitKind, rhs: &LitKind) -> Option<bool> {
+    match (lhs, rhs) {
+        (&LitKind::Bool(lhs), &LitKind::Bool(rhs)) => Some(lhs == rhs),
+        // The suffixes are either the same or a type error that rustc will report for us.
+        (&LitKind::Int(lhs, _), &LitKind::Int(rhs, _)) => Some(lhs == rhs),
+        (&LitKind::Char(lhs), &LitKind::Char(rhs)) => Some(lhs == rhs),
+        (&LitKind::Byte(lhs), &LitKind::Byte(rhs)) => Some(lhs == rhs),
+        (&LitKind::Str(lhs, _), &LitKind::Str(rhs, _)) => Some(lhs == rhs),
+        _ => None,
+    }
+}
+
+/// Count how often each local is written to or mutably borrowed.
+struct AssignmentCounter {
+    assignments: IndexVec<Local, usize>,
+}
+
+impl Visitor for AssignmentCounter {
+    fn visit_lvalue(&mut self, lvalue: &Lvalue, ctx: LvalueContext, loc: Location) {
+        if ctx.is_mutating_use() {
+            if let Lvalue::Local(local) = *lvalue {
+                self.assignments[local] += 1;
+            }
+        }
+
+        self.super_lvalue(lvalue, ctx, loc);
+    }
+}
@@ -0,0 +1,48 @@
+//! This pass dumps a graphviz rendering of the MIR at a specified point.
+
+use graphviz;
+use mir::Mir;
+use std::borrow::Cow;
+use super::{MirPassHook, Pass};
+use super::dump_mir::Disambiguator;
+use ty::TyCtxt;
+
+pub struct DumpGraphviz;
+
+impl MirPassHook for DumpGraphviz {
+    fn on_mir_pass<'a, 'tcx>(
+        &mut self,
+        tcx: TyCtxt<'a, 'tcx>,
+        mir: &Mir,
+        pass: &Pass,
+        is_after: bool)
+    {
+        if !mir.config.dump.graphviz {
+            return;
+        }
+
+        let pass_name = pass.name();
+        let disambiguator = Disambiguator {
+            pass: pass,
+            is_after: is_after,
+        }.to_string();
+
+        if !mir.config.dump_pass(&pass_name, &disambiguator) {
+            return;
+        }
+
+        let file_path = mir.config.dump_path(
+            mir.fn_decl.ident,
+            &format!("{}.{}.dot", pass_name, disambiguator));
+
+        mir.config.write_dump(&tcx, mir.span, &file_path, |file| {
+            graphviz::write_mir_graphviz(tcx, mir, file)
+        });
+    }
+}
+
+impl Pass for DumpGraphviz {
+    fn name(&self) -> Cow<'static, str> {
+        Cow::from("DumpGraphviz")
+    }
+}
@@ -18,8 +18,8 @@ use super::{MirPassHook, Pass};
 use ty::TyCtxt;
 
 pub struct Disambiguator<'a> {
-    pass: &'a Pass,
-    is_after: bool
+    pub pass: &'a Pass,
+    pub is_after: bool
 }
 
 impl<'a> fmt::Display for Disambiguator<'a> {
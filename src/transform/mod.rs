@@ -10,11 +10,15 @@
 
 use mir::Mir;
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fmt;
 use ty::TyCtxt;
 
+pub mod check_trace;
+pub mod const_prop;
+pub mod dump_graphviz;
 pub mod dump_mir;
-//pub mod simplify;
+pub mod simplify;
 pub mod validate;
 
 /// Various information about pass.
@@ -83,6 +87,11 @@ impl Passes {
                                 tcx: TyCtxt<'a, 'tcx>,
                                 mir: &mut Mir) {
         for pass in &mut self.passes {
+            if !mir.config.pass_enabled(&pass.name()) {
+                debug!("run_passes: skipping disabled pass {:?}", pass.name());
+                continue;
+            }
+
             pass.run_pass(tcx, mir, &mut self.pass_hooks);
         }
     }
@@ -94,6 +103,61 @@ impl Passes {
     pub fn push_hook(&mut self, hook: Box<MirPassHook>) {
         self.pass_hooks.push(hook);
     }
+
+    /// Add a fresh instance of every pass registered with `register_mir_pass`.
+    pub fn push_registered_passes(&mut self) {
+        REGISTERED_PASSES.with(|factories| {
+            for factory in factories.borrow().iter() {
+                self.passes.push(factory());
+            }
+        });
+    }
+}
+
+thread_local! {
+    static REGISTERED_PASSES: RefCell<Vec<Box<Fn() -> Box<MirMapPass>>>> = RefCell::new(vec![]);
+}
+
+/// The names of the built-in passes, as accepted by `disable_passes(...)`.
+const BUILTIN_PASSES: &'static [&'static str] = &[
+    "NopPass",
+    "SimplifyCfg",
+    "ConstProp",
+    "SimplifyLocals",
+];
+
+/// Register a pass from another plugin crate. The registered passes run in order of registration
+/// on every state machine, after the built-in optimizations and right before it is translated back
+/// into Rust. `factory` is called once for every state machine, so the pass is free to keep
+/// per-function state.
+///
+/// The registry lives in the copy of this library that does the expansion, so calling this from
+/// a crate that only does `extern crate stateful` has no effect. Instead, write a plugin whose
+/// `#[plugin_registrar]` calls `stateful::plugin_registrar` and then registers its passes, and
+/// load that plugin in place of `#![plugin(stateful)]`:
+///
+/// ```rust,ignore
+/// #[plugin_registrar]
+/// pub fn plugin_registrar(registry: &mut Registry) {
+///     stateful::plugin_registrar(registry);
+///     stateful::transform::register_mir_pass(|| MyPass);
+/// }
+/// ```
+pub fn register_mir_pass<F, P>(factory: F)
+    where F: Fn() -> P + 'static,
+          P: MirPass + 'static,
+{
+    REGISTERED_PASSES.with(|factories| {
+        factories.borrow_mut().push(Box::new(move || Box::new(factory()) as Box<MirMapPass>));
+    });
+}
+
+/// Is there a built-in or registered pass with this name?
+pub fn is_known_pass(name: &str) -> bool {
+    BUILTIN_PASSES.contains(&name) ||
+        REGISTERED_PASSES.with(|factories| {
+            factories.borrow().iter().any(|factory| factory().name() == name)
+        })
 }
 
 /// A pass that does nothing.
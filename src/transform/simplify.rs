@@ -22,6 +22,13 @@
 //! also be run before any analysis passes because it removes dead blocks, and some of these can be
 //! ill-typed.
 //!
+//! Stateful's MIR has two kinds of edges that must survive simplification. The target of a
+//! `Suspend` is where the state machine resumes, and so it becomes a `ResumeState` variant that
+//! must not be collapsed into some other block. The `after_target` of a `Break` is never jumped
+//! to, but dataflow relies on it to initialize the code following the break. Neither kind of
+//! edge is ever redirected, and a block is only merged into its predecessor when it has a single
+//! `goto` predecessor and is not a resume block.
+//!
 //! The cause of this typing issue is typeck allowing most blocks whose end is not reachable have
 //! an arbitrary return type, rather than having the usual () return type (as a note, typeck's
 //! notion of reachability is in fact slightly weaker than MIR CFG reachability - see #31617). A
@@ -40,9 +47,10 @@
 use data_structures::bitvec::BitVector;
 use data_structures::indexed_vec::{Idx, IndexVec};
 use mir::*;
+use mir::visit::{MutVisitor, Visitor, LvalueContext};
 use std::borrow::Cow;
+use std::collections::BTreeSet;
 use std::fmt;
-//use mir::visit::{MutVisitor, Visitor, LvalueContext};
 use super::{MirPass, Pass};
 use traversal;
 use ty::TyCtxt;
@@ -77,7 +85,8 @@ impl<'l> Pass for SimplifyCfg<'l> {
 
 pub struct CfgSimplifier<'a> {
     basic_blocks: &'a mut IndexVec<BasicBlock, BasicBlockData>,
-    pred_count: IndexVec<BasicBlock, u32>
+    pred_count: IndexVec<BasicBlock, u32>,
+    resume_blocks: BTreeSet<BasicBlock>,
 }
 
 impl<'a> CfgSimplifier<'a> {
@@ -96,11 +105,13 @@ impl<'a> CfgSimplifier<'a> {
             }
         }
 
+        let resume_blocks = mir.resume_blocks();
         let basic_blocks = mir.basic_blocks_mut();
 
         CfgSimplifier {
             basic_blocks: basic_blocks,
-            pred_count: pred_count
+            pred_count: pred_count,
+            resume_blocks: resume_blocks,
         }
     }
 
@@ -118,7 +129,7 @@ impl<'a> CfgSimplifier<'a> {
                 let mut terminator = self.basic_blocks[bb].terminator.take()
                     .expect("invalid terminator state");
 
-                for successor in terminator.successors_mut() {
+                for successor in collapsible_successors_mut(&mut terminator) {
                     self.collapse_goto_chain(successor, &mut changed);
                 }
 
@@ -127,7 +138,7 @@ impl<'a> CfgSimplifier<'a> {
                 while inner_changed {
                     inner_changed = false;
                     inner_changed |= self.simplify_branch(&mut terminator);
-                    //inner_changed |= self.merge_successor(&mut new_stmts, &mut terminator);
+                    inner_changed |= self.merge_successor(&mut new_stmts, &mut terminator);
                     changed |= inner_changed;
                 }
 
@@ -144,14 +155,16 @@ impl<'a> CfgSimplifier<'a> {
     // Collapse a goto chain starting from `start`
     fn collapse_goto_chain(&mut self, start: &mut BasicBlock, changed: &mut bool) {
         let mut terminator = match self.basic_blocks[*start] {
+            // The labelled states of a `#[state_machine]` keep their block, even when empty.
             BasicBlockData {
                 ref statements,
+                ref label,
                 terminator: ref mut terminator @ Some(Terminator {
                     kind: TerminatorKind::Goto { target: _ },
                     ..
                 }),
                 ..
-            } if statements.is_empty() => terminator.take(),
+            } if statements.is_empty() && label.is_none() => terminator.take(),
             // if `terminator` is None, this means we are in a loop. In that
             // case, let all the loop collapse to its entry.
             _ => return
@@ -193,6 +206,13 @@ impl<'a> CfgSimplifier<'a> {
             _ => return false
         };
 
+        // Resume blocks are entered from the outside of the state machine, so they have to stay
+        // around even if their only other predecessor is this `goto`. The same goes for the
+        // labelled states, which the user expects to see as states.
+        if self.resume_blocks.contains(&target) || self.basic_blocks[target].label.is_some() {
+            return false
+        }
+
         debug!("merging block {:?} into {:?}", target, terminator);
         *terminator = match self.basic_blocks[target].terminator.take() {
             Some(terminator) => terminator,
@@ -211,8 +231,14 @@ impl<'a> CfgSimplifier<'a> {
     // turn a branch with all successors identical to a goto
     fn simplify_branch(&mut self, terminator: &mut Terminator) -> bool {
         match terminator.kind {
-            TerminatorKind::If { .. } |
-            TerminatorKind::Match { .. } => {},
+            TerminatorKind::If { .. } => {},
+            // Arms with bindings or guards do more than pick a successor, so we can only drop the
+            // match if none of its arms do anything else.
+            TerminatorKind::Match { ref arms, .. } => {
+                if arms.iter().any(|arm| !arm.lvalues.is_empty() || arm.guard.is_some()) {
+                    return false
+                }
+            }
             _ => return false
         };
 
@@ -236,18 +262,40 @@ impl<'a> CfgSimplifier<'a> {
     }
 }
 
+/// The successors of a terminator that can be redirected to another block. This excludes the
+/// resume target of a `Suspend` and the `after_target` of a `Break`.
+fn collapsible_successors_mut(terminator: &mut Terminator) -> Vec<&mut BasicBlock> {
+    match terminator.kind {
+        TerminatorKind::Goto { ref mut target } |
+        TerminatorKind::Break { ref mut target, .. } => vec![target],
+        TerminatorKind::If { targets: (ref mut then, ref mut else_), .. } => vec![then, else_],
+        TerminatorKind::Match { ref mut arms, .. } => {
+            arms.iter_mut().map(|arm| &mut arm.block).collect()
+        }
+        TerminatorKind::Suspend { .. } |
+        TerminatorKind::Return => vec![],
+    }
+}
+
 fn remove_dead_blocks(mir: &mut Mir) {
     let mut seen = BitVector::new(mir.basic_blocks().len());
     for (bb, _) in traversal::preorder(mir) {
         seen.insert(bb.index());
     }
 
+    retain_blocks(mir, &seen);
+}
+
+/// Remove every block that isn't in `live`, renumbering the remaining blocks and the edges
+/// between them. All the successors of the live blocks, including the `after_target` of a break,
+/// must themselves be live.
+pub fn retain_blocks(mir: &mut Mir, live: &BitVector) {
     let basic_blocks = mir.basic_blocks_mut();
 
     let num_blocks = basic_blocks.len();
     let mut replacements : Vec<_> = (0..num_blocks).map(BasicBlock::new).collect();
     let mut used_blocks = 0;
-    for alive_index in seen.iter() {
+    for alive_index in live.iter() {
         replacements[alive_index] = BasicBlock::new(used_blocks);
         if alive_index != used_blocks {
             // Swap the next alive block data with the current available slot. Since alive_index is
@@ -265,22 +313,30 @@ fn remove_dead_blocks(mir: &mut Mir) {
     }
 }
 
-/*
+/// Remove the temporaries that are no longer used after the other passes ran.
+///
+/// User variables are never removed, even if the MIR doesn't mention them anymore, since they
+/// may still be referred to by name in the opaque AST fragments we carry along, like the
+/// `StatementKind::Stmt` statements and match guards.
 pub struct SimplifyLocals;
 
 impl Pass for SimplifyLocals {
-    fn name(&self) -> ::std::borrow::Cow<'static, str> { "SimplifyLocals".into() }
+    fn name(&self) -> Cow<'static, str> { "SimplifyLocals".into() }
 }
 
 impl MirPass for SimplifyLocals {
-    fn run_pass<'a, 'tcx>(&mut self, _: TyCtxt<'a, 'tcx>, mir: &mut Mir) {
+    fn run_pass<'a, 'tcx>(&mut self, _tcx: TyCtxt<'a, 'tcx>, mir: &mut Mir) {
         let mut marker = DeclMarker { locals: BitVector::new(mir.local_decls.len()) };
         marker.visit_mir(mir);
-        // Return pointer and arguments are always live
-        marker.locals.insert(0);
-        for idx in mir.args_iter() {
-            marker.locals.insert(idx.index());
+
+        // Everything but the temporaries is always live. This also keeps the return pointer, the
+        // coroutine arguments and the function arguments at their fixed indices.
+        for local in mir.local_decls.indices() {
+            if mir.local_kind(local) != LocalKind::Temp {
+                marker.locals.insert(local.index());
+            }
         }
+
         let map = make_local_map(&mut mir.local_decls, marker.locals);
         // Update references to all vars and tmps now
         LocalUpdater { map: map }.visit_mir(mir);
@@ -289,7 +345,7 @@ impl MirPass for SimplifyLocals {
 }
 
 /// Construct the mapping while swapping out unused stuff out from the `vec`.
-fn make_local_map<'tcx, I: Idx, V>(vec: &mut IndexVec<I, V>, mask: BitVector) -> Vec<usize> {
+fn make_local_map<I: Idx, V>(vec: &mut IndexVec<I, V>, mask: BitVector) -> Vec<usize> {
     let mut map: Vec<usize> = ::std::iter::repeat(!0).take(vec.len()).collect();
     let mut used = 0;
     for alive_index in mask.iter() {
@@ -309,7 +365,7 @@ struct DeclMarker {
 
 impl Visitor for DeclMarker {
     fn visit_lvalue(&mut self, lval: &Lvalue, ctx: LvalueContext, loc: Location) {
-        if ctx == LvalueContext::StorageLive || ctx == LvalueContext::StorageDead {
+        if ctx.is_storage_marker() {
             // ignore these altogether, they get removed along with their otherwise unused decls.
             return;
         }
@@ -340,12 +396,11 @@ impl MutVisitor for LocalUpdater {
         });
         self.super_basic_block_data(block, data);
     }
+
     fn visit_lvalue(&mut self, lval: &mut Lvalue, ctx: LvalueContext, loc: Location) {
-        match *lval {
-            Lvalue::Local(ref mut l) => *l = Local::new(self.map[l.index()]),
-            _ => (),
-        };
+        if let Lvalue::Local(ref mut l) = *lval {
+            *l = Local::new(self.map[l.index()]);
+        }
         self.super_lvalue(lval, ctx, loc);
     }
 }
-*/
@@ -8,12 +8,37 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! This pass just dumps MIR at a specified point.
+//! This hook checks the structural invariants of the MIR around every pass, so that a pass that
+//! produces malformed MIR is caught right away instead of as an ICE in the translator. It checks
+//! that:
+//!
+//! * every block has a terminator, and every edge points at a block that exists,
+//! * the builder's `End` block ends in `Return`, and no other block returns,
+//! * every `Suspend` resumes with the `COROUTINE_ARGS` local,
+//! * the visibility scopes form a tree rooted at `ARGUMENT_VISIBILITY_SCOPE`, and every source
+//!   info refers to one of them,
+//! * every `Lvalue::Local` refers to a declared local,
+//! * the storage markers are consistent along every path: a local is only used when its storage
+//!   is live on every path leading there, and isn't killed with `StorageDead` when its storage is
+//!   dead on every path leading there.
+//!
+//! Locals without any `StorageLive` are considered live everywhere, which covers the arguments,
+//! the return pointer and match bindings. The builder happily marks a local live again in every
+//! iteration of a loop, and only ends the storage of the scopes it leaves normally, so a local
+//! may still be live where a `break` or `return` ends up. Neither is an error, and so a local may
+//! also be killed when it is only live on some of the paths. The `after_target` of a `Break` is
+//! not followed, since that code is never reached.
+//!
+//! All the violations are reported together, each pointing at the offending block, and then
+//! compilation is aborted, since the translator would only choke on the malformed MIR.
 
 use data_structures::indexed_vec::Idx;
-use mir::Mir;
+use mir::*;
+use mir::visit::{LvalueContext, Visitor};
 use std::borrow::Cow;
+use std::collections::BTreeSet;
 use super::{MirPassHook, Pass};
+use syntax::codemap::Span;
 use ty::TyCtxt;
 
 pub struct Validate;
@@ -23,30 +48,412 @@ impl MirPassHook for Validate {
         &mut self,
         tcx: TyCtxt<'a, 'tcx>,
         mir: &Mir,
-        _pass: &Pass,
-        _is_after: bool)
+        pass: &Pass,
+        is_after: bool)
     {
-        let basic_blocks = mir.basic_blocks();
-        for (bb, block) in basic_blocks.iter_enumerated() {
-            let terminator = block.terminator();
+        let violations = validate(mir);
 
-            for succ in terminator.successors() {
-                if succ.index() >= basic_blocks.len() {
-                    span_bug!(
-                        &tcx,
-                        mir.span,
-                        "block {:?} terminator does not exist: {:?} len: {:?}",
-                        bb,
-                        terminator.kind,
-                        basic_blocks.len());
-                }
-            }
+        if violations.is_empty() {
+            return;
+        }
+
+        let when = if is_after { "after" } else { "before" };
+
+        for violation in &violations {
+            let message = match violation.block {
+                Some(block) => format!("{:?}: {}", block, violation.message),
+                None => violation.message.clone(),
+            };
+
+            tcx.span_err(
+                violation.span,
+                &format!("invalid MIR {} pass `{}`: {}", when, pass.name(), message));
         }
+
+        tcx.sess.span_diagnostic.abort_if_errors();
     }
 }
 
 impl<'b> Pass for Validate {
-    fn name(&self) -> Cow<'static, str> { 
+    fn name(&self) -> Cow<'static, str> {
         Cow::from("Validate")
     }
 }
+
+pub struct Violation {
+    pub block: Option<BasicBlock>,
+    pub span: Span,
+    pub message: String,
+}
+
+/// Check the MIR, returning every invariant it violates.
+pub fn validate(mir: &Mir) -> Vec<Violation> {
+    let mut validator = Validator {
+        mir: mir,
+        violations: vec![],
+    };
+
+    validator.check_scopes();
+    validator.check_locals();
+
+    // The remaining checks walk the CFG, which only makes sense if it is well formed.
+    if validator.check_terminators() {
+        validator.check_suspends();
+        validator.check_storage();
+    }
+
+    validator.violations
+}
+
+struct Validator<'a> {
+    mir: &'a Mir,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, block: Option<BasicBlock>, span: Span, message: String) {
+        self.violations.push(Violation {
+            block: block,
+            span: span,
+            message: message,
+        });
+    }
+
+    /// The span of the statement or terminator at `location`.
+    fn span(&self, location: Location) -> Span {
+        let data = &self.mir[location.block];
+
+        match data.statements.get(location.statement_index) {
+            Some(statement) => statement.source_info.span,
+            None => {
+                match data.terminator {
+                    Some(ref terminator) => terminator.source_info.span,
+                    None => data.span,
+                }
+            }
+        }
+    }
+
+    /// Returns true if the CFG itself is well formed.
+    fn check_terminators(&mut self) -> bool {
+        let mir = self.mir;
+        let num_blocks = mir.basic_blocks().len();
+        let mut valid = true;
+        let mut return_blocks = vec![];
+
+        for (block, data) in mir.basic_blocks().iter_enumerated() {
+            let terminator = match data.terminator {
+                Some(ref terminator) => terminator,
+                None => {
+                    self.report(Some(block), data.span, "block has no terminator".to_string());
+                    valid = false;
+                    continue;
+                }
+            };
+
+            for target in terminator.successors() {
+                if target.index() >= num_blocks {
+                    self.report(
+                        Some(block),
+                        terminator.source_info.span,
+                        format!("terminator `{:?}` jumps to {:?}, but there are only {} blocks",
+                                terminator.kind,
+                                target,
+                                num_blocks));
+                    valid = false;
+                }
+            }
+
+            match terminator.kind {
+                TerminatorKind::Return => {
+                    return_blocks.push(block);
+                }
+                // Every `return` ends up in the `End` block, so it can't go anywhere else.
+                ref kind if data.name == Some("End") => {
+                    self.report(
+                        Some(block),
+                        terminator.source_info.span,
+                        format!("the end block must `return`, found `{:?}`", kind));
+                }
+                _ => { }
+            }
+        }
+
+        if return_blocks.len() > 1 {
+            for &block in &return_blocks[1..] {
+                let span = mir[block].terminator().source_info.span;
+                self.report(
+                    Some(block),
+                    span,
+                    format!("block returns, but {:?} is already the end block",
+                            return_blocks[0]));
+            }
+        }
+
+        valid
+    }
+
+    fn check_suspends(&mut self) {
+        let mir = self.mir;
+
+        for (block, data) in mir.basic_blocks().iter_enumerated() {
+            let terminator = data.terminator();
+
+            if let TerminatorKind::Suspend { destination: (ref lvalue, _), .. } = terminator.kind {
+                if *lvalue != Lvalue::Local(COROUTINE_ARGS) {
+                    self.report(
+                        Some(block),
+                        terminator.source_info.span,
+                        format!("suspend resumes into {:?} instead of {:?}",
+                                lvalue,
+                                COROUTINE_ARGS));
+                }
+            }
+        }
+    }
+
+    fn check_scopes(&mut self) {
+        let mir = self.mir;
+
+        for (scope, data) in mir.visibility_scopes.iter_enumerated() {
+            match data.parent_scope {
+                None if scope == ARGUMENT_VISIBILITY_SCOPE => { }
+                None => {
+                    self.report(None, data.span, format!("{:?} has no parent scope", scope));
+                }
+                // Parents are always created before their children, which also rules out cycles.
+                Some(parent) if parent.index() >= scope.index() => {
+                    self.report(
+                        None,
+                        data.span,
+                        format!("{:?} has parent {:?}, which isn't an earlier scope",
+                                scope,
+                                parent));
+                }
+                Some(_) => { }
+            }
+        }
+
+        for (local, decl) in mir.local_decls.iter_enumerated() {
+            if decl.source_info.scope.index() >= mir.visibility_scopes.len() {
+                self.report(
+                    None,
+                    decl.source_info.span,
+                    format!("{:?} is declared in unknown scope {:?}",
+                            local,
+                            decl.source_info.scope));
+            }
+        }
+
+        for (block, data) in mir.basic_blocks().iter_enumerated() {
+            let source_infos = data.statements.iter()
+                .map(|statement| &statement.source_info)
+                .chain(data.terminator.as_ref().map(|terminator| &terminator.source_info));
+
+            for source_info in source_infos {
+                if source_info.scope.index() >= mir.visibility_scopes.len() {
+                    self.report(
+                        Some(block),
+                        source_info.span,
+                        format!("refers to unknown scope {:?}", source_info.scope));
+                }
+            }
+        }
+    }
+
+    fn check_locals(&mut self) {
+        let mir = self.mir;
+        let mut collector = LocalCollector { uses: vec![] };
+        collector.visit_mir(mir);
+
+        for (local, _, location) in collector.uses {
+            if local.index() >= mir.local_decls.len() {
+                let span = self.span(location);
+                self.report(
+                    Some(location.block),
+                    span,
+                    format!("{:?} is not declared, there are only {} locals",
+                            local,
+                            mir.local_decls.len()));
+            }
+        }
+    }
+
+    fn check_storage(&mut self) {
+        let mir = self.mir;
+
+        // Only the locals that get a `StorageLive` somewhere have a storage lifetime at all.
+        let mut tracked = BTreeSet::new();
+        for data in mir.basic_blocks().iter() {
+            for statement in &data.statements {
+                if let StatementKind::StorageLive(Lvalue::Local(local)) = statement.kind {
+                    tracked.insert(local);
+                }
+            }
+        }
+
+        if tracked.is_empty() {
+            return;
+        }
+
+        // Compute which locals may be live, and which are live on every path, on entry to each
+        // block.
+        let mut entry_sets = vec![None; mir.basic_blocks().len()];
+        entry_sets[START_BLOCK.index()] = Some(Storage::default());
+
+        let mut worklist = vec![START_BLOCK];
+
+        while let Some(block) = worklist.pop() {
+            let mut storage = entry_sets[block.index()].clone().unwrap();
+            let data = &mir[block];
+
+            for (index, statement) in data.statements.iter().enumerate() {
+                let location = Location { block: block, statement_index: index };
+                self.storage_effect(&tracked, &mut storage, statement, location, false);
+            }
+
+            for target in storage_successors(data.terminator()) {
+                let entry = &mut entry_sets[target.index()];
+
+                let changed = match *entry {
+                    Some(ref mut entry) => entry.join(&storage),
+                    None => {
+                        *entry = Some(storage.clone());
+                        true
+                    }
+                };
+
+                if changed {
+                    worklist.push(target);
+                }
+            }
+        }
+
+        // Now that the sets are stable, report the violations.
+        for (block, data) in mir.basic_blocks().iter_enumerated() {
+            let mut storage = match entry_sets[block.index()] {
+                Some(ref storage) => storage.clone(),
+                None => { continue; }
+            };
+
+            for (index, statement) in data.statements.iter().enumerate() {
+                let location = Location { block: block, statement_index: index };
+                self.storage_effect(&tracked, &mut storage, statement, location, true);
+            }
+
+            let location = Location { block: block, statement_index: data.statements.len() };
+            let mut collector = LocalCollector { uses: vec![] };
+            collector.visit_terminator(block, data.terminator(), location);
+            self.check_uses(&tracked, &storage, collector.uses);
+        }
+    }
+
+    fn storage_effect(&mut self,
+                      tracked: &BTreeSet<Local>,
+                      storage: &mut Storage,
+                      statement: &Statement,
+                      location: Location,
+                      report: bool) {
+        match statement.kind {
+            StatementKind::StorageLive(Lvalue::Local(local)) => {
+                storage.maybe_live.insert(local);
+                storage.live.insert(local);
+            }
+            StatementKind::StorageDead(Lvalue::Local(local)) => {
+                if report && tracked.contains(&local) && !storage.maybe_live.contains(&local) {
+                    self.report(
+                        Some(location.block),
+                        statement.source_info.span,
+                        format!("`StorageDead({:?})` of a local that isn't live on any path",
+                                local));
+                }
+
+                storage.maybe_live.remove(&local);
+                storage.live.remove(&local);
+            }
+            _ => {
+                if report {
+                    let mut collector = LocalCollector { uses: vec![] };
+                    collector.visit_statement(location.block, statement, location);
+                    self.check_uses(tracked, storage, collector.uses);
+                }
+            }
+        }
+    }
+
+    fn check_uses(&mut self,
+                  tracked: &BTreeSet<Local>,
+                  storage: &Storage,
+                  uses: Vec<(Local, LvalueContext, Location)>) {
+        for (local, ctx, location) in uses {
+            if ctx.is_storage_marker() || !tracked.contains(&local) ||
+                storage.live.contains(&local)
+            {
+                continue;
+            }
+
+            let paths = if storage.maybe_live.contains(&local) {
+                "some paths"
+            } else {
+                "every path"
+            };
+            let span = self.span(location);
+            self.report(
+                Some(location.block),
+                span,
+                format!("{:?} is used as {:?} while its storage is dead on {}",
+                        local,
+                        ctx,
+                        paths));
+        }
+    }
+}
+
+/// The storage of the tracked locals on entry to a block.
+#[derive(Clone, Default)]
+struct Storage {
+    /// The locals whose storage is live on some path.
+    maybe_live: BTreeSet<Local>,
+
+    /// The locals whose storage is live on every path.
+    live: BTreeSet<Local>,
+}
+
+impl Storage {
+    /// Merge in the storage along another incoming edge, returning true if anything changed.
+    fn join(&mut self, other: &Storage) -> bool {
+        let maybe_live_len = self.maybe_live.len();
+        let live_len = self.live.len();
+
+        // `maybe_live` only ever grows and `live` only ever shrinks, so comparing the sizes is
+        // enough to tell if either changed.
+        self.maybe_live.extend(other.maybe_live.iter().cloned());
+        self.live = self.live.intersection(&other.live).cloned().collect();
+
+        self.maybe_live.len() != maybe_live_len || self.live.len() != live_len
+    }
+}
+
+/// The edges the storage of locals flows along. This leaves out the `after_target` of a break,
+/// since the break already ended the storage of the scopes it exits.
+fn storage_successors(terminator: &Terminator) -> Vec<BasicBlock> {
+    match terminator.kind {
+        TerminatorKind::Break { target, .. } => vec![target],
+        ref kind => kind.successors(),
+    }
+}
+
+/// Collect every mention of a local.
+struct LocalCollector {
+    uses: Vec<(Local, LvalueContext, Location)>,
+}
+
+impl Visitor for LocalCollector {
+    fn visit_lvalue(&mut self, lvalue: &Lvalue, ctx: LvalueContext, loc: Location) {
+        if let Lvalue::Local(local) = *lvalue {
+            self.uses.push((local, ctx, loc));
+        }
+
+        self.super_lvalue(lvalue, ctx, loc);
+    }
+}
@@ -182,7 +182,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     }
                     ScopeStatement::Terminator(terminator) => {
                         terminated = true;
-                        self.terminator(local_stack, terminator)
+                        self.terminator(block, local_stack, terminator)
                     }
                 }
             })
@@ -210,7 +210,8 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         stmts
     }
 
-    fn terminator(&self,
+    fn terminator(&mut self,
+                  block: BasicBlock,
                   local_stack: &mut LocalStack,
                   terminator: &Terminator) -> Vec<ast::Stmt> {
         let span = terminator.source_info.span;
@@ -218,24 +219,28 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         match terminator.kind {
             TerminatorKind::Goto { target } => {
-                self.goto(span, target, local_stack)
+                self.jump(span, block, target, local_stack)
             }
-            TerminatorKind::Break { target, after_target: _ } => {
-                self.goto(span, target, local_stack)
+            TerminatorKind::Break { target, after_target } => {
+                let mut stmts = self.dead_code(span, block, after_target, local_stack);
+                stmts.extend(self.jump(span, block, target, local_stack));
+                stmts
             }
             TerminatorKind::If { ref cond, targets: (then_block, else_block) } => {
-                let cond = cond.to_expr(&self.mir.local_decls);
+                let cond = cond.to_expr(span, &self.mir.local_decls);
 
+                let then_stmts = self.jump(span, block, then_block, local_stack);
                 let then_block = ast_builder
                     .span(self.block_span(then_block))
                     .block()
-                    .with_stmts(self.goto(span, then_block, local_stack))
+                    .with_stmts(then_stmts)
                     .build();
 
+                let else_stmts = self.jump(span, block, else_block, local_stack);
                 let else_block = ast_builder
                     .span(self.block_span(else_block))
                     .block()
-                    .with_stmts(self.goto(span, else_block, local_stack))
+                    .with_stmts(else_stmts)
                     .build();
 
                 vec![
@@ -246,7 +251,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 ]
             }
             TerminatorKind::Match { ref discr, ref arms } => {
-                let discr = discr.to_expr(&self.mir.local_decls);
+                let discr = discr.to_expr(span, &self.mir.local_decls);
 
                 let arms = arms.iter()
                     .map(|arm| {
@@ -259,20 +264,20 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                                 }
                             }
 
-                            (true, self.goto(span, arm.block, local_stack))
+                            (true, self.jump(span, block, arm.block, local_stack))
                         });
 
                         let ast_builder = ast_builder.span(self.block_span(arm.block));
-                        let block = ast_builder.block()
-                            .span(self.mir.span)
+                        let body = ast_builder.block()
                             .with_stmts(stmts)
                             .build();
 
                         ast_builder.arm()
                             .with_pats(arm.pats.iter().cloned())
                             .with_guard(arm.guard.clone())
-                            .body().build_block(block)
-                    });
+                            .body().build_block(body)
+                    })
+                    .collect::<Vec<_>>();
 
                 vec![
                     ast_builder.stmt().expr().match_()
@@ -283,12 +288,12 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             }
             TerminatorKind::Return => {
                 let next_state = ast_builder.expr().path()
-                    .span(self.mir.span)
-                    .ids(&["ResumeState", "Illegal"])
+                    .span(self.glue_span)
+                    .ids(&["ResumeState", "Done"])
                     .build();
 
                 match self.mir.state_machine_kind {
-                    StateMachineKind::Generator => {
+                    StateMachineKind::Generator | StateMachineKind::StateMachine => {
                         vec![
                             /*
                             // generate `let () = return_;` to make sure it's been assigned the
@@ -298,36 +303,92 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                                 .expr().id("return_"),
                                 */
                             ast_builder.stmt().semi().return_expr().tuple()
-                                .expr().none()
+                                .expr().path()
+                                    .global()
+                                    .id(self.mir.config.std_crate())
+                                    .ids(&["option", "Option", "None"])
+                                    .build()
                                 .expr().build(next_state)
                                 .build()
                         ]
                     }
-                    StateMachineKind::Async => {
-                        let return_expr = ast_builder.expr().id("return_");
-                        let ready_expr = ast_builder.expr().call()
+                    StateMachineKind::PushParser => {
+                        // The return pointer holds the parsed value, and how many bytes at the end
+                        // of the last chunk it didn't use.
+                        let return_expr = Lvalue::Local(RETURN_POINTER)
+                            .to_expr(span, &self.mir.local_decls);
+
+                        let return_expr = ast_builder.expr().call()
                             .path()
                                 .global()
-                                .ids(&["futures", "Async", "Ready"])
+                                .id(self.mir.config.std_crate())
+                                .ids(&["option", "Option", "Some"])
                                 .build()
                             .with_arg(return_expr)
                             .build();
 
                         vec![
-                            ast_builder.stmt().semi().return_expr().ok().tuple()
-                                .expr().build(ready_expr)
+                            ast_builder.stmt().semi().return_expr().tuple()
+                                .expr().build(return_expr)
+                                .expr().build(next_state)
+                                .build()
+                        ]
+                    }
+                    StateMachineKind::Actor => {
+                        // The return pointer holds the last reply, if the actor made one.
+                        let return_expr = Lvalue::Local(RETURN_POINTER)
+                            .to_expr(span, &self.mir.local_decls);
+
+                        vec![
+                            ast_builder.stmt().semi().return_expr().tuple()
+                                .expr().build(return_expr)
                                 .expr().build(next_state)
                                 .build()
                         ]
                     }
+                    StateMachineKind::Async => {
+                        // The return pointer holds the `Result` of the whole future. Either way the
+                        // future is done, so the error carries the next state just like the item.
+                        let return_expr = Lvalue::Local(RETURN_POINTER)
+                            .to_expr(span, &self.mir.local_decls);
+
+                        let std = self.mir.config.std_crate();
+                        let expr = quote_expr!(self.cx,
+                            match $return_expr {
+                                ::$std::result::Result::Ok(item) => {
+                                    return ::$std::result::Result::Ok(
+                                        (::futures::Async::Ready(item), $next_state));
+                                }
+                                ::$std::result::Result::Err(err) => {
+                                    return ::$std::result::Result::Err((err, $next_state));
+                                }
+                            }
+                        );
+
+                        vec![
+                            ast_builder.stmt().expr().build(expr)
+                        ]
+                    }
                 }
             }
             TerminatorKind::Suspend {
                 destination: (_, target),
                 ref arg,
             } => {
-                let arg = arg.to_expr(&self.mir.local_decls);
-                let next_state = self.resume_state_expr(target, local_stack);
+                let is_live = self.relooper.is_live(block);
+
+                // A suspend in dead code isn't a resume point. The value it would suspend with is
+                // still type checked by returning it under an `if false`, and then the dead code
+                // runs on into the target.
+                let arg = arg.to_expr(span, &self.mir.local_decls);
+                let next_state = if is_live {
+                    self.resume_state_expr(target, local_stack)
+                } else {
+                    ast_builder.expr().path()
+                        .span(self.glue_span)
+                        .ids(&["ResumeState", "Done"])
+                        .build()
+                };
 
                 let ast_builder = ast_builder.span(arg.span);
                 let expr = ast_builder.expr().tuple()
@@ -336,33 +397,55 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     .build();
 
                 let expr = match self.mir.state_machine_kind {
-                    StateMachineKind::Generator => expr,
-                    StateMachineKind::Async => ast_builder.expr().ok().build(expr),
+                    StateMachineKind::Generator |
+                    StateMachineKind::StateMachine |
+                    StateMachineKind::PushParser |
+                    StateMachineKind::Actor => expr,
+                    StateMachineKind::Async => {
+                        ast_builder.expr().call()
+                            .path()
+                                .global()
+                                .id(self.mir.config.std_crate())
+                                .ids(&["result", "Result", "Ok"])
+                                .build()
+                            .with_arg(expr)
+                            .build()
+                    }
                 };
 
-                vec![
-                    ast_builder.stmt().semi().return_expr()
-                        .build(expr)
-                ]
+                let return_stmt = ast_builder.stmt().semi().return_expr()
+                    .build(expr);
+
+                if is_live {
+                    vec![return_stmt]
+                } else {
+                    self.dead_suspend(span, block, target, return_stmt, local_stack)
+                }
             }
         }
     }
 
-    fn goto(&self, span: Span,
-            target: BasicBlock,
-            local_stack: &LocalStack) -> Vec<ast::Stmt> {
-        let next_state = self.internal_state_expr(target, local_stack);
+    /// Translate a suspend in dead code, which returns `return_stmt` under an `if false` and then
+    /// runs on into `target`. The target may read the coroutine arguments, which live code
+    /// could have already moved out of, so they are shadowed by a value that is never created.
+    /// Its type is inferred from how the target uses it, and is `()` if it isn't used at all.
+    fn dead_suspend(&mut self,
+                    span: Span,
+                    block: BasicBlock,
+                    target: BasicBlock,
+                    return_stmt: ast::Stmt,
+                    local_stack: &mut LocalStack) -> Vec<ast::Stmt> {
+        let coroutine_args = local_stack.get_name(COROUTINE_ARGS)
+            .expect("coroutine args are always named");
+
+        let mut stmts = vec![
+            self.if_false(span, vec![return_stmt]),
+            quote_stmt!(self.cx, let $coroutine_args = (|| unreachable!())();)
+                .expect("coroutine args stmt"),
+        ];
 
-        let ast_builder = self.ast_builder.span(span);
-        let next_expr = ast_builder.expr()
-            .assign()
-            .id("state")
-            .build(next_state);
-
-        vec![
-            ast_builder.stmt().semi().build(next_expr),
-            ast_builder.stmt().semi().continue_(),
-        ]
+        stmts.extend(self.jump(span, block, target, local_stack));
+        stmts
     }
 }
 
@@ -3,11 +3,14 @@ use aster::AstBuilder;
 use mir::*;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use super::internal_state::InternalState;
+use super::relooper::Relooper;
 use super::resume_state::ResumeState;
 use syntax::ast;
 use syntax::codemap::Span;
 use syntax::ext::base::ExtCtxt;
+use syntax::fold::{self, Folder};
 use syntax::ptr::P;
+use syntax_pos::ExpnId;
 use ty::TyCtxt;
 
 type ScopeLocals = HashMap<BasicBlock, BTreeMap<VisibilityScope, Vec<Local>>>;
@@ -17,11 +20,19 @@ pub struct Builder<'a, 'b: 'a> {
     pub cx: &'a ExtCtxt<'b>,
     pub ast_builder: AstBuilder,
     pub mir: &'a Mir,
+
+    /// The span for code that only the plugin wrote, like the driver impls and the jumps between
+    /// states. It is marked as coming from the expansion of the state machine attribute.
+    pub glue_span: Span,
+
     pub assignments: &'a DefiniteAssignment,
 
     /// All the blocks that are the target of a resume.
     pub resume_blocks: BTreeSet<BasicBlock>,
 
+    /// Which blocks are states, and how to structure the others.
+    pub relooper: Relooper,
+
     /// A map of basic blocks to their locals, grouped by scope.
     pub scope_locals: ScopeLocals,
 
@@ -34,13 +45,16 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                mir: &'a Mir,
                assignments: &'a DefiniteAssignment) -> Self {
         let scope_locals = group_locals_by_scope(mir, assignments);
+        let glue_span = Span { expn_id: tcx.backtrace(), .. mir.span };
 
         Builder {
             cx: &tcx,
-            ast_builder: AstBuilder::new().span(mir.span),
+            ast_builder: AstBuilder::new().span(glue_span),
             mir: mir,
+            glue_span: glue_span,
             assignments: assignments,
-            resume_blocks: find_resume_blocks(mir),
+            resume_blocks: mir.resume_blocks(),
+            relooper: Relooper::new(mir),
             scope_locals: scope_locals,
             scope_paths: compute_scope_paths(mir),
         }
@@ -49,8 +63,9 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
     pub fn state_machine(&mut self) -> P<ast::Block> {
         let start_state_expr = self.start_state_expr();
 
-        let state_machine_impl = self.state_machine_impl();
-        let state_machine_impl_driver = self.state_machine_impl_driver();
+        let state_machine_struct = self.mark_glue_item(self.state_machine_struct());
+        let state_machine_impl = self.mark_glue_item(self.state_machine_impl());
+        let state_machine_impl_driver = self.mark_glue_item(self.state_machine_impl_driver());
 
         let ResumeState {
             stmts: resume_state_stmts,
@@ -62,10 +77,29 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             expr: internal_state_expr,
         } = self.internal_state();
 
+        // The state enums hold the declared types of the user's locals, which keep their own spans,
+        // so that errors in them point at the `let` they came from. Only the rest of the enums
+        // is marked as glue.
+        let resume_state_stmts = resume_state_stmts.into_iter()
+            .map(|stmt| self.mark_glue_stmt(stmt))
+            .collect::<Vec<_>>();
+        let internal_state_stmts = internal_state_stmts.into_iter()
+            .map(|stmt| self.mark_glue_stmt(stmt))
+            .collect::<Vec<_>>();
+
         let expr = quote_expr!(self.cx,
             StateMachine::new($start_state_expr, resume)
         );
 
+        // Wrap the state machine in one of the `std::io` adapters if it was asked for.
+        let expr = if self.mir.config.read {
+            quote_expr!(self.cx, ::stateful_runtime::ChunkReader::new($expr))
+        } else if self.mir.config.write {
+            quote_expr!(self.cx, ::stateful_runtime::ParserWriter::new($expr))
+        } else {
+            expr
+        };
+
         // If we're not using impl trait, we need to wrap the closure in a box.
         let expr = match self.mir.fn_decl.return_ty().node {
             ast::TyKind::ImplTrait(_) => expr,
@@ -73,11 +107,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         };
 
         quote_block!(self.cx, {
-            struct StateMachine<S, F> {
-                state: S,
-                resume: F,
-            }
-
+            $state_machine_struct
             $state_machine_impl
             $state_machine_impl_driver
 
@@ -93,13 +123,55 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         })
     }
 
+    fn state_machine_struct(&self) -> P<ast::Item> {
+        let std = self.mir.config.std_crate();
+
+        match self.mir.state_machine_kind {
+            // A push parser runs up to its first `needs_input!()` when it is created, since the
+            // start state never gets any input. If it finishes right away, the value is kept
+            // until the first `feed`, which doesn't consume anything.
+            StateMachineKind::PushParser => {
+                quote_item!(self.cx,
+                    struct StateMachine<S, F, Item> {
+                        state: S,
+                        resume: F,
+                        done: ::$std::option::Option<Item>,
+                    }
+                ).unwrap()
+            }
+            // The message type only shows up in the arguments of the resume closure, so it needs a
+            // marker for the `Actor` impl to name it. Any reply made before the first `recv!()`
+            // is kept until it is taken with `greeting`.
+            StateMachineKind::Actor => {
+                quote_item!(self.cx,
+                    struct StateMachine<S, F, Message, Reply> {
+                        state: S,
+                        resume: F,
+                        greeting: ::$std::option::Option<Reply>,
+                        marker: ::$std::marker::PhantomData<fn(Message)>,
+                    }
+                ).unwrap()
+            }
+            _ => {
+                quote_item!(self.cx,
+                    struct StateMachine<S, F> {
+                        state: S,
+                        resume: F,
+                    }
+                ).unwrap()
+            }
+        }
+    }
+
     fn state_machine_impl(&self) -> P<ast::Item> {
+        let std = self.mir.config.std_crate();
+
         match self.mir.state_machine_kind {
-            StateMachineKind::Generator => {
+            StateMachineKind::Generator | StateMachineKind::StateMachine => {
                 quote_item!(self.cx,
                     impl<S, F, Item> StateMachine<S, F>
-                        where S: ::std::default::Default,
-                              F: Fn(S, ()) -> (::std::option::Option<Item>, S),
+                        where S: ::$std::default::Default,
+                              F: Fn(S, ()) -> (::$std::option::Option<Item>, S),
                     {
                         fn new(initial_state: S, resume: F) -> Self {
                             StateMachine {
@@ -113,11 +185,11 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             StateMachineKind::Async => {
                 quote_item!(self.cx,
                     impl<S, F, Item, Error> StateMachine<S, F>
-                        where S: ::std::default::Default,
+                        where S: ::$std::default::Default,
                               F: Fn(S, ())
-                              -> ::std::result::Result<(::futures::Async<Item>, S), Error>,
+                              -> ::$std::result::Result<(::futures::Async<Item>, S), (Error, S)>,
                     {
-                        fn new(initial_state: S, next: F) -> Self {
+                        fn new(initial_state: S, resume: F) -> Self {
                             StateMachine {
                                 state: initial_state,
                                 resume: resume,
@@ -126,21 +198,64 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                     }
                 ).unwrap()
             }
+            StateMachineKind::PushParser => {
+                quote_item!(self.cx,
+                    impl<S, F, Item> StateMachine<S, F, Item>
+                        where S: ::$std::default::Default,
+                              F: Fn(S, ::$std::vec::Vec<u8>)
+                              -> (::$std::option::Option<(Item, usize)>, S),
+                    {
+                        fn new(initial_state: S, resume: F) -> Self {
+                            let (done, state) = resume(initial_state, ::$std::vec::Vec::new());
+
+                            StateMachine {
+                                state: state,
+                                resume: resume,
+                                done: done.map(|(item, _)| item),
+                            }
+                        }
+                    }
+                ).unwrap()
+            }
+            // Like a push parser, an actor runs up to its first `recv!()` when it is created.
+            StateMachineKind::Actor => {
+                quote_item!(self.cx,
+                    impl<S, F, Message, Reply> StateMachine<S, F, Message, Reply>
+                        where S: ::$std::default::Default,
+                              F: Fn(S, ::$std::option::Option<Message>)
+                              -> (::$std::option::Option<Reply>, S),
+                    {
+                        fn new(initial_state: S, resume: F) -> Self {
+                            let (greeting, state) =
+                                resume(initial_state, ::$std::option::Option::None);
+
+                            StateMachine {
+                                state: state,
+                                resume: resume,
+                                greeting: greeting,
+                                marker: ::$std::marker::PhantomData,
+                            }
+                        }
+                    }
+                ).unwrap()
+            }
         }
     }
 
     fn state_machine_impl_driver(&self) -> P<ast::Item> {
+        let std = self.mir.config.std_crate();
+
         match self.mir.state_machine_kind {
-            StateMachineKind::Generator => {
+            StateMachineKind::Generator | StateMachineKind::StateMachine => {
                 quote_item!(self.cx,
-                    impl<S, F, Item> ::std::iter::Iterator for StateMachine<S, F>
-                        where S: ::std::default::Default,
-                              F: Fn(S, ()) -> (::std::option::Option<Item>, S)
+                    impl<S, F, Item> ::$std::iter::Iterator for StateMachine<S, F>
+                        where S: ::$std::default::Default,
+                              F: Fn(S, ()) -> (::$std::option::Option<Item>, S)
                     {
                         type Item = Item;
 
-                        fn next(&mut self) -> ::std::option::Option<Item> {
-                            let state = ::std::mem::replace(&mut self.state, S::default());
+                        fn next(&mut self) -> ::$std::option::Option<Item> {
+                            let state = ::$std::mem::replace(&mut self.state, S::default());
                             let (value, state) = (self.resume)(state, ());
                             self.state = state;
                             value
@@ -151,18 +266,84 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             StateMachineKind::Async => {
                 quote_item!(self.cx,
                     impl<S, F, Item, Error> ::futures::Future for StateMachine<S, F>
-                        where S: ::std::default::Default,
+                        where S: ::$std::default::Default,
                               F: Fn(S, ())
-                              -> ::std::result::Result<(::futures::Async<Item>, S), Error>,
+                              -> ::$std::result::Result<(::futures::Async<Item>, S), (Error, S)>,
                     {
                         type Item = Item;
                         type Error = Error;
 
                         fn poll(&mut self) -> ::futures::Poll<Item, Error> {
-                            let state = ::std::mem::replace(&mut self.state, S::default());
-                            let (value, state) = try!((self.resume)(state, ()));
+                            let state = ::$std::mem::replace(&mut self.state, S::default());
+
+                            match (self.resume)(state, ()) {
+                                ::$std::result::Result::Ok((value, state)) => {
+                                    self.state = state;
+                                    ::$std::result::Result::Ok(value)
+                                }
+                                ::$std::result::Result::Err((err, state)) => {
+                                    self.state = state;
+                                    ::$std::result::Result::Err(err)
+                                }
+                            }
+                        }
+                    }
+                ).unwrap()
+            }
+            StateMachineKind::PushParser => {
+                quote_item!(self.cx,
+                    impl<S, F, Item> ::stateful_runtime::PushParser for StateMachine<S, F, Item>
+                        where S: ::$std::default::Default,
+                              F: Fn(S, ::$std::vec::Vec<u8>)
+                              -> (::$std::option::Option<(Item, usize)>, S),
+                    {
+                        type Output = Item;
+
+                        fn feed(&mut self, input: &[u8]) -> ::stateful_runtime::Progress<Item> {
+                            // A parser that finished when it was created didn't use any input.
+                            if let ::$std::option::Option::Some(item) = self.done.take() {
+                                return ::stateful_runtime::Progress::Done(item, 0);
+                            }
+
+                            let state = ::$std::mem::replace(&mut self.state, S::default());
+                            let (value, state) = (self.resume)(state, input.to_vec());
                             self.state = state;
-                            Ok(value)
+
+                            match value {
+                                ::$std::option::Option::Some((item, unconsumed)) => {
+                                    let unconsumed = ::$std::cmp::min(unconsumed, input.len());
+                                    let consumed = input.len() - unconsumed;
+                                    ::stateful_runtime::Progress::Done(item, consumed)
+                                }
+                                ::$std::option::Option::None => {
+                                    ::stateful_runtime::Progress::NeedsInput
+                                }
+                            }
+                        }
+                    }
+                ).unwrap()
+            }
+            StateMachineKind::Actor => {
+                quote_item!(self.cx,
+                    impl<S, F, Message, Reply> ::stateful_runtime::Actor
+                        for StateMachine<S, F, Message, Reply>
+                        where S: ::$std::default::Default,
+                              F: Fn(S, ::$std::option::Option<Message>)
+                              -> (::$std::option::Option<Reply>, S),
+                    {
+                        type Message = Message;
+                        type Reply = Reply;
+
+                        fn greeting(&mut self) -> ::$std::option::Option<Reply> {
+                            self.greeting.take()
+                        }
+
+                        fn handle(&mut self, msg: Message) -> ::$std::option::Option<Reply> {
+                            let state = ::$std::mem::replace(&mut self.state, S::default());
+                            let (reply, state) =
+                                (self.resume)(state, ::$std::option::Option::Some(msg));
+                            self.state = state;
+                            reply
                         }
                     }
                 ).unwrap()
@@ -174,28 +355,45 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
     pub fn block_span(&self, block: BasicBlock) -> Span {
         self.mir[block].span
     }
+
+    /// Mark all the spans in an item as coming from the expansion of the state machine attribute.
+    /// The spans still point at the same code, but rustc reports errors in them with a note
+    /// about the expansion, and lints that skip macro generated code skip them.
+    fn mark_glue_item(&self, item: P<ast::Item>) -> P<ast::Item> {
+        GlueMarker { expn_id: self.glue_span.expn_id, keep_tys: false }
+            .fold_item(item)
+            .expect_one("expected one item")
+    }
+
+    /// Like `mark_glue_item`, but the types in the statement keep their spans.
+    fn mark_glue_stmt(&self, stmt: ast::Stmt) -> ast::Stmt {
+        GlueMarker { expn_id: self.glue_span.expn_id, keep_tys: true }
+            .fold_stmt(stmt)
+            .expect_one("expected one statement")
+    }
 }
 
-/// Find all the blocks that could be resumed into.
-///
-/// In order properly lift any resume arguments into the state machine, we need to first
-/// identify all the blocks that can be resumed into.
-fn find_resume_blocks(mir: &Mir) -> BTreeSet<BasicBlock> {
-    mir.basic_blocks().iter()
-        .filter_map(|block_data| {
-            if let Some(ref terminator) = block_data.terminator {
-                match terminator.kind {
-                    TerminatorKind::Suspend { destination: (_, block), .. } => {
-                        Some(block)
-                    }
-                    _ => None
-                }
-            } else {
-                None
-            }
-        })
-        .chain(Some(START_BLOCK))
-        .collect()
+struct GlueMarker {
+    expn_id: ExpnId,
+    keep_tys: bool,
+}
+
+impl Folder for GlueMarker {
+    fn new_span(&mut self, span: Span) -> Span {
+        Span { expn_id: self.expn_id, .. span }
+    }
+
+    fn fold_ty(&mut self, ty: P<ast::Ty>) -> P<ast::Ty> {
+        if self.keep_tys {
+            ty
+        } else {
+            fold::noop_fold_ty(ty, self)
+        }
+    }
+
+    fn fold_mac(&mut self, mac: ast::Mac) -> ast::Mac {
+        fold::noop_fold_mac(mac, self)
+    }
 }
 
 fn group_locals_by_scope(mir: &Mir, assignments: &DefiniteAssignment) -> ScopeLocals {
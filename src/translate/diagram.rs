@@ -0,0 +1,158 @@
+//! Render the generated state machine as a diagram. Every `InternalState` variant becomes a node
+//! listing the locals it saves, `ResumeState` variants become the entry points that the driver
+//! resumes into, and the edges are the gotos and suspends between them. Blocks that were
+//! structured into the code of a state are folded into that state's node.
+
+use data_structures::indexed_vec::Idx;
+use graphviz::{escape_dot, escape_mermaid};
+use mir::*;
+use std::io::{self, Write};
+use super::builder::Builder;
+
+impl<'a, 'b: 'a> Builder<'a, 'b> {
+    /// Write the state machine as a graphviz DOT graph.
+    pub fn write_state_diagram_dot(&self, w: &mut Write) -> io::Result<()> {
+        writeln!(w, "digraph StateMachine {{")?;
+        writeln!(w, r#"    graph [fontname="monospace"];"#)?;
+        writeln!(w, r#"    node [fontname="monospace", shape="box"];"#)?;
+        writeln!(w, r#"    edge [fontname="monospace"];"#)?;
+
+        for &block in &self.resume_blocks {
+            writeln!(w, r#"    resume{} [label="ResumeState::{}", peripheries="2"];"#,
+                     block.index(),
+                     self.state_id(block))?;
+            writeln!(w, r#"    resume{} -> internal{} [label="resume"];"#,
+                     block.index(),
+                     block.index())?;
+        }
+
+        writeln!(w, r#"    done [label="ResumeState::Done", shape="doublecircle"];"#)?;
+
+        for &block in self.relooper.states() {
+            // `\l` ends a left-justified line, so it is added after escaping the names.
+            let mut label = escape_dot(&format!("InternalState::{}", self.state_id(block)));
+            for name in self.saved_local_names(block) {
+                label.push_str("\\l  ");
+                label.push_str(&escape_dot(&name));
+            }
+            label.push_str("\\l");
+
+            writeln!(w, r#"    internal{} [label="{}"];"#, block.index(), label)?;
+        }
+
+        for (source, edge) in self.state_edges() {
+            let (target, label, style) = match edge {
+                StateEdge::Goto(target, label) => {
+                    (format!("internal{}", target.index()), label, "solid")
+                }
+                StateEdge::Suspend(target, label) => {
+                    (format!("resume{}", target.index()), label, "dashed")
+                }
+                StateEdge::Return => ("done".to_string(), "return".to_string(), "solid"),
+            };
+
+            writeln!(w, r#"    internal{} -> {} [label="{}", style="{}"];"#,
+                     source.index(),
+                     target,
+                     escape_dot(&label),
+                     style)?;
+        }
+
+        writeln!(w, "}}")
+    }
+
+    /// Write the state machine as a Mermaid `stateDiagram`.
+    pub fn write_state_diagram_mermaid(&self, w: &mut Write) -> io::Result<()> {
+        writeln!(w, "stateDiagram")?;
+
+        for &block in &self.resume_blocks {
+            writeln!(w, "    resume{} : ResumeState::{}", block.index(), self.state_id(block))?;
+            writeln!(w, "    resume{} --> internal{} : resume", block.index(), block.index())?;
+        }
+
+        writeln!(w, "    [*] --> resume{}", START_BLOCK.index())?;
+
+        for &block in self.relooper.states() {
+            writeln!(w, "    internal{} : InternalState::{}", block.index(), self.state_id(block))?;
+
+            for name in self.saved_local_names(block) {
+                writeln!(w, "    internal{} : {}", block.index(), escape_mermaid(&name))?;
+            }
+        }
+
+        for (source, edge) in self.state_edges() {
+            match edge {
+                StateEdge::Goto(target, label) => {
+                    writeln!(w, "    internal{} --> internal{} : goto {}",
+                             source.index(), target.index(), escape_mermaid(&label))?;
+                }
+                StateEdge::Suspend(target, label) => {
+                    writeln!(w, "    internal{} --> resume{} : {}",
+                             source.index(), target.index(), escape_mermaid(&label))?;
+                }
+                StateEdge::Return => {
+                    writeln!(w, "    internal{} --> [*] : return", source.index())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The locals that are saved in the state for a block, like `x: _3`.
+    fn saved_local_names(&self, block: BasicBlock) -> Vec<String> {
+        self.scope_locals[&block].values()
+            .flat_map(|locals| locals)
+            .map(|&local| format!("{}: {:?}", self.mir.local_decls[local].name, local))
+            .collect()
+    }
+
+    /// Collect the transitions out of every state.
+    fn state_edges(&self) -> Vec<(BasicBlock, StateEdge)> {
+        let mut edges = vec![];
+
+        for (block, data) in self.mir.basic_blocks().iter_enumerated() {
+            // Dead code never runs, so it doesn't move the state machine anywhere.
+            if !self.relooper.is_live(block) {
+                continue;
+            }
+
+            let source = self.relooper.owner(block);
+            let kind = &data.terminator().kind;
+
+            match *kind {
+                TerminatorKind::Return => {
+                    edges.push((source, StateEdge::Return));
+                }
+                TerminatorKind::Suspend { destination: (_, target), ref arg } => {
+                    let label = format!("suspend({:?})", arg);
+                    edges.push((source, StateEdge::Suspend(target, label)));
+                }
+                // The `after_target` of a break is only there for dataflow, it's never jumped
+                // to.
+                TerminatorKind::Break { target, .. } => {
+                    if self.relooper.is_state(target) {
+                        edges.push((source, StateEdge::Goto(target, String::new())));
+                    }
+                }
+                _ => {
+                    for (target, label) in kind.successors().into_iter()
+                        .zip(kind.fmt_successor_labels())
+                    {
+                        if self.relooper.is_state(target) {
+                            edges.push((source, StateEdge::Goto(target, label.into_owned())));
+                        }
+                    }
+                }
+            }
+        }
+
+        edges
+    }
+}
+
+enum StateEdge {
+    Goto(BasicBlock, String),
+    Suspend(BasicBlock, String),
+    Return,
+}
@@ -0,0 +1,179 @@
+//! The state enums only carry the generics of the function that the types of their saved locals
+//! refer to. An `enum` with a type or lifetime parameter that none of its variants use is an
+//! error, and the extra parameters also make the types in error messages much harder to read.
+//!
+//! A parameter that is only mentioned by the bounds of another parameter, as `U` is in
+//! `T: Into<U>`, still needs to be declared for the bound to make sense, so those parameters are
+//! held by an extra `Phantom(PhantomData<fn() -> (...)>)` variant that is never constructed. The
+//! parameters sit in a function's return type, so that the state is still `Send`, `Sync` and
+//! `UnwindSafe` whatever they are, since nothing of their types is ever stored.
+
+use std::collections::HashSet;
+use super::builder::Builder;
+use syntax::ast;
+use syntax::ptr::P;
+use syntax::visit::{self, Visitor};
+
+pub struct StateGenerics {
+    /// The generics of the function that the state enum needs.
+    pub generics: ast::Generics,
+
+    /// The `Phantom` variant, if some of the generics are only used by bounds.
+    pub phantom_variant: Option<ast::Variant>,
+}
+
+impl<'a, 'b: 'a> Builder<'a, 'b> {
+    /// Find the generics of the function that are needed by a state enum with these variants.
+    pub fn state_generics(&self, variants: &[ast::Variant]) -> StateGenerics {
+        let generics = &self.mir.fn_decl.generics;
+
+        let mut used = Names::default();
+        for variant in variants {
+            for field in variant.node.data.fields() {
+                used.visit_ty(&field.ty);
+            }
+        }
+        let used = used.0;
+
+        // Pull in everything the bounds of the needed generics refer to, until nothing changes.
+        let mut needed = used.clone();
+
+        loop {
+            let mut names = Names::default();
+
+            for lifetime_def in &generics.lifetimes {
+                if needed.contains(&lifetime_def.lifetime.name) {
+                    for bound in &lifetime_def.bounds {
+                        names.visit_lifetime(bound);
+                    }
+                }
+            }
+
+            for ty_param in generics.ty_params.iter() {
+                if needed.contains(&ty_param.ident.name) {
+                    for bound in ty_param.bounds.iter() {
+                        names.visit_ty_param_bound(bound);
+                    }
+                }
+            }
+
+            for predicate in &generics.where_clause.predicates {
+                if is_predicate_needed(predicate, generics, &needed) {
+                    names.visit_where_predicate(predicate);
+                }
+            }
+
+            let len = needed.len();
+            needed.extend(names.0);
+
+            if needed.len() == len {
+                break;
+            }
+        }
+
+        let mut state_generics = generics.clone();
+        state_generics.lifetimes.retain(|lifetime_def| {
+            needed.contains(&lifetime_def.lifetime.name)
+        });
+        state_generics.ty_params = P::from_vec(
+            generics.ty_params.iter()
+                .filter(|ty_param| needed.contains(&ty_param.ident.name))
+                .cloned()
+                .collect()
+        );
+        state_generics.where_clause.predicates.retain(|predicate| {
+            is_predicate_needed(predicate, generics, &needed)
+        });
+
+        StateGenerics {
+            phantom_variant: self.phantom_variant(&state_generics, &used),
+            generics: state_generics,
+        }
+    }
+
+    /// Build a `Phantom(PhantomData<fn() -> (&'a (), U)>)` variant for the generics that are not
+    /// used by any of the other variants.
+    fn phantom_variant(&self,
+                       generics: &ast::Generics,
+                       used: &HashSet<ast::Name>) -> Option<ast::Variant> {
+        let ast_builder = self.ast_builder.span(generics.span);
+
+        let lifetime_tys = generics.lifetimes.iter()
+            .filter(|lifetime_def| !used.contains(&lifetime_def.lifetime.name))
+            .map(|lifetime_def| {
+                ast_builder.ty().ref_()
+                    .lifetime(lifetime_def.lifetime.name)
+                    .ty().unit()
+            });
+
+        let ty_param_tys = generics.ty_params.iter()
+            .filter(|ty_param| !used.contains(&ty_param.ident.name))
+            .map(|ty_param| ast_builder.ty().id(ty_param.ident));
+
+        let tys = lifetime_tys.chain(ty_param_tys).collect::<Vec<_>>();
+
+        if tys.is_empty() {
+            return None;
+        }
+
+        let tuple_ty = ast_builder.ty().tuple().with_tys(tys).build();
+        let fn_ty = quote_ty!(self.cx, fn() -> $tuple_ty);
+
+        let phantom_ty = ast_builder.ty().path()
+            .global()
+            .id(self.mir.config.std_crate())
+            .id("marker")
+            .segment("PhantomData")
+                .with_ty(fn_ty)
+                .build()
+            .build();
+
+        Some(ast_builder.variant("Phantom").tuple().ty().build(phantom_ty).build())
+    }
+}
+
+/// Whether a where clause predicate mentions one of the needed generics, either in the bounded
+/// type, as in `T: Clone`, or in the bounds, as in `Foo: Into<T>`.
+fn is_predicate_needed(predicate: &ast::WherePredicate,
+                       generics: &ast::Generics,
+                       needed: &HashSet<ast::Name>) -> bool {
+    match *predicate {
+        ast::WherePredicate::BoundPredicate(..) |
+        ast::WherePredicate::RegionPredicate(..) => {
+            let mut names = Names::default();
+            names.visit_where_predicate(predicate);
+
+            names.0.iter().any(|name| {
+                needed.contains(name) && is_generic_param(generics, *name)
+            })
+        }
+        ast::WherePredicate::EqPredicate(..) => false,
+    }
+}
+
+/// Whether a name is one of the lifetime or type parameters of the function. The needed names
+/// also hold the first segment of every path in the saved locals' types, such as `Vec`, which
+/// shouldn't pull in every predicate that mentions them.
+fn is_generic_param(generics: &ast::Generics, name: ast::Name) -> bool {
+    generics.lifetimes.iter().any(|lifetime_def| lifetime_def.lifetime.name == name) ||
+        generics.ty_params.iter().any(|ty_param| ty_param.ident.name == name)
+}
+
+/// Collects the lifetimes and the first segment of the paths mentioned by a piece of syntax,
+/// which includes the names of all the generic parameters it refers to.
+#[derive(Default)]
+struct Names(HashSet<ast::Name>);
+
+impl<'a> Visitor<'a> for Names {
+    fn visit_lifetime(&mut self, lifetime: &'a ast::Lifetime) {
+        self.0.insert(lifetime.name);
+    }
+
+    fn visit_path(&mut self, path: &'a ast::Path, _id: ast::NodeId) {
+        if let Some(segment) = path.segments.first() {
+            self.0.insert(segment.identifier.name);
+        }
+
+        visit::walk_path(self, path);
+    }
+}
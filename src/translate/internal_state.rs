@@ -1,22 +1,25 @@
-use data_structures::indexed_vec::Idx;
 use mir::*;
 use std::collections::HashSet;
 use super::builder::Builder;
+use super::generics::StateGenerics;
 use super::local_stack::LocalStack;
+use super::relooper::scope_id;
 use super::state::StateKind;
 use syntax::ast;
 use syntax::ptr::P;
 
 impl<'a, 'b: 'a> Builder<'a, 'b> {
     pub fn internal_state(&mut self) -> InternalState {
-        let blocks = self.mir.basic_blocks();
+        // Only the states get a variant, the other blocks are translated into regular control
+        // flow inside of them.
+        let blocks = self.relooper.states().iter().cloned().collect::<Vec<_>>();
 
         let mut ty_param_ids = Vec::new();
         let mut seen_ty_param_ids = HashSet::new();
         let mut variants = Vec::with_capacity(blocks.len());
         let mut arms = Vec::with_capacity(blocks.len());
 
-        for block in blocks.indices() {
+        for block in blocks {
             let (variant, tp) = self.state_variant(block, StateKind::Internal);
             variants.push(variant);
 
@@ -33,13 +36,22 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             arms.push(self.internal_arm(block));
         }
 
-        let generics = self.ast_builder.from_generics(self.mir.fn_decl.generics.clone())
+        let StateGenerics { generics, phantom_variant } = self.state_generics(&variants);
+
+        if let Some(phantom_variant) = phantom_variant {
+            variants.push(phantom_variant);
+            arms.push(quote_arm!(self.cx, InternalState::Phantom(_) => { unreachable!() }));
+        }
+
+        let generics = self.ast_builder.from_generics(generics)
             .with_ty_param_ids(ty_param_ids.iter())
             .build();
 
         let enum_name = self.ast_builder.id("InternalState");
 
-        let enum_item = self.ast_builder.item().enum_(enum_name)
+        let enum_item = self.ast_builder.item()
+            .attr().allow(&["non_camel_case_types"])
+            .enum_(enum_name)
             .generics().with(generics.clone()).build()
             .with_variants(variants)
             .build();
@@ -49,7 +61,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         ];
 
         let expr = quote_expr!(self.cx,
-            loop {
+            'state: loop {
                 match state {
                     $arms
                 }
@@ -74,28 +86,13 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         let span = self.block_span(block);
         let ast_builder = self.ast_builder.span(span);
 
-        let mut local_stack = LocalStack::new(self.mir);
-
-        // First, Load up all the locals into the stack.
-        for locals in self.scope_locals[&block].values() {
-            for local in locals {
-                local_stack.push(*local);
-            }
-        }
-
-        // Next, setup the arm body.
-        let body_stmts = self.block(block, &mut local_stack);
-        let mut body = ast_builder.block()
-            .with_stmts(body_stmts)
-            .build();
+        let body = self.state_body(block);
 
         let state_path = self.state_path(block, StateKind::Internal);
         let scope_locals = &self.scope_locals[&block];
 
         let pats = scope_locals.iter()
-            .map(|(scope, _)| {
-                ast_builder.pat().id(format!("scope{}", scope.index()))
-            })
+            .map(|(&scope, _)| ast_builder.pat().id(scope_id(scope)))
             .collect::<Vec<_>>();
 
         // Construct the pattern, which looks like:
@@ -111,6 +108,34 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 .build()
         };
 
+        ast_builder.arm()
+            .with_pat(pat)
+            .body().build_block(body)
+    }
+
+    /// Translate the code of a block, along with all the blocks it dominates, that expects the
+    /// locals that are alive on entry to be packed into `scopeN` tuples.
+    pub fn state_body(&mut self, block: BasicBlock) -> P<ast::Block> {
+        let span = self.block_span(block);
+        let ast_builder = self.ast_builder.span(span);
+
+        let mut local_stack = LocalStack::new(self.mir);
+
+        // First, Load up all the locals into the stack.
+        for locals in self.scope_locals[&block].values() {
+            for local in locals {
+                local_stack.push(*local);
+            }
+        }
+
+        // Next, setup the body.
+        let body_stmts = self.region(block, &mut local_stack);
+        let mut body = ast_builder.block()
+            .with_stmts(body_stmts)
+            .build();
+
+        let scope_locals = &self.scope_locals[&block];
+
         // Finally, we'll unpack the variables in a unique block in order to get shadowing to work
         // correctly.
         for (scope, locals) in scope_locals.iter().rev() {
@@ -136,16 +161,14 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
             let stmt = ast_builder.stmt()
                 .let_().build(pat)
-                .expr().id(format!("scope{}", scope.index()));
+                .expr().id(scope_id(*scope));
 
             body = ast_builder.block()
                 .stmt().build(stmt)
                 .expr().build_block(body);
         }
 
-        ast_builder.arm()
-            .with_pat(pat)
-            .body().build_block(body)
+        body
     }
 }
 
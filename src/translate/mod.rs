@@ -11,6 +11,20 @@ pub fn translate(tcx: TyCtxt, mir: &Mir) -> P<ast::Item> {
     let mut builder = builder::Builder::new(tcx, mir, &assignments);
     let block = builder.state_machine();
 
+    if mir.config.dump.states {
+        let ident = mir.fn_decl.ident;
+
+        let dot_path = mir.config.dump_path(ident, "states.dot");
+        mir.config.write_dump(&tcx, mir.span, &dot_path, |file| {
+            builder.write_state_diagram_dot(file)
+        });
+
+        let mermaid_path = mir.config.dump_path(ident, "states.mmd");
+        mir.config.write_dump(&tcx, mir.span, &mermaid_path, |file| {
+            builder.write_state_diagram_mermaid(file)
+        });
+    }
+
     let ast_builder = AstBuilder::new().span(mir.span);
         
     ast_builder.item()
@@ -25,8 +39,11 @@ pub fn translate(tcx: TyCtxt, mir: &Mir) -> P<ast::Item> {
 
 mod block;
 mod builder;
+mod diagram;
+mod generics;
 mod internal_state;
 mod local_stack;
+mod relooper;
 mod resume_state;
 mod state;
 mod stmt;
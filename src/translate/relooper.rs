@@ -0,0 +1,623 @@
+//! Reconstruct structured control flow from the MIR so that only the blocks that really need to be
+//! states get an `InternalState` variant.
+//!
+//! This follows the approach of relooper and "Beyond Relooper": we compute the dominator tree of
+//! the control flow graph, and then emit each block inside the code of its immediate dominator.
+//! A jump to another block then turns into one of:
+//!
+//! * A transition through the state machine, if the target is a state. States are the resume
+//!   blocks, the blocks that are dominated by no other block (like loop headers that are reachable
+//!   from more than one resume block), and the labelled states of a `#[state_machine]`.
+//! * `continue 'loop_bbN` if this is a back edge to a loop header.
+//! * `break 'join_bbN` if the target has more than one forward predecessor. The code of the
+//!   dominator is wrapped in a `'join_bbN: loop { ... }` that is immediately followed by the
+//!   target.
+//! * Otherwise the target has a single predecessor, and is simply emitted in place of the jump.
+//!
+//! The locals that are alive on entry to a block are passed along in `bbN_scopeM` tuples for
+//! joins and loops, just like they would be packed into a state variant.
+//!
+//! Dead code, like the code after a `break` or a branch that `ConstProp` folded away, hangs off
+//! the `after_target` of a `Break`. It never runs, but it still needs to be type checked, so it
+//! is emitted in place of the `Break` under an `if false { ... }`. Dead code runs straight on
+//! through its suspends, which aren't resume points, and a jump from it back into live code
+//! becomes `unreachable!()`. This way dead code never adds a state.
+
+use data_structures::indexed_vec::Idx;
+use mir::*;
+use std::collections::{BTreeMap, BTreeSet};
+use super::builder::Builder;
+use super::local_stack::LocalStack;
+use syntax::ast;
+use syntax::codemap::{Span, respan};
+
+pub struct Relooper {
+    /// The blocks that get an `InternalState` variant.
+    states: BTreeSet<BasicBlock>,
+
+    /// The blocks that can run. All the others are dead code.
+    live: BTreeSet<BasicBlock>,
+
+    /// The dead blocks that are the `after_target` of a `Break` in live code. Each is emitted in
+    /// place of that `Break`, along with the dead code it dominates.
+    dead_roots: BTreeSet<BasicBlock>,
+
+    /// The immediate dominator of every block that is not a state or a dead root.
+    idom: BTreeMap<BasicBlock, BasicBlock>,
+
+    /// The reverse postorder index of every block.
+    rpo: BTreeMap<BasicBlock, usize>,
+
+    /// Blocks that are the target of a back edge.
+    loop_headers: BTreeSet<BasicBlock>,
+
+    /// Blocks with more than one forward predecessor, grouped by their immediate dominator and
+    /// sorted by their reverse postorder.
+    merge_children: BTreeMap<BasicBlock, Vec<BasicBlock>>,
+}
+
+/// How a jump between two blocks is translated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Jump {
+    /// Transition through the state machine.
+    State,
+    /// Emit the target in place of the jump.
+    Inline,
+    /// Leave the `'join_bbN` loop that precedes the target.
+    Break,
+    /// Restart the `'loop_bbN` loop of the target.
+    Continue,
+    /// Leave dead code for a block that is emitted somewhere else.
+    Unreachable,
+}
+
+impl Relooper {
+    pub fn new(mir: &Mir) -> Self {
+        let live = mir.live_blocks();
+        let mut states = mir.resume_blocks();
+
+        // The labelled states of a `#[state_machine]` are states even if they could be inlined.
+        states.extend(mir.labelled_blocks().into_iter().filter(|block| live.contains(block)));
+
+        let dead_roots = mir.basic_blocks().iter_enumerated()
+            .filter(|&(block, _)| live.contains(&block))
+            .filter_map(|(_, data)| {
+                match data.terminator().kind {
+                    TerminatorKind::Break { after_target, .. } if !live.contains(&after_target) => {
+                        Some(after_target)
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<BTreeSet<_>>();
+
+        // Dead blocks that no `Break` leads to are still translated as states, so that they get
+        // type checked. `SimplifyCfg` removes these, so this only happens without it.
+        let reachable = reachable_blocks(mir, &live, &dead_roots);
+        states.extend(mir.basic_blocks().indices().filter(|block| {
+            !live.contains(block) && !reachable.contains(block)
+        }));
+
+        loop {
+            let roots = states.union(&dead_roots).cloned().collect::<BTreeSet<_>>();
+            let (rpo, idom) = dominators(mir, &live, &roots);
+            let mut changed = false;
+
+            for block in mir.basic_blocks().indices() {
+                if roots.contains(&block) {
+                    continue;
+                }
+
+                // Blocks that are only dominated by the entry into the state machine can be
+                // reached from more than one state, so they need to be a state themselves.
+                if idom[&block] == block {
+                    states.insert(block);
+                    changed = true;
+                }
+            }
+
+            // We can only structure reducible control flow, so any retreating edge that isn't a
+            // back edge needs to go through the state machine.
+            for source in mir.basic_blocks().indices() {
+                for target in region_targets(mir, &live, source) {
+                    if !roots.contains(&target)
+                        && rpo[&target] <= rpo[&source]
+                        && !dominates(&idom, &roots, target, source)
+                    {
+                        states.insert(target);
+                        changed = true;
+                    }
+                }
+            }
+
+            if !changed {
+                return Relooper::structure(mir, states, live, dead_roots, rpo, idom);
+            }
+        }
+    }
+
+    fn structure(mir: &Mir,
+                 states: BTreeSet<BasicBlock>,
+                 live: BTreeSet<BasicBlock>,
+                 dead_roots: BTreeSet<BasicBlock>,
+                 rpo: BTreeMap<BasicBlock, usize>,
+                 idom: BTreeMap<BasicBlock, BasicBlock>) -> Self {
+        let idom = idom.into_iter()
+            .filter(|&(block, _)| !states.contains(&block) && !dead_roots.contains(&block))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut loop_headers = BTreeSet::new();
+        let mut forward_preds = BTreeMap::new();
+
+        for source in mir.basic_blocks().indices() {
+            for target in region_targets(mir, &live, source) {
+                if states.contains(&target) || dead_roots.contains(&target) {
+                    continue;
+                }
+
+                if rpo[&target] <= rpo[&source] {
+                    loop_headers.insert(target);
+                } else {
+                    *forward_preds.entry(target).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut merge_children = BTreeMap::new();
+        for (&block, &count) in &forward_preds {
+            if count > 1 {
+                merge_children.entry(idom[&block]).or_insert_with(Vec::new).push(block);
+            }
+        }
+
+        for children in merge_children.values_mut() {
+            children.sort_by_key(|block| rpo[block]);
+        }
+
+        Relooper {
+            states: states,
+            live: live,
+            dead_roots: dead_roots,
+            idom: idom,
+            rpo: rpo,
+            loop_headers: loop_headers,
+            merge_children: merge_children,
+        }
+    }
+
+    /// The blocks that get an `InternalState` variant.
+    pub fn states(&self) -> &BTreeSet<BasicBlock> {
+        &self.states
+    }
+
+    pub fn is_state(&self, block: BasicBlock) -> bool {
+        self.states.contains(&block)
+    }
+
+    pub fn is_live(&self, block: BasicBlock) -> bool {
+        self.live.contains(&block)
+    }
+
+    pub fn is_loop_header(&self, block: BasicBlock) -> bool {
+        self.loop_headers.contains(&block)
+    }
+
+    /// The join blocks whose `'join_bbN` loops wrap the code of `block`, innermost first.
+    pub fn merge_children(&self, block: BasicBlock) -> &[BasicBlock] {
+        self.merge_children.get(&block).map_or(&[][..], |children| &children[..])
+    }
+
+    /// The state whose code contains `block`.
+    pub fn owner(&self, mut block: BasicBlock) -> BasicBlock {
+        while let Some(&parent) = self.idom.get(&block) {
+            block = parent;
+        }
+
+        block
+    }
+
+    pub fn jump(&self, source: BasicBlock, target: BasicBlock) -> Jump {
+        if !self.is_live(source) && (self.is_live(target) || self.dead_roots.contains(&target)) {
+            Jump::Unreachable
+        } else if self.is_state(target) {
+            Jump::State
+        } else if self.rpo[&target] <= self.rpo[&source] {
+            Jump::Continue
+        } else if self.merge_children(self.idom[&target]).contains(&target) {
+            Jump::Break
+        } else {
+            Jump::Inline
+        }
+    }
+}
+
+/// The blocks a terminator can jump to without going through a suspend. The `after_target` of a
+/// `Break` is never jumped to, so it's not included.
+pub fn jump_targets(terminator: &Terminator) -> Vec<BasicBlock> {
+    match terminator.kind {
+        TerminatorKind::Goto { target } |
+        TerminatorKind::Break { target, .. } => vec![target],
+        TerminatorKind::If { targets: (then, else_), .. } => vec![then, else_],
+        TerminatorKind::Match { ref arms, .. } => arms.iter().map(|arm| arm.block).collect(),
+        TerminatorKind::Return |
+        TerminatorKind::Suspend { .. } => vec![],
+    }
+}
+
+/// The blocks `block` jumps to in the code it is emitted with. Dead code also runs on into the
+/// `after_target` of a `Break` and the target of a `Suspend`, but never back into live code.
+fn region_targets(mir: &Mir, live: &BTreeSet<BasicBlock>, block: BasicBlock) -> Vec<BasicBlock> {
+    let terminator = mir[block].terminator();
+    let mut targets = jump_targets(terminator);
+
+    if live.contains(&block) {
+        return targets;
+    }
+
+    match terminator.kind {
+        TerminatorKind::Break { after_target, .. } => { targets.push(after_target); }
+        TerminatorKind::Suspend { destination: (_, target), .. } => { targets.push(target); }
+        _ => { }
+    }
+
+    targets.retain(|target| !live.contains(target));
+    targets
+}
+
+fn reachable_blocks(mir: &Mir,
+                    live: &BTreeSet<BasicBlock>,
+                    roots: &BTreeSet<BasicBlock>) -> BTreeSet<BasicBlock> {
+    let mut reachable = BTreeSet::new();
+    let mut worklist = roots.iter().cloned().collect::<Vec<_>>();
+
+    while let Some(block) = worklist.pop() {
+        if reachable.insert(block) {
+            worklist.extend(region_targets(mir, live, block));
+        }
+    }
+
+    reachable
+}
+
+/// Compute the reverse postorder and the immediate dominators of every block, where all the roots
+/// are entered from a virtual entry block. Blocks that are only dominated by the virtual entry are
+/// their own immediate dominator.
+///
+/// This uses "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy.
+fn dominators(mir: &Mir, live: &BTreeSet<BasicBlock>, roots: &BTreeSet<BasicBlock>)
+              -> (BTreeMap<BasicBlock, usize>, BTreeMap<BasicBlock, BasicBlock>) {
+    // Compute the postorder with an explicit stack, so deep functions can't overflow ours.
+    let mut postorder = vec![];
+    let mut visited = BTreeSet::new();
+
+    for &root in roots.iter().rev() {
+        if !visited.insert(root) {
+            continue;
+        }
+
+        let mut stack = vec![(root, region_targets(mir, live, root))];
+
+        while let Some((block, mut successors)) = stack.pop() {
+            match successors.pop() {
+                Some(successor) => {
+                    stack.push((block, successors));
+
+                    if visited.insert(successor) {
+                        let successors = region_targets(mir, live, successor);
+                        stack.push((successor, successors));
+                    }
+                }
+                None => {
+                    postorder.push(block);
+                }
+            }
+        }
+    }
+
+    let rpo = postorder.iter().rev()
+        .enumerate()
+        .map(|(index, &block)| (block, index))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut preds = BTreeMap::new();
+    for source in mir.basic_blocks().indices() {
+        for target in region_targets(mir, live, source) {
+            preds.entry(target).or_insert_with(Vec::new).push(source);
+        }
+    }
+
+    // `None` stands for the virtual entry block.
+    let mut idom: BTreeMap<BasicBlock, Option<BasicBlock>> = BTreeMap::new();
+    for &root in roots {
+        idom.insert(root, None);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for &block in postorder.iter().rev() {
+            if roots.contains(&block) {
+                continue;
+            }
+
+            let mut new_idom = None;
+            let mut first = true;
+
+            for &pred in preds.get(&block).map_or(&[][..], |preds| &preds[..]) {
+                if !idom.contains_key(&pred) {
+                    continue;
+                }
+
+                new_idom = if first {
+                    Some(pred)
+                } else {
+                    intersect(&rpo, &idom, new_idom, Some(pred))
+                };
+                first = false;
+            }
+
+            if idom.get(&block) != Some(&new_idom) {
+                idom.insert(block, new_idom);
+                changed = true;
+            }
+        }
+    }
+
+    let idom = idom.into_iter()
+        .map(|(block, parent)| (block, parent.unwrap_or(block)))
+        .collect();
+
+    (rpo, idom)
+}
+
+fn intersect(rpo: &BTreeMap<BasicBlock, usize>,
+             idom: &BTreeMap<BasicBlock, Option<BasicBlock>>,
+             mut lhs: Option<BasicBlock>,
+             mut rhs: Option<BasicBlock>) -> Option<BasicBlock> {
+    while lhs != rhs {
+        match (lhs, rhs) {
+            (Some(l), Some(r)) => {
+                if rpo[&l] > rpo[&r] {
+                    lhs = idom[&l];
+                } else {
+                    rhs = idom[&r];
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    lhs
+}
+
+/// Check if `dominator` dominates `block`.
+fn dominates(idom: &BTreeMap<BasicBlock, BasicBlock>,
+             roots: &BTreeSet<BasicBlock>,
+             dominator: BasicBlock,
+             mut block: BasicBlock) -> bool {
+    loop {
+        if block == dominator {
+            return true;
+        }
+
+        let parent = idom[&block];
+        if parent == block || roots.contains(&block) {
+            return false;
+        }
+
+        block = parent;
+    }
+}
+
+impl<'a, 'b: 'a> Builder<'a, 'b> {
+    /// Translate a block along with all the blocks it dominates that aren't states.
+    pub fn region(&mut self, block: BasicBlock, local_stack: &mut LocalStack) -> Vec<ast::Stmt> {
+        let span = self.block_span(block);
+        let mut stmts = self.block(block, local_stack);
+
+        let merge_children = self.relooper.merge_children(block).to_vec();
+
+        for child in merge_children {
+            let is_loop_header = self.relooper.is_loop_header(child);
+            let ast_builder = self.ast_builder.span(self.block_span(child));
+
+            let mut outer_stmts = self.scope_locals[&child].keys()
+                .map(|&scope| {
+                    let id = self.entry_id(child, scope);
+                    if is_loop_header {
+                        ast_builder.stmt().let_().mut_id(id).build()
+                    } else {
+                        ast_builder.stmt().let_().id(id).build()
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            outer_stmts.push(self.labeled_loop(span, "join", child, stmts));
+            outer_stmts.extend(self.enter_from_entry_ids(child));
+
+            stmts = outer_stmts;
+        }
+
+        stmts
+    }
+
+    /// Translate a jump from `source` to `target`.
+    pub fn jump(&mut self,
+                span: Span,
+                source: BasicBlock,
+                target: BasicBlock,
+                local_stack: &LocalStack) -> Vec<ast::Stmt> {
+        let ast_builder = self.ast_builder.span(span);
+        let kind = self.relooper.jump(source, target);
+
+        match kind {
+            Jump::State => {
+                let next_state = self.internal_state_expr(target, local_stack);
+                let next_expr = ast_builder.expr()
+                    .assign()
+                    .id("state")
+                    .build(next_state);
+
+                vec![
+                    ast_builder.stmt().semi().build(next_expr),
+                    self.labeled_jump(span, Jump::Continue, "state", None),
+                ]
+            }
+            Jump::Break | Jump::Continue => {
+                let mut stmts = self.scope_tuples(target, local_stack).into_iter()
+                    .map(|(scope, expr)| {
+                        let assign = ast_builder.expr()
+                            .assign().id(self.entry_id(target, scope))
+                            .build(expr);
+
+                        ast_builder.stmt().semi().build(assign)
+                    })
+                    .collect::<Vec<_>>();
+
+                let prefix = if kind == Jump::Break { "join" } else { "loop" };
+                stmts.push(self.labeled_jump(span, kind, prefix, Some(target)));
+                stmts
+            }
+            Jump::Inline => self.inline(span, target, local_stack),
+            Jump::Unreachable => {
+                vec![quote_stmt!(self.cx, unreachable!();).expect("unreachable stmt")]
+            }
+        }
+    }
+
+    /// Translate the dead code that the `Break` at the end of `source` skips over, under an
+    /// `if false { ... }` so that it's type checked but never runs. Nothing is emitted if the
+    /// `after_target` is live, since it's then translated with the code that jumps to it.
+    pub fn dead_code(&mut self,
+                     span: Span,
+                     source: BasicBlock,
+                     after_target: BasicBlock,
+                     local_stack: &LocalStack) -> Vec<ast::Stmt> {
+        if self.relooper.is_live(after_target) {
+            return vec![];
+        }
+
+        // Dead code in live code starts a new dead region, while dead code in dead code is just
+        // another block of the region it's in.
+        let stmts = if self.relooper.is_live(source) {
+            self.inline(span, after_target, local_stack)
+        } else {
+            self.jump(span, source, after_target, local_stack)
+        };
+
+        vec![self.if_false(span, stmts)]
+    }
+
+    /// Build `if false { stmts }`.
+    pub fn if_false(&self, span: Span, stmts: Vec<ast::Stmt>) -> ast::Stmt {
+        let ast_builder = self.ast_builder.span(span);
+        let expr = quote_expr!(self.cx, if false { $stmts });
+
+        ast_builder.stmt().expr().build(expr)
+    }
+
+    /// Emit `target` in place of a jump to it.
+    fn inline(&mut self,
+              span: Span,
+              target: BasicBlock,
+              local_stack: &LocalStack) -> Vec<ast::Stmt> {
+        let ast_builder = self.ast_builder.span(span);
+        let is_loop_header = self.relooper.is_loop_header(target);
+        let mut stmts = self.scope_tuples(target, local_stack).into_iter()
+            .map(|(scope, expr)| {
+                if is_loop_header {
+                    ast_builder.stmt().let_().mut_id(self.entry_id(target, scope))
+                        .expr().build(expr)
+                } else {
+                    ast_builder.stmt().let_id(scope_id(scope))
+                        .expr().build(expr)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if is_loop_header {
+            stmts.extend(self.enter_from_entry_ids(target));
+        } else {
+            let body = self.state_body(target);
+            stmts.push(ast_builder.stmt().expr().build_block(body));
+        }
+
+        vec![
+            ast_builder.stmt().expr().block().with_stmts(stmts).build(),
+        ]
+    }
+
+    /// Enter a join or a loop header from the `bbN_scopeM` variables its predecessors assigned.
+    fn enter_from_entry_ids(&mut self, block: BasicBlock) -> Vec<ast::Stmt> {
+        let span = self.block_span(block);
+        let ast_builder = self.ast_builder.span(span);
+
+        let mut stmts = self.scope_locals[&block].keys()
+            .map(|&scope| {
+                ast_builder.stmt().let_id(scope_id(scope))
+                    .expr().id(self.entry_id(block, scope))
+            })
+            .collect::<Vec<_>>();
+
+        let body = self.state_body(block);
+        stmts.push(ast_builder.stmt().expr().build_block(body));
+
+        if self.relooper.is_loop_header(block) {
+            vec![self.labeled_loop(span, "loop", block, stmts)]
+        } else {
+            vec![ast_builder.stmt().expr().block().with_stmts(stmts).build()]
+        }
+    }
+
+    fn entry_id(&self, block: BasicBlock, scope: VisibilityScope) -> ast::Ident {
+        self.ast_builder.id(format!("bb{}_scope{}", block.index(), scope.index()))
+    }
+
+    fn label(&self, span: Span, prefix: &str, block: Option<BasicBlock>) -> ast::SpannedIdent {
+        let name = match block {
+            Some(block) => format!("'{}_bb{}", prefix, block.index()),
+            None => format!("'{}", prefix),
+        };
+
+        respan(span, self.ast_builder.id(name))
+    }
+
+    /// Build `'prefix_bbN: loop { stmts }`.
+    pub fn labeled_loop(&self,
+                        span: Span,
+                        prefix: &str,
+                        block: BasicBlock,
+                        stmts: Vec<ast::Stmt>) -> ast::Stmt {
+        let ast_builder = self.ast_builder.span(span);
+        let body = ast_builder.block().with_stmts(stmts).build();
+        let label = self.label(span, prefix, Some(block));
+
+        ast_builder.stmt().expr()
+            .build_expr_kind(ast::ExprKind::Loop(body, Some(label)))
+    }
+
+    /// Build `break 'prefix_bbN;` or `continue 'prefix_bbN;`.
+    fn labeled_jump(&self,
+                    span: Span,
+                    kind: Jump,
+                    prefix: &str,
+                    block: Option<BasicBlock>) -> ast::Stmt {
+        let label = Some(self.label(span, prefix, block));
+
+        let expr_kind = match kind {
+            Jump::Break => ast::ExprKind::Break(label, None),
+            Jump::Continue => ast::ExprKind::Continue(label),
+            Jump::State | Jump::Inline | Jump::Unreachable => {
+                span_bug!(self.cx, span, "{:?} is not a labeled jump", kind);
+            }
+        };
+
+        self.ast_builder.span(span).stmt().semi().build_expr_kind(expr_kind)
+    }
+}
+
+/// The name of the tuple that holds the locals of a scope, like `scope1`.
+pub fn scope_id(scope: VisibilityScope) -> String {
+    format!("scope{}", scope.index())
+}
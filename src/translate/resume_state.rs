@@ -2,6 +2,7 @@ use data_structures::indexed_vec::Idx;
 use mir::*;
 use std::collections::HashSet;
 use super::builder::Builder;
+use super::generics::StateGenerics;
 use super::local_stack::LocalStack;
 use super::state::StateKind;
 use syntax::ast;
@@ -14,7 +15,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         let mut variants = Vec::with_capacity(blocks.len());
         let mut seen_ty_param_ids = HashSet::new();
         let mut ty_param_ids = vec![];
-        let arms = blocks.iter()
+        let mut arms = blocks.iter()
             .map(|&block| {
                 let (variant, tp) = self.state_variant(block, StateKind::Resume);
                 variants.push(variant);
@@ -33,30 +34,46 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             })
             .collect::<Vec<_>>();
 
-        let generics = self.ast_builder.from_generics(self.mir.fn_decl.generics.clone())
+        let StateGenerics { generics: fn_generics, phantom_variant } =
+            self.state_generics(&variants);
+
+        if let Some(phantom_variant) = phantom_variant {
+            variants.push(phantom_variant);
+            arms.push(quote_arm!(self.cx, ResumeState::Phantom(_) => { unreachable!() }));
+        }
+
+        let generics = self.ast_builder.from_generics(fn_generics.clone())
             .with_ty_param_ids(ty_param_ids.iter())
             .build();
 
-        let enum_item = self.ast_builder.item().enum_("ResumeState")
+        // The type parameters are named after the locals, as in `T_buffer`.
+        let enum_item = self.ast_builder.item()
+            .attr().allow(&["non_camel_case_types"])
+            .enum_("ResumeState")
             .generics().with(generics.clone()).build()
             .with_variants(variants)
-            .id("Illegal")
+            .id("Done")
+            .id("Poisoned")
             .build();
 
         let state_path = self.ast_builder
             .path()
                 .segment("ResumeState")
-                .with_generics(self.mir.fn_decl.generics.clone())
+                .with_generics(fn_generics)
                 .with_tys(
                     ty_param_ids.iter().map(|variable| self.ast_builder.ty().id(variable))
                 )
                 .build()
             .build();
 
+        // The drivers swap the default state in while the state machine is running, so if the
+        // body panics, the state machine is left poisoned.
+        let std = self.mir.config.std_crate();
         let default_item = quote_item!(self.cx,
-            impl $generics ::std::default::Default for $state_path {
+            #[allow(non_camel_case_types)]
+            impl $generics ::$std::default::Default for $state_path {
                 fn default() -> Self {
-                    ResumeState::Illegal
+                    ResumeState::Poisoned
                 }
             }
         ).expect("state default item");
@@ -66,10 +83,14 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             self.ast_builder.stmt().build_item(default_item),
         ];
 
+        let done_expr = self.done_expr();
+        let poisoned_expr = self.poisoned_expr();
+
         let expr = quote_expr!(self.cx,
             match resume_state {
                 $arms
-                ResumeState::Illegal => { panic!("illegal state") }
+                ResumeState::Done => { $done_expr }
+                ResumeState::Poisoned => { $poisoned_expr }
             }
         );
 
@@ -79,6 +100,53 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         }
     }
 
+    /// What resuming a state machine does after its body finished. Generators stay exhausted, like
+    /// any fused iterator, while everything else panics. See the `stateful_runtime` docs.
+    fn done_expr(&self) -> P<ast::Expr> {
+        let std = self.mir.config.std_crate();
+
+        match self.mir.state_machine_kind {
+            StateMachineKind::Generator | StateMachineKind::StateMachine => {
+                quote_expr!(self.cx,
+                    return (::$std::option::Option::None, ResumeState::Done)
+                )
+            }
+            StateMachineKind::Async => {
+                quote_expr!(self.cx, panic!("polled after completion"))
+            }
+            StateMachineKind::PushParser => {
+                quote_expr!(self.cx, panic!("fed after completion"))
+            }
+            StateMachineKind::Actor => {
+                quote_expr!(self.cx, panic!("handled a message after completion"))
+            }
+        }
+    }
+
+    /// What resuming a state machine does after its body panicked. Generators act as if they
+    /// were exhausted, and stay poisoned, while everything else panics, like futures do when they
+    /// are polled after a panic.
+    fn poisoned_expr(&self) -> P<ast::Expr> {
+        let std = self.mir.config.std_crate();
+
+        match self.mir.state_machine_kind {
+            StateMachineKind::Generator | StateMachineKind::StateMachine => {
+                quote_expr!(self.cx,
+                    return (::$std::option::Option::None, ResumeState::Poisoned)
+                )
+            }
+            StateMachineKind::Async => {
+                quote_expr!(self.cx, panic!("polled after panic"))
+            }
+            StateMachineKind::PushParser => {
+                quote_expr!(self.cx, panic!("fed after panic"))
+            }
+            StateMachineKind::Actor => {
+                quote_expr!(self.cx, panic!("handled a message after panic"))
+            }
+        }
+    }
+
     pub fn start_state_expr(&self) -> P<ast::Expr> {
         let span = self.block_span(START_BLOCK);
         let ast_builder = self.ast_builder.span(span);
@@ -4,6 +4,7 @@ use super::builder::Builder;
 use super::local_stack::LocalStack;
 use syntax::ast;
 use syntax::ptr::P;
+use syntax::visit::{self, Visitor};
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum StateKind {
@@ -21,48 +22,51 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         let ast_builder = self.ast_builder.span(span);
 
         let state_path = self.state_path(block, kind);
-        let scope_locals = &self.scope_locals[&block];
+        let exprs = self.scope_tuples(block, local_stack);
 
-        if scope_locals.is_empty() {
+        if exprs.is_empty() {
             ast_builder.expr().build_path(state_path)
         } else {
-            // Pack up all the locals back into scope tuples.
-            let exprs = scope_locals.iter()
-                .map(|(_, locals)| {
-                    ast_builder.expr().tuple()
-                        .with_exprs(
-                            locals.iter().map(|local| {
-                                if let Some(name) = local_stack.get_name(*local) {
-                                    ast_builder.expr().id(name)
-                                } else {
-                                    span_bug!(
-                                        self.cx,
-                                        span,
-                                        "No name found for local={:?} real name={:?}?",
-                                        local,
-                                        self.mir.local_decls[*local].name)
-                                }
-                            })
-                        )
-                        .build()
-                });
-
             ast_builder.expr().call()
                 .build_path(state_path)
-                .with_args(exprs)
+                .with_args(exprs.into_iter().map(|(_, expr)| expr))
                 .build()
         }
     }
 
+    /// Pack up all the locals that are alive on entry to a block into scope tuples.
+    pub fn scope_tuples(&self,
+                        block: BasicBlock,
+                        local_stack: &LocalStack) -> Vec<(VisibilityScope, P<ast::Expr>)> {
+        let span = self.block_span(block);
+        let ast_builder = self.ast_builder.span(span);
+
+        self.scope_locals[&block].iter()
+            .map(|(&scope, locals)| {
+                let expr = ast_builder.expr().tuple()
+                    .with_exprs(
+                        locals.iter().map(|local| {
+                            if let Some(name) = local_stack.get_name(*local) {
+                                ast_builder.expr().id(name)
+                            } else {
+                                span_bug!(
+                                    self.cx,
+                                    span,
+                                    "No name found for local={:?} real name={:?}?",
+                                    local,
+                                    self.mir.local_decls[*local].name)
+                            }
+                        })
+                    )
+                    .build();
+
+                (scope, expr)
+            })
+            .collect()
+    }
+
     pub fn state_id(&self, block: BasicBlock) -> ast::Ident {
-        match self.mir[block].name {
-            Some(name) => {
-                self.ast_builder.id(format!("State{}{}", block.index(), name))
-            }
-            None => {
-                self.ast_builder.id(format!("State{}", block.index()))
-            }
-        }
+        self.ast_builder.id(self.mir.state_name(block))
     }
 
     pub fn state_path(&self, block: BasicBlock, kind: StateKind) -> ast::Path {
@@ -73,7 +77,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         self.ast_builder
             .path()
-            .span(self.mir.span)
+            .span(self.glue_span)
             .id(enum_name)
             .id(self.state_id(block))
             .build()
@@ -105,12 +109,17 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
             let mut tuple_tys = vec![];
 
             for local in locals {
-                if let Some(ref ty) = self.mir.local_decls[*local].ty {
-                    tuple_tys.push(ty.clone());
-                } else {
-                    let id = ast_builder.id(format!("T{}", local.index()));
-                    tuple_tys.push(ast_builder.ty().id(id));
-                    ty_param_ids.push(id);
+                // Use the type the local was declared with if it can be written in an `enum`,
+                // otherwise let the compiler infer it through a type parameter.
+                match self.mir.local_decls[*local].ty {
+                    Some(ref ty) if is_nameable(ty, &self.mir.fn_decl.generics) => {
+                        tuple_tys.push(ty.clone());
+                    }
+                    _ => {
+                        let id = self.ty_param_id(*local);
+                        tuple_tys.push(ast_builder.ty().id(id));
+                        ty_param_ids.push(id);
+                    }
                 }
             }
 
@@ -135,4 +144,115 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
 
         (variant, ty_param_ids)
     }
+
+    /// The type parameter for a local without a nameable type, which is named after the local
+    /// so that type errors point at something readable, as in `T_buffer`. Shadowed names also
+    /// get the index of the local.
+    fn ty_param_id(&self, local: Local) -> ast::Ident {
+        let name = self.mir.local_decls[local].name.name;
+
+        let is_shadowed = self.mir.local_decls.iter()
+            .filter(|local_decl| local_decl.name.name == name)
+            .count() > 1;
+
+        if is_shadowed {
+            self.ast_builder.id(format!("T_{}_{}", name, local.index()))
+        } else {
+            self.ast_builder.id(format!("T_{}", name))
+        }
+    }
+}
+
+/// Whether a declared type can be used as the type of a variant field. Fields cannot have
+/// placeholders, `impl Trait`, or elided lifetimes, which are all fine in a `let`.
+///
+/// A lifetime can also be elided from a path, as in `slice::Iter<u8>` or `Cow<str>`, and there's
+/// no telling from the syntax whether a type takes one. So a path is only trusted if it names a
+/// primitive type or a type parameter of the function, spells out its lifetimes, or is one of the
+/// common `std` types that don't take any.
+fn is_nameable(ty: &ast::Ty, generics: &ast::Generics) -> bool {
+    struct NameableVisitor<'a> {
+        generics: &'a ast::Generics,
+        is_nameable: bool,
+    }
+
+    impl<'a> NameableVisitor<'a> {
+        fn is_path_nameable(&self, path: &ast::Path) -> bool {
+            let segment = match path.segments.last() {
+                Some(segment) => segment,
+                None => { return false; }
+            };
+
+            let has_lifetimes = match segment.parameters {
+                Some(ref parameters) => {
+                    match **parameters {
+                        ast::PathParameters::AngleBracketed(ref data) => {
+                            !data.lifetimes.is_empty()
+                        }
+                        ast::PathParameters::Parenthesized(..) => false,
+                    }
+                }
+                None => false,
+            };
+
+            if has_lifetimes {
+                return true;
+            }
+
+            let name = segment.identifier.name;
+            let name_str = name.as_str();
+
+            if path.segments.len() == 1 && !path.is_global() {
+                if PRIMITIVE_TYS.iter().any(|ty| &*name_str == *ty) ||
+                    self.generics.ty_params.iter().any(|ty_param| ty_param.ident.name == name)
+                {
+                    return true;
+                }
+            }
+
+            STD_TYS.iter().any(|ty| &*name_str == *ty)
+        }
+    }
+
+    impl<'a, 'b> Visitor<'a> for NameableVisitor<'b> {
+        fn visit_ty(&mut self, ty: &'a ast::Ty) {
+            match ty.node {
+                ast::TyKind::Infer |
+                ast::TyKind::ImplTrait(..) |
+                ast::TyKind::Typeof(..) |
+                ast::TyKind::Rptr(None, _) |
+                ast::TyKind::Path(Some(_), _) => {
+                    self.is_nameable = false;
+                }
+                ast::TyKind::Path(None, ref path) if !self.is_path_nameable(path) => {
+                    self.is_nameable = false;
+                }
+                _ => {
+                    visit::walk_ty(self, ty);
+                }
+            }
+        }
+    }
+
+    let mut visitor = NameableVisitor {
+        generics: generics,
+        is_nameable: true,
+    };
+
+    visitor.visit_ty(ty);
+
+    visitor.is_nameable
 }
+
+const PRIMITIVE_TYS: &'static [&'static str] = &[
+    "bool", "char", "str",
+    "i8", "i16", "i32", "i64", "isize",
+    "u8", "u16", "u32", "u64", "usize",
+    "f32", "f64",
+];
+
+/// The `std` types that don't take a lifetime, and so are safe to name in a variant field.
+const STD_TYS: &'static [&'static str] = &[
+    "Arc", "BTreeMap", "BTreeSet", "BinaryHeap", "Box", "Cell", "HashMap", "HashSet",
+    "LinkedList", "Option", "Rc", "RefCell", "Result", "String", "Vec", "VecDeque",
+];
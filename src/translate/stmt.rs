@@ -14,7 +14,7 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
         match stmt.kind {
             StatementKind::Stmt(ref stmt) => vec![stmt.clone()],
             StatementKind::Let { ref pat, ref lvalues, ref ty, ref rvalue } => {
-                let rvalue = rvalue.to_expr(&self.mir.local_decls);
+                let rvalue = rvalue.to_expr(stmt_span, &self.mir.local_decls);
 
                 // Rename shadowed variables.
                 let mut stmts = lvalues.iter()
@@ -37,8 +37,8 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 stmts
             }
             StatementKind::Assign(ref lvalue, ref rvalue) => {
-                let lvalue = lvalue.to_expr(&self.mir.local_decls);
-                let rvalue = rvalue.to_expr(&self.mir.local_decls);
+                let lvalue = lvalue.to_expr(stmt_span, &self.mir.local_decls);
+                let rvalue = rvalue.to_expr(stmt_span, &self.mir.local_decls);
 
                 vec![
                     ast_builder.stmt().semi()
@@ -51,11 +51,11 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 ref func,
                 ref args,
             } => {
-                let lvalue = destination.to_expr(&self.mir.local_decls);
+                let lvalue = destination.to_expr(stmt_span, &self.mir.local_decls);
 
-                let func = func.to_expr(&self.mir.local_decls);
+                let func = func.to_expr(stmt_span, &self.mir.local_decls);
                 let args = args.iter()
-                    .map(|arg| arg.to_expr(&self.mir.local_decls));
+                    .map(|arg| arg.to_expr(stmt_span, &self.mir.local_decls));
 
                 let rvalue = ast_builder.expr()
                     .call().build(func)
@@ -75,12 +75,12 @@ impl<'a, 'b: 'a> Builder<'a, 'b> {
                 ref self_,
                 ref args,
             } => {
-                let lvalue = destination.to_expr(&self.mir.local_decls);
+                let lvalue = destination.to_expr(stmt_span, &self.mir.local_decls);
 
-                let self_ = self_.to_expr(&self.mir.local_decls);
+                let self_ = self_.to_expr(stmt_span, &self.mir.local_decls);
 
                 let args = args.iter()
-                    .map(|arg| arg.to_expr(&self.mir.local_decls));
+                    .map(|arg| arg.to_expr(stmt_span, &self.mir.local_decls));
 
                 let rvalue = ast_builder.expr()
                     .span(ident.span).method_call(ident.node)
@@ -0,0 +1,188 @@
+//! The types that the code generated for some state machines refers to. Unlike generators and
+//! futures, which implement `Iterator` and `futures::Future`, there is no standard trait for these
+//! kinds, so crates that use them need to `extern crate stateful_runtime` alongside the plugin.
+//! So do generators with a recursive `yield_from!(...)`, which box the inner generator with
+//! `box_iter`.
+//!
+//! These live apart from the `stateful` plugin, which links against the compiler, so that using a
+//! state machine doesn't mean linking the plugin into the program.
+//!
+//! # Completion and panics
+//!
+//! Every kind of state machine holds its state in a `ResumeState` enum, which is swapped out
+//! while the body runs. Once the body has finished, a `#[generator]` or `#[state_machine]` keeps
+//! returning `None`, while resuming a future, push parser or actor panics with "polled after
+//! completion", "fed after completion" or "handled a message after completion".
+//!
+//! If the body panics, the state machine is left poisoned. A generator then also keeps returning
+//! `None`, and the other kinds panic with "polled after panic", "fed after panic" or "handled a
+//! message after panic", so a caller that catches the panic with `std::panic::catch_unwind` can
+//! keep the state machine around without observing a half finished state.
+//!
+//! A state machine is `UnwindSafe` when all of the locals it saves across suspends are. This only
+//! shows through an `impl Trait` return type: a `Box<Iterator<Item=T>>` or `Box<Future<...>>`
+//! trait object doesn't carry it, so boxed state machines need `std::panic::AssertUnwindSafe` to
+//! be passed to `catch_unwind`.
+
+use std::cmp;
+use std::io;
+
+/// What a `PushParser` did with the input it was fed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress<T> {
+    /// The parser consumed all of the input, and needs more before it can finish.
+    NeedsInput,
+
+    /// The parser finished with this value, after consuming this many bytes of the input it was
+    /// just fed. The rest of that input comes after the parsed value, and is left to the caller.
+    Done(T, usize),
+}
+
+/// A parser that is driven by pushing chunks of input into it, as returned by a `#[push_parser]`
+/// function. The function body reads the next chunk with `needs_input!()`, which suspends the
+/// parser until the caller feeds it more bytes, so the parser can be written as sequential code
+/// that still works with partial reads.
+///
+/// Each chunk is copied into a `Vec<u8>` that the body owns, so it can be kept across later
+/// `needs_input!()` calls. If the parsed value ends partway through the last chunk, the body
+/// says how many bytes at the end of that chunk it didn't use with `unconsumed!(len)`, and the
+/// rest of the chunk is reported as consumed by `Progress::Done`. Every `needs_input!()` resets
+/// this back to zero.
+///
+/// Feeding a parser that is already done panics.
+pub trait PushParser {
+    type Output;
+
+    fn feed(&mut self, input: &[u8]) -> Progress<Self::Output>;
+}
+
+/// A session that handles one message at a time, as returned by an `#[actor]` function. The
+/// function body waits for the next message with `recv!()`, and answers it with `reply!(value)`,
+/// so the protocol can be written as sequential code instead of a hand written `enum` of phases.
+///
+/// `handle` runs the body until it waits for the next message again, or finishes, and returns the
+/// last reply it made along the way, if any. Handling a message after the actor has finished
+/// panics.
+///
+/// The body runs up to its first `recv!()` when the actor is created, so a reply made before then,
+/// such as a greeting banner, doesn't answer any message. It is kept until it is taken with
+/// `greeting`, and isn't returned by `handle`.
+pub trait Actor {
+    type Message;
+    type Reply;
+
+    /// Take the reply the actor made before it waited for its first message, if any.
+    fn greeting(&mut self) -> Option<Self::Reply>;
+
+    fn handle(&mut self, msg: Self::Message) -> Option<Self::Reply>;
+}
+
+/// Box an iterator into a trait object, keeping its lifetime. A recursive `yield_from!(...)` in a
+/// `#[generator]` boxes the inner generator with this, since a generator can't contain its own
+/// state.
+pub fn box_iter<'a, I>(iter: I) -> Box<Iterator<Item=I::Item> + 'a>
+    where I: Iterator + 'a,
+{
+    Box::new(iter)
+}
+
+/// A `std::io::Read` over an iterator of byte chunks, as returned by a `#[generator(read)]`
+/// function. The part of a chunk that doesn't fit into the buffer passed to `read` is kept for
+/// the next call, and the iterator isn't advanced again once it is exhausted.
+pub struct ChunkReader<I: Iterator> {
+    iter: I,
+    chunk: Option<I::Item>,
+    pos: usize,
+    done: bool,
+}
+
+impl<I> ChunkReader<I>
+    where I: Iterator,
+          I::Item: AsRef<[u8]>,
+{
+    pub fn new(iter: I) -> Self {
+        ChunkReader {
+            iter: iter,
+            chunk: None,
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<I> io::Read for ChunkReader<I>
+    where I: Iterator,
+          I::Item: AsRef<[u8]>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.done && !buf.is_empty() {
+            if let Some(ref chunk) = self.chunk {
+                let rest = &chunk.as_ref()[self.pos..];
+
+                if !rest.is_empty() {
+                    let len = cmp::min(buf.len(), rest.len());
+                    buf[..len].copy_from_slice(&rest[..len]);
+                    self.pos += len;
+
+                    return Ok(len);
+                }
+            }
+
+            match self.iter.next() {
+                Some(chunk) => {
+                    self.chunk = Some(chunk);
+                    self.pos = 0;
+                }
+                None => {
+                    self.chunk = None;
+                    self.done = true;
+                }
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+/// A `std::io::Write` that feeds every written slice into a push parser, as returned by a
+/// `#[push_parser(write)]` function. The parser finishes with an `io::Result<()>`, which is
+/// returned from the write that finished it, along with how many bytes of that write the parser
+/// consumed. Once it is done, writes return `Ok(0)`, so `write_all` reports any bytes past the
+/// end of the parsed value as a `WriteZero` error instead of dropping them.
+pub struct ParserWriter<P> {
+    parser: P,
+    done: bool,
+}
+
+impl<P> ParserWriter<P>
+    where P: PushParser<Output=io::Result<()>>,
+{
+    pub fn new(parser: P) -> Self {
+        ParserWriter {
+            parser: parser,
+            done: false,
+        }
+    }
+}
+
+impl<P> io::Write for ParserWriter<P>
+    where P: PushParser<Output=io::Result<()>>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+
+        match self.parser.feed(buf) {
+            Progress::NeedsInput => Ok(buf.len()),
+            Progress::Done(result, consumed) => {
+                self.done = true;
+                result.map(|()| consumed)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
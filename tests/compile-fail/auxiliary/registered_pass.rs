@@ -0,0 +1,32 @@
+// no-prefer-dynamic
+
+#![crate_type = "dylib"]
+#![feature(plugin_registrar, rustc_private)]
+
+extern crate rustc_plugin;
+extern crate stateful;
+
+use rustc_plugin::Registry;
+use stateful::mir::Mir;
+use stateful::transform::{MirPass, Pass, register_mir_pass};
+use stateful::ty::TyCtxt;
+use std::borrow::Cow;
+
+/// Rejects every state machine, so the tests can tell that it ran.
+struct RejectAll;
+
+impl Pass for RejectAll {
+    fn name(&self) -> Cow<'static, str> { "RejectAll".into() }
+}
+
+impl MirPass for RejectAll {
+    fn run_pass<'a, 'tcx>(&mut self, tcx: TyCtxt<'a, 'tcx>, mir: &mut Mir) {
+        tcx.span_err(mir.span, "rejected by a registered pass");
+    }
+}
+
+#[plugin_registrar]
+pub fn plugin_registrar(registry: &mut Registry) {
+    stateful::plugin_registrar(registry);
+    register_mir_pass(|| RejectAll);
+}
@@ -0,0 +1,14 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let value: usize = await!(1); //~ ERROR `await!(...)` can only be used in `#[async]` functions
+    yield_!(value);
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
@@ -0,0 +1,16 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let items = vec![1, 2, 3];
+    let first = items.first();
+    yield_!(1); //~ ERROR cannot move out of `items` because it is borrowed [E0505]
+    yield_!(*first.unwrap());
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
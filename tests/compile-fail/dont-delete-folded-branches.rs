@@ -0,0 +1,22 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    if false {
+        let x = y; //~ ERROR unresolved value `y` [E0425]
+        yield_!(x);
+    }
+
+    match 1 {
+        1 => { yield_!(1); }
+        2 => { yield_!(z); } //~ ERROR unresolved value `z` [E0425]
+        _ => { yield_!(w); } //~ ERROR unresolved value `w` [E0425]
+    }
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
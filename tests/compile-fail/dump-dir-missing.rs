@@ -0,0 +1,13 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+// Every dump that can't be written is reported at the function. The generator is on one line,
+// since the errors point at the whole function.
+#[generator(dump(dataflow, states, source), dump_dir = "target/missing")] fn gen() -> Box<Iterator<Item=usize>> { yield_!(1); }
+//~^ ERROR could not write `target/missing/stateful.gen.6.definite_init.preflow.dot`
+//~| ERROR could not write `target/missing/stateful.gen.6.definite_init.postflow.dot`
+//~| ERROR could not write `target/missing/stateful.gen.6.states.dot`
+//~| ERROR could not write `target/missing/stateful.gen.6.states.mmd`
+//~| ERROR could not write `target/missing/stateful.gen.6.rs`
+
+fn main() {}
@@ -0,0 +1,14 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+// The type parameter of a saved local without a declared type is named after it. The generator
+// is on one line, since the error points at the state enum that holds the local.
+#[generator] fn gen() -> Box<Iterator<Item=u8>> { let buffer = Default::default(); yield_!(1); let _ = &buffer; }
+//~^ ERROR [E0282]
+//~| NOTE cannot infer type for `T_buffer`
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
@@ -0,0 +1,14 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    struct Unit; //~ ERROR item declarations are not supported in state machine functions
+    yield_!(1);
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
@@ -4,9 +4,9 @@
 fn do_move<T>(_item: T) {}
 
 #[generator]
-fn gen(item: String) -> Box<Iterator<Item=String>> { //~ ERROR use of possibly uninitialized variable: `item` [E0381]
-    yield_!(moved!(item));
+fn gen(item: String) -> Box<Iterator<Item=String>> {
     yield_!(moved!(item));
+    yield_!(moved!(item)); //~ ERROR use of possibly uninitialized variable: `item` [E0381]
 }
 
 fn main() {
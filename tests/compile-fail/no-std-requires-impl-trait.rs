@@ -0,0 +1,9 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator(no_std)]
+fn gen() -> Box<Iterator<Item=usize>> { //~ ERROR `generator(no_std)` functions must return an `impl Trait` type
+    yield_!(1);
+}
+
+fn main() {}
@@ -0,0 +1,31 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+extern crate stateful_runtime;
+
+use std::io;
+use stateful_runtime::{Actor, PushParser};
+
+#[push_parser(no_std)] //~ ERROR `push_parser` functions cannot be `no_std`
+fn parser() -> Box<PushParser<Output=usize>> {
+    needs_input!().len()
+}
+
+#[actor(no_std)] //~ ERROR `actor` functions cannot be `no_std`
+fn actor() -> Box<Actor<Message=usize, Reply=usize>> {
+    let msg = recv!();
+    reply!(msg);
+}
+
+#[generator(read, no_std)] //~ ERROR the `read` and `write` options need `std::io`, so they cannot be `no_std`
+fn reader() -> Box<io::Read> {
+    yield_!(vec![1u8]);
+}
+
+#[push_parser(write, no_std)] //~ ERROR `push_parser` functions cannot be `no_std`
+fn writer() -> Box<io::Write> {
+    needs_input!();
+    Ok(())
+}
+
+fn main() {}
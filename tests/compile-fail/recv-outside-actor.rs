@@ -0,0 +1,14 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let value: usize = recv!(); //~ ERROR `recv!()` can only be used in `#[actor]` functions
+    yield_!(value);
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
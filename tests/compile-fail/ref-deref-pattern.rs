@@ -0,0 +1,15 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen(items: Vec<(usize, usize)>) -> Box<Iterator<Item=usize>> {
+    for &(a, b) in items.iter() { //~ ERROR `&` patterns are not supported in state machine functions
+        yield_!(a + b);
+    }
+}
+
+fn main() {
+    for value in gen(vec![(1, 2)]) {
+        println!("{}", value);
+    }
+}
@@ -0,0 +1,14 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen(pair: (usize, usize)) -> Box<Iterator<Item=usize>> {
+    let (ref a, b) = pair; //~ ERROR `ref` bindings are not supported in state machine functions
+    yield_!(b);
+}
+
+fn main() {
+    for value in gen((1, 2)) {
+        println!("{}", value);
+    }
+}
@@ -0,0 +1,17 @@
+// aux-build:registered_pass.rs
+
+#![feature(plugin)]
+#![plugin(registered_pass)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> { //~ ERROR rejected by a registered pass
+    yield_!(1);
+}
+
+// Registered passes can be disabled like the built-in ones.
+#[generator(disable_passes(RejectAll))]
+fn gen2() -> Box<Iterator<Item=usize>> {
+    yield_!(1);
+}
+
+fn main() {}
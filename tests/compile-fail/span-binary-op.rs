@@ -0,0 +1,16 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let x = 1usize;
+    yield_!(x);
+    let y = x + "a"; //~ ERROR is not satisfied [E0277]
+    yield_!(y);
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
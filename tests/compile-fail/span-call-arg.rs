@@ -0,0 +1,17 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+fn takes_str(_: &str) {}
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let n = 1usize;
+    yield_!(n);
+    takes_str(n); //~ ERROR mismatched types [E0308]
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
@@ -0,0 +1,16 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let s = String::new();
+    yield_!(1);
+    let n: usize = s; //~ ERROR mismatched types [E0308]
+    yield_!(n);
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
@@ -0,0 +1,22 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+fn takes_strs(_: &str, _: &str) {}
+
+// Errors point at the operand that is wrong, not at the start of the statement that uses it, so
+// the operands here are on lines of their own.
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let n = 1usize;
+    yield_!(n);
+    takes_strs(
+        "a",
+        n, //~ ERROR mismatched types [E0308]
+    );
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
@@ -0,0 +1,41 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[state_machine]
+fn collision() -> Box<Iterator<Item=usize>> {
+    'a_b: loop {
+        suspend!(1);
+        break;
+    }
+
+    'aB: loop { //~ ERROR states `'a_b` and `'aB` would both be named `AB`
+        suspend!(2);
+        break;
+    }
+}
+
+#[state_machine]
+fn reserved() -> Box<Iterator<Item=usize>> {
+    'state1: loop { //~ ERROR state `'state1` would be named `State1`, which is reserved
+        suspend!(1);
+        break;
+    }
+
+    'done: loop {
+        break;
+    }
+}
+
+#[state_machine]
+fn unnamed() -> Box<Iterator<Item=usize>> {
+    'start: loop {
+        suspend!(1);
+        break;
+    }
+
+    '__: loop { //~ ERROR cannot name a state after `'__`
+        break;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    suspend!(1); //~ ERROR `suspend!(...)` cannot be directly called
+    yield_!(suspend!(2)); //~ ERROR `suspend!(...)` cannot be directly called
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
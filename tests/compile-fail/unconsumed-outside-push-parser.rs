@@ -0,0 +1,14 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    unconsumed!(1); //~ ERROR `unconsumed!(...)` can only be used in `#[push_parser]` functions
+    yield_!(1);
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
@@ -0,0 +1,24 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator(dump(mir, everything))] //~ ERROR unknown dump kind `everything`
+fn gen() -> Box<Iterator<Item=usize>> {
+    yield_!(1);
+}
+
+#[generator(fast)] //~ ERROR unknown state machine option `fast`
+fn gen2() -> Box<Iterator<Item=usize>> {
+    yield_!(1);
+}
+
+#[generator(dump_passes = "SimplifyCfg")] //~ ERROR expected `dump_passes(...)`
+fn gen3() -> Box<Iterator<Item=usize>> {
+    yield_!(1);
+}
+
+#[generator(disable_passes(ConstProp, Inline))] //~ ERROR unknown pass `Inline`
+fn gen4() -> Box<Iterator<Item=usize>> {
+    yield_!(1);
+}
+
+fn main() {}
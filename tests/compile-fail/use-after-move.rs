@@ -0,0 +1,16 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+#[generator]
+fn gen() -> Box<Iterator<Item=usize>> {
+    let s = String::from("wee");
+    let t = s;
+    yield_!(t.len());
+    yield_!(s.len()); //~ ERROR use of moved value: `s` [E0382]
+}
+
+fn main() {
+    for value in gen() {
+        println!("{}", value);
+    }
+}
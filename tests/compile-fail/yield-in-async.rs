@@ -0,0 +1,16 @@
+#![feature(plugin)]
+#![plugin(stateful)]
+
+extern crate futures;
+
+use futures::Future;
+
+#[async]
+fn fut() -> Box<Future<Item=usize, Error=()>> {
+    yield_!(1); //~ ERROR `yield_!(...)` can only be used in `#[generator]` functions
+    Ok(2)
+}
+
+fn main() {
+    fut().wait().unwrap();
+}
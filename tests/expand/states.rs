@@ -0,0 +1,68 @@
+// Functions for `stateful-expand` to expand in `tests/test_expand.rs`. This file is only parsed.
+
+#[state_machine]
+fn machine(rounds: usize) -> Box<Iterator<Item=&'static str>> {
+    let mut count = 0;
+
+    'ping: loop {
+        if count == rounds {
+            return;
+        }
+
+        suspend!("ping");
+        goto!('pong);
+    }
+
+    'pong: loop {
+        count += 1;
+        suspend!("pong");
+        goto!('ping);
+    }
+}
+
+#[generator]
+fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+    let mut i = 0;
+    while i < n {
+        yield_!(i);
+        i += 1;
+    }
+}
+
+#[push_parser(no_std)]
+fn parser() -> Box<PushParser<Output=usize>> {
+    let input = needs_input!();
+    input.len()
+}
+
+#[generator]
+fn folded() -> Box<Iterator<Item=usize>> {
+    if true {
+        yield_!(1);
+    } else {
+        yield_!(2);
+    }
+
+    match 2 {
+        1 => { yield_!(3); }
+        2 => { yield_!(4); }
+        _ => { yield_!(5); }
+    }
+}
+
+#[generator]
+fn inlined(n: usize) -> Box<Iterator<Item=usize>> {
+    let x = if n > 1 { n } else { 1 };
+    let y = match x {
+        1 => 2,
+        _ => x * 2,
+    };
+
+    yield_!(y);
+
+    if y > 10 {
+        return;
+    }
+
+    yield_!(y + 1);
+}
@@ -0,0 +1,347 @@
+//! Runs a single MIR pass over the hand-written functions in `tests/mir-opt`.
+//!
+//! Every `foo.mar` is parsed and printed back, which has to give the same text again. If its
+//! header names a pass with `// pass_name = SimplifyCfg`, that pass is run and the result is
+//! compared with `foo.after.mar`. If it instead lists `// error = ...` lines, the MIR has to fail
+//! validation with every one of those messages. Blank lines, comment lines and runs of
+//! whitespace are ignored in the comparisons, so the expected output doesn't have to line up the
+//! comments like the printer does.
+//!
+//! The other tests here load a single function to check the rest of the MIR tooling on it.
+
+#![feature(rustc_private)]
+
+extern crate stateful;
+extern crate syntax;
+
+use stateful::config::Config;
+use stateful::data_structures::indexed_vec::Idx;
+use stateful::graphviz::{escape_dot, escape_mermaid, write_mir_graphviz};
+use stateful::interpret::{self, Trace, Value};
+use stateful::json::write_mir_json;
+use stateful::mir::{BasicBlock, Mir, TerminatorKind};
+use stateful::parse_mir::parse_mir;
+use stateful::pretty::write_mir_fn;
+use stateful::transform::{MirMapPass, MirPass, Pass, Passes};
+use stateful::transform::NopPass;
+use stateful::transform::check_trace::CheckTrace;
+use stateful::transform::const_prop::ConstProp;
+use stateful::transform::dump_graphviz::DumpGraphviz;
+use stateful::transform::simplify::{SimplifyCfg, SimplifyLocals};
+use stateful::transform::validate::{Validate, validate};
+use stateful::ty::TyCtxt;
+use std::borrow::Cow;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use syntax::ast;
+use syntax::codemap::DUMMY_SP;
+use syntax::ext::base::{DummyResolver, ExtCtxt};
+use syntax::ext::expand::ExpansionConfig;
+use syntax::parse::ParseSess;
+
+fn pass(name: &str) -> Box<MirMapPass> {
+    match name {
+        "ConstProp" => Box::new(ConstProp),
+        "SimplifyCfg" => Box::new(SimplifyCfg::new("mir-opt")),
+        "SimplifyLocals" => Box::new(SimplifyLocals),
+        _ => panic!("unknown pass `{}`", name),
+    }
+}
+
+fn read(path: &Path) -> String {
+    let mut text = String::new();
+    File::open(path).and_then(|mut file| file.read_to_string(&mut text)).unwrap();
+    text
+}
+
+/// Find a `// key = value` line in the header.
+fn header<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    headers(text, key).into_iter().next()
+}
+
+/// Find every `// key = value` line in the header.
+fn headers<'a>(text: &'a str, key: &str) -> Vec<&'a str> {
+    let prefix = format!("// {} = ", key);
+
+    text.lines()
+        .filter(|line| line.starts_with(&prefix[..]))
+        .map(|line| line[prefix.len()..].trim())
+        .collect()
+}
+
+fn normalize(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+        .collect()
+}
+
+fn check(what: &str, expected: &str, actual: &str) -> Result<(), String> {
+    if normalize(expected) == normalize(actual) {
+        Ok(())
+    } else {
+        Err(format!("{} differs, got:\n{}", what, actual))
+    }
+}
+
+/// Parse the MIR in `source` with these options and pass it to `f`.
+fn with_mir<F>(source: &str, config: Config, f: F) -> Result<(), String>
+    where F: FnOnce(TyCtxt, Mir) -> Result<(), String>,
+{
+    let sess = ParseSess::new();
+    let mut resolver = DummyResolver;
+    let cx = ExtCtxt::new(&sess, ExpansionConfig::default("mir_opt".to_string()), &mut resolver);
+    let tcx = TyCtxt::new(&cx);
+
+    match parse_mir(&sess, config, source) {
+        Ok(mir) => f(tcx, mir),
+        Err(mut err) => {
+            err.emit();
+            Err("failed to parse".to_string())
+        }
+    }
+}
+
+fn fixture(name: &str) -> String {
+    read(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("mir-opt").join(name))
+}
+
+fn run(path: &Path) -> Result<(), String> {
+    let source = read(path);
+    with_mir(&source, Config::default(), |tcx, mir| run_mir(path, &source, tcx, mir))
+}
+
+fn run_mir(path: &Path, source: &str, tcx: TyCtxt, mut mir: Mir) -> Result<(), String> {
+    let mut printed = vec![];
+    write_mir_fn(tcx, &mir, &mut printed).unwrap();
+    check("printed MIR", source, &String::from_utf8(printed).unwrap())?;
+
+    let errors = headers(source, "error");
+    if !errors.is_empty() {
+        let violations = validate(&mir).into_iter()
+            .map(|violation| violation.message)
+            .collect::<Vec<_>>();
+
+        for error in errors {
+            if !violations.iter().any(|violation| violation.contains(error)) {
+                return Err(format!("expected a violation `{}`, got:\n{}",
+                                   error,
+                                   violations.join("\n")));
+            }
+        }
+
+        return Ok(());
+    }
+
+    let pass_name = match header(source, "pass_name") {
+        Some(pass_name) => pass_name,
+        None => { return Ok(()); }
+    };
+
+    let mut passes = Passes::new();
+    passes.push_hook(Box::new(Validate));
+    passes.push_pass(pass(pass_name));
+    passes.run_passes(tcx, &mut mir);
+
+    let mut printed = vec![];
+    write_mir_fn(tcx, &mir, &mut printed).unwrap();
+
+    let expected = read(&path.with_extension("after.mar"));
+    check(&format!("MIR after `{}`", pass_name), &expected, &String::from_utf8(printed).unwrap())
+}
+
+#[test]
+fn mir_opt() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("mir-opt");
+    let mut failures = vec![];
+
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if !name.ends_with(".mar") || name.ends_with(".after.mar") {
+            continue;
+        }
+
+        if let Err(err) = run(&path) {
+            failures.push(format!("{}: {}", name, err));
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("{} MIR tests failed:\n\n{}", failures.len(), failures.join("\n\n"));
+    }
+}
+
+#[test]
+fn json() {
+    with_mir(&fixture("round_trip.mar"), Config::default(), |tcx, mir| {
+        let mut json = vec![];
+        write_mir_json(tcx, &mir, &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+
+        let expected = [
+            "\"kind\": \"Assign\", \"lvalue\": { \"kind\": \"Local\", \"local\": \"_8\" }, \
+             \"rvalue\": { \"kind\": \"Ref\", \"mutable\": true, \
+             \"lvalue\": { \"kind\": \"Deref\", \
+             \"base\": { \"kind\": \"Local\", \"local\": \"_2\" } } }",
+            "\"kind\": \"Suspend\", \"destination\": { \"kind\": \"Local\", \"local\": \"_1\" }, \
+             \"target\": \"bb4\", \"arg\": { \"kind\": \"Consume\", \
+             \"lvalue\": { \"kind\": \"Local\", \"local\": \"_5\" } }",
+            "\"kind\": \"Break\", \"target\": \"bb4\", \"after_target\": \"bb3\"",
+            "\"kind\": \"StorageDead\", \"lvalue\": { \"kind\": \"Local\", \"local\": \"_4\" }",
+        ];
+
+        for expected in &expected {
+            if !json.contains(expected) {
+                return Err(format!("expected `{}` in:\n{}", expected, json));
+            }
+        }
+
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn graphviz() {
+    with_mir(&fixture("round_trip.mar"), Config::default(), |tcx, mut mir| {
+        // Passes can leave a block without a terminator, which still has to be rendered.
+        mir.basic_blocks_mut()[BasicBlock::new(3)].terminator = None;
+
+        let mut dot = vec![];
+        write_mir_graphviz(tcx, &mir, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+
+        let expected = [
+            "digraph Mir {",
+            "subgraph cluster_scope4 {",
+            "bb1 -> bb4 [style=\"dashed\", label=\"suspend(consume _5)\"];",
+            "bb2 -> bb4 [label=\"target\"];",
+            "bb2 -> bb3 [label=\"after_target\"];",
+            "&lt;no terminator&gt;",
+        ];
+
+        for expected in &expected {
+            if !dot.contains(expected) {
+                return Err(format!("expected `{}` in:\n{}", expected, dot));
+            }
+        }
+
+        if dot.contains("bb3 -> ") {
+            return Err(format!("expected no edges out of bb3 in:\n{}", dot));
+        }
+
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn dump_graphviz() {
+    let dump_dir = env::temp_dir().join("stateful-mir-opt-dump-graphviz");
+    fs::create_dir_all(&dump_dir).unwrap();
+
+    let mut config = Config::default();
+    config.dump.graphviz = true;
+    config.dump_dir = Some(dump_dir);
+
+    with_mir(&fixture("round_trip.mar"), config, |tcx, mut mir| {
+        let before = mir.config.dump_path(mir.fn_decl.ident, "NopPass.test-before.dot");
+        let after = mir.config.dump_path(mir.fn_decl.ident, "NopPass.test-after.dot");
+        let _ = fs::remove_file(&before);
+        let _ = fs::remove_file(&after);
+
+        let mut passes = Passes::new();
+        passes.push_hook(Box::new(DumpGraphviz));
+        passes.push_pass(Box::new(NopPass::new("test")));
+        passes.run_passes(tcx, &mut mir);
+
+        for path in &[before, after] {
+            if !read(path).starts_with("digraph Mir {") {
+                return Err(format!("expected a graph in `{}`", path.display()));
+            }
+        }
+
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn diagram_escapes() {
+    assert_eq!(escape_dot("say \"hi\\\"\n"), "say \\\"hi\\\\\\\"\\n");
+    assert_eq!(escape_mermaid("a;\r\nb"), "a#59; b");
+}
+
+#[test]
+fn interpret() {
+    with_mir(&fixture("interpret_loop.mar"), Config::default(), |_, mir| {
+        let trace = interpret::run(&mir, vec![Value::Int(3)]).map_err(|err| err.to_string())?;
+        let expected = Trace {
+            suspends: vec![Value::Int(0), Value::Int(1), Value::Int(2)],
+            returned: Value::Int(3),
+        };
+
+        if trace != expected {
+            return Err(format!("expected {:?}, got {:?}", expected, trace));
+        }
+
+        Ok(())
+    }).unwrap();
+
+    // Integers are interpreted as `i64`, so this is `i64::MIN / -1`, which has to wrap instead of
+    // taking the compiler down with it.
+    let source = fixture("interpret_loop.mar").replace(
+        "+(copy _0, copy _3)",
+        "/(const 9223372036854775808, const 18446744073709551615)");
+
+    with_mir(&source, Config::default(), |_, mir| {
+        let trace = interpret::run(&mir, vec![Value::Int(3)]).map_err(|err| err.to_string())?;
+
+        if trace.returned != Value::Int(i64::min_value()) {
+            return Err(format!("expected the division to wrap, got {:?}", trace.returned));
+        }
+
+        Ok(())
+    }).unwrap();
+}
+
+/// Swaps the targets of every `if`, which changes what the state machine does.
+struct SwapIfTargets;
+
+impl Pass for SwapIfTargets {
+    fn name(&self) -> Cow<'static, str> { "SwapIfTargets".into() }
+}
+
+impl MirPass for SwapIfTargets {
+    fn run_pass<'a, 'tcx>(&mut self, _tcx: TyCtxt<'a, 'tcx>, mir: &mut Mir) {
+        for data in mir.basic_blocks_mut().iter_mut() {
+            if let TerminatorKind::If { ref mut targets, .. } = data.terminator_mut().kind {
+                *targets = (targets.1, targets.0);
+            }
+        }
+    }
+}
+
+#[test]
+fn check_trace() {
+    let mut config = Config::default();
+    config.interpret = Some(vec![ast::Lit {
+        node: ast::LitKind::Int(3, ast::LitIntType::Unsuffixed),
+        span: DUMMY_SP,
+    }]);
+
+    with_mir(&fixture("interpret_loop.mar"), config, |tcx, mut mir| {
+        let mut passes = Passes::new();
+        passes.push_hook(Box::new(CheckTrace::new()));
+        passes.push_pass(Box::new(NopPass::new("test")));
+        passes.push_pass(Box::new(SwapIfTargets));
+        passes.run_passes(tcx, &mut mir);
+
+        match tcx.sess.span_diagnostic.err_count() {
+            1 => Ok(()),
+            count => Err(format!("expected `SwapIfTargets` to be reported once, got {} errors",
+                                 count)),
+        }
+    }).unwrap();
+}
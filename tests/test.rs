@@ -1,4 +1,4 @@
-#![feature(plugin)]
+#![feature(attr_literals, plugin)]
 #![plugin(stateful)]
 
 #![cfg_attr(feature = "impl_trait", feature(conservative_impl_trait))]
@@ -9,6 +9,17 @@
 #![allow(unused_mut)]
 #![allow(unused_variables)]
 
+extern crate futures;
+extern crate stateful_runtime;
+
 mod empty;
+mod test_actor;
+mod test_async;
+mod test_expand;
 mod test_generator;
-//mod test_compile_fail;
+mod test_io;
+mod test_panic;
+mod test_push_parser;
+mod test_state_machine;
+#[cfg(feature = "unstable-testing")]
+mod test_compile_fail;
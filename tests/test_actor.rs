@@ -0,0 +1,154 @@
+use stateful_runtime::Actor;
+
+#[test]
+fn test_echo() {
+    #[actor]
+    fn echo() -> Box<Actor<Message=usize, Reply=usize>> {
+        loop {
+            let msg = recv!();
+            reply!(msg);
+        }
+    }
+
+    let mut actor = echo();
+    assert_eq!(actor.handle(1), Some(1));
+    assert_eq!(actor.handle(2), Some(2));
+}
+
+#[test]
+fn test_no_reply() {
+    #[actor]
+    fn sum() -> Box<Actor<Message=usize, Reply=usize>> {
+        let mut total = 0;
+
+        loop {
+            let msg = recv!();
+
+            if msg == 0 {
+                reply!(total);
+                total = 0;
+            } else {
+                total += msg;
+            }
+        }
+    }
+
+    let mut actor = sum();
+    assert_eq!(actor.handle(1), None);
+    assert_eq!(actor.handle(2), None);
+    assert_eq!(actor.handle(0), Some(3));
+    assert_eq!(actor.handle(4), None);
+    assert_eq!(actor.handle(0), Some(4));
+}
+
+#[test]
+fn test_reply_on_return() {
+    #[actor]
+    fn countdown(count: usize) -> Box<Actor<Message=(), Reply=usize>> {
+        let mut count = count;
+
+        loop {
+            recv!();
+            count -= 1;
+            reply!(count);
+
+            if count == 0 {
+                return;
+            }
+        }
+    }
+
+    let mut actor = countdown(2);
+    assert_eq!(actor.handle(()), Some(1));
+    assert_eq!(actor.handle(()), Some(0));
+}
+
+#[test]
+fn test_session() {
+    #[derive(Debug, PartialEq)]
+    enum Reply {
+        Welcome(String),
+        Echo(String),
+        Denied,
+        Bye,
+    }
+
+    #[actor]
+    fn session(password: &'static str) -> Box<Actor<Message=String, Reply=Reply>> {
+        let user = recv!();
+
+        loop {
+            if recv!() == password {
+                break;
+            }
+
+            reply!(Reply::Denied);
+        }
+
+        reply!(Reply::Welcome(user.clone()));
+
+        loop {
+            let line = recv!();
+
+            if line == "quit" {
+                reply!(Reply::Bye);
+                return;
+            }
+
+            reply!(Reply::Echo(moved!(line)));
+        }
+    }
+
+    let mut actor = session("hunter2");
+    assert_eq!(actor.handle("alice".to_string()), None);
+    assert_eq!(actor.handle("hunter1".to_string()), Some(Reply::Denied));
+    assert_eq!(actor.handle("hunter2".to_string()), Some(Reply::Welcome("alice".to_string())));
+    assert_eq!(actor.handle("hi".to_string()), Some(Reply::Echo("hi".to_string())));
+    assert_eq!(actor.handle("quit".to_string()), Some(Reply::Bye));
+}
+
+#[test]
+fn test_greeting() {
+    #[actor]
+    fn server() -> Box<Actor<Message=String, Reply=String>> {
+        reply!("220 ready".to_string());
+
+        loop {
+            let line = recv!();
+            reply!(format!("250 {}", line));
+        }
+    }
+
+    let mut actor = server();
+    assert_eq!(actor.greeting(), Some("220 ready".to_string()));
+    assert_eq!(actor.greeting(), None);
+    assert_eq!(actor.handle("hello".to_string()), Some("250 hello".to_string()));
+
+    // An actor that doesn't greet has nothing to take.
+    #[actor]
+    fn silent() -> Box<Actor<Message=(), Reply=()>> {
+        loop {
+            recv!();
+        }
+    }
+
+    assert_eq!(silent().greeting(), None);
+}
+
+#[test]
+fn test_hidden_reply_local() {
+    #[actor]
+    fn echo() -> Box<Actor<Message=usize, Reply=usize>> {
+        // A local with the same name as the one holding the pending reply doesn't clash with it.
+        let mut actor_reply = 0;
+
+        loop {
+            actor_reply += recv!();
+            reply!(actor_reply);
+        }
+    }
+
+    let mut actor = echo();
+    assert_eq!(actor.handle(1), Some(1));
+    assert_eq!(actor.handle(2), Some(3));
+}
@@ -0,0 +1,112 @@
+use futures::{future, stream, Future};
+
+#[derive(Debug, PartialEq)]
+struct IoError;
+
+#[derive(Debug, PartialEq)]
+struct TimerError;
+
+#[derive(Debug, PartialEq)]
+enum Error {
+    Io(IoError),
+    Timer(TimerError),
+}
+
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<TimerError> for Error {
+    fn from(err: TimerError) -> Self {
+        Error::Timer(err)
+    }
+}
+
+#[test]
+fn test_await() {
+    #[async]
+    fn add(a: u32, b: u32) -> Box<Future<Item=u32, Error=Error>> {
+        let a = await!(future::ok::<u32, Error>(a));
+        let b = await!(future::ok::<u32, Error>(b));
+        a + b
+    }
+
+    assert_eq!(add(1, 2).wait(), Ok(3));
+}
+
+#[test]
+fn test_await_converts_errors() {
+    #[async]
+    fn read() -> Box<Future<Item=u32, Error=Error>> {
+        let a = await!(future::ok::<u32, IoError>(1));
+        let b = await!(future::err::<u32, TimerError>(TimerError));
+        a + b
+    }
+
+    assert_eq!(read().wait(), Err(Error::Timer(TimerError)));
+}
+
+#[test]
+fn test_await_result() {
+    #[async]
+    fn read() -> Box<Future<Item=u32, Error=Error>> {
+        match await_result!(future::err::<u32, IoError>(IoError)) {
+            Ok(value) => value,
+            Err(IoError) => 5,
+        }
+    }
+
+    assert_eq!(read().wait(), Ok(5));
+}
+
+#[test]
+fn test_return() {
+    #[async]
+    fn read(early: bool) -> Box<Future<Item=u32, Error=Error>> {
+        if early {
+            return 1;
+        }
+
+        await!(future::ok::<u32, Error>(2))
+    }
+
+    assert_eq!(read(true).wait(), Ok(1));
+    assert_eq!(read(false).wait(), Ok(2));
+}
+
+#[test]
+fn test_await_next() {
+    #[async]
+    fn first() -> Box<Future<Item=Option<u32>, Error=Error>> {
+        let items: Vec<Result<u32, IoError>> = vec![Ok(1), Ok(2)];
+        let mut items = stream::iter(items);
+
+        match await_next!(items) {
+            Some(Ok(item)) => Some(item),
+            Some(Err(_)) => None,
+            None => None,
+        }
+    }
+
+    assert_eq!(first().wait(), Ok(Some(1)));
+}
+
+#[test]
+fn test_async_for() {
+    #[async]
+    fn sum(items: Vec<Result<u32, IoError>>) -> Box<Future<Item=u32, Error=Error>> {
+        let mut total = 0;
+
+        #[async]
+        for item in stream::iter(items) {
+            total += item;
+        }
+
+        total
+    }
+
+    assert_eq!(sum(vec![Ok(1), Ok(2), Ok(3)]).wait(), Ok(6));
+    assert_eq!(sum(vec![Ok(1), Err(IoError), Ok(3)]).wait(), Err(Error::Io(IoError)));
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// The `stateful-expand` binary built alongside this test. Tests run from
+/// `target/<profile>/deps`, and the binaries go in `target/<profile>`.
+fn expand_bin() -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    let profile_dir = exe.parent().and_then(Path::parent).unwrap();
+
+    profile_dir.join(format!("stateful-expand{}", env::consts::EXE_SUFFIX))
+}
+
+/// Run `stateful-expand --states` on a function in `tests/expand/states.rs`.
+fn expand(function: &str) -> Output {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    Command::new(expand_bin())
+        .arg("--states")
+        .arg(root.join("tests").join("expand").join("states.rs"))
+        .arg(function)
+        .output()
+        .unwrap()
+}
+
+/// The lines of the expansion that match on this state variant.
+fn arms(source: &str, variant: &str) -> Vec<String> {
+    source.lines()
+        .filter(|line| line.trim_left().starts_with(variant) && line.contains("=>"))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// The names of the variants of this state enum that the expansion matches on, other than `Done`
+/// and `Poisoned`.
+fn variants(source: &str, enum_name: &str) -> BTreeSet<String> {
+    let prefix = format!("{}::", enum_name);
+
+    source.lines()
+        .map(|line| line.trim_left())
+        .filter(|line| line.starts_with(&prefix[..]) && line.contains("=>"))
+        .map(|line| {
+            line[prefix.len()..].chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect::<String>()
+        })
+        .filter(|variant| variant != "Done" && variant != "Poisoned")
+        .collect()
+}
+
+#[test]
+fn test_expand_annotates_states() {
+    let output = expand("gen");
+    assert!(output.status.success());
+
+    let source = String::from_utf8(output.stdout).unwrap();
+    let arms = arms(&source, "ResumeState::State0Start");
+    assert!(!arms.is_empty(), "no arm for the start state in:\n{}", source);
+
+    for arm in arms {
+        assert!(arm.contains("// state 0: ") && arm.contains("states.rs:"),
+                "expected `{}` to be annotated", arm);
+    }
+}
+
+#[test]
+fn test_expand_annotates_labelled_states() {
+    let output = expand("machine");
+    assert!(output.status.success());
+
+    let source = String::from_utf8(output.stdout).unwrap();
+
+    for variant in &["InternalState::Ping", "InternalState::Pong"] {
+        let arms = arms(&source, variant);
+        assert!(!arms.is_empty(), "no arm for `{}` in:\n{}", variant, source);
+
+        for arm in arms {
+            assert!(arm.contains("// state ") && arm.contains("states.rs:"),
+                    "expected `{}` to be annotated", arm);
+        }
+    }
+}
+
+#[test]
+fn test_expand_checks_options() {
+    let output = expand("parser");
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("`push_parser` functions cannot be `no_std`"), "{}", stderr);
+}
+
+#[test]
+fn test_expand_folded_branches_are_not_states() {
+    let output = expand("folded");
+    assert!(output.status.success());
+
+    // Only the start and the two `yield_!`s that can run are resume points. The arms that are
+    // never taken are still in the expansion, but under an `if false`.
+    let source = String::from_utf8(output.stdout).unwrap();
+    let variants = variants(&source, "ResumeState");
+    assert_eq!(variants.len(), 3, "unexpected resume states {:?} in:\n{}", variants, source);
+    assert!(source.contains("if false"), "no dead code in:\n{}", source);
+}
+
+#[test]
+fn test_expand_inlines_blocks() {
+    let output = expand("inlined");
+    assert!(output.status.success());
+
+    // The `if`, the `match` and their joins are all inlined, so the only states are the start
+    // and the two resume points.
+    let source = String::from_utf8(output.stdout).unwrap();
+    let resume_variants = variants(&source, "ResumeState");
+    let internal_variants = variants(&source, "InternalState");
+
+    assert_eq!(resume_variants.len(), 3,
+               "unexpected resume states {:?} in:\n{}", resume_variants, source);
+    assert_eq!(internal_variants, resume_variants,
+               "unexpected internal states in:\n{}", source);
+}
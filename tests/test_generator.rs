@@ -1,3 +1,6 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use super::empty::Empty;
 
 #[test]
@@ -162,6 +165,125 @@ fn test_moved() {
     assert_eq!(iter.next(), None);
 }
 
+#[test]
+fn test_generics_not_held_in_state() {
+    #[generator]
+    fn gen<'a, T: Default + 'static>(count: usize) -> Box<Iterator<Item=T>> {
+        let mut i = 0;
+        while i < count {
+            yield_!(T::default());
+            i += 1;
+        }
+    }
+
+    let iter = gen::<usize>(2);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![0, 0]);
+}
+
+#[test]
+fn test_generics_only_in_bounds() {
+    #[generator]
+    fn gen<T, U>(items: Vec<T>) -> Box<Iterator<Item=U>>
+        where T: Into<U> + 'static,
+              U: 'static,
+    {
+        for item in moved!(items) {
+            yield_!(item.into());
+        }
+    }
+
+    let iter = gen::<u8, u32>(vec![1, 2]);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_generics_only_in_predicate_bounds() {
+    struct Wrapper<T>(T) where u64: From<T>;
+
+    #[generator]
+    fn gen<T>(items: Vec<T>) -> Box<Iterator<Item=u64>>
+        where T: Copy + 'static,
+              u64: From<T>,
+    {
+        for item in moved!(items) {
+            let wrapper: Wrapper<T> = Wrapper(item);
+            yield_!(1);
+            yield_!(u64::from(wrapper.0));
+        }
+    }
+
+    let iter = gen::<u8>(vec![2, 3]);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2, 1, 3]);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_generics_only_in_bounds_are_not_stored() {
+    use std::rc::Rc;
+
+    fn assert_send<T: Send>(value: T) -> T {
+        value
+    }
+
+    // `U` is never stored in the state, so it doesn't stop the generator from being `Send`.
+    #[generator]
+    fn gen<T, U>(items: Vec<T>) -> impl Iterator<Item=U>
+        where T: Into<U> + 'static,
+              U: 'static,
+    {
+        for item in moved!(items) {
+            yield_!(item.into());
+        }
+    }
+
+    let iter = assert_send(gen::<u8, Rc<u8>>(vec![1, 2]));
+    assert_eq!(iter.map(|item| *item).collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_typed_locals() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let (a, b): (usize, String) = (1, "ab".to_string());
+        let items: Vec<_> = vec![3, 4];
+        let name: &str = "abcde";
+
+        yield_!(a);
+        yield_!(b.len());
+
+        for item in moved!(items) {
+            yield_!(item);
+        }
+
+        yield_!(name.len());
+    }
+
+    let iter = gen();
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_typed_locals_with_elided_lifetimes() {
+    use std::borrow::Cow;
+    use std::slice;
+
+    // These types hide a lifetime in their path, so they can't be written in the state enum.
+    #[generator]
+    fn gen(items: &'static [usize]) -> Box<Iterator<Item=usize>> {
+        let iter: slice::Iter<usize> = items.iter();
+        let name: Cow<str> = Cow::Borrowed("abc");
+
+        for item in iter {
+            yield_!(*item);
+        }
+
+        yield_!(name.len());
+    }
+
+    let iter = gen(&[1, 2]);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
 #[test]
 fn test_partial_decl() {
     #[generator]
@@ -327,6 +449,339 @@ fn test_if_yield() {
     assert_eq!(iter.next(), None);
 }
 
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_no_std() {
+    #[generator(no_std)]
+    fn gen(items: &'static [u8]) -> impl Iterator<Item=u8> {
+        for item in items.iter() {
+            if *item != 0 {
+                yield_!(*item);
+            }
+        }
+    }
+
+    let mut iter = gen(&[1, 0, 2]);
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(iter.next(), Some(2));
+    assert_eq!(iter.next(), None);
+}
+
+/// Check that the first `SimplifyCfg` left fewer blocks, and so fewer candidate states, in the MIR
+/// of the function `name` whose attribute is on `line`.
+fn assert_fewer_blocks(name: &str, line: u32) {
+    fn count_blocks(mir: &str) -> usize {
+        mir.lines()
+            .filter(|line| line.trim_left().starts_with("bb") && line.contains(": {"))
+            .count()
+    }
+
+    let before = count_blocks(&read_dump(name, line, ".SimplifyCfg.initial-before.mar"));
+    let after = count_blocks(&read_dump(name, line, ".SimplifyCfg.initial-after.mar"));
+    assert!(after < before, "expected fewer than {} blocks, got {}", before, after);
+}
+
+#[test]
+fn test_simplify_goto_chains() {
+    let line = line!() + 1;
+    #[generator(dump(mir), dump_passes(SimplifyCfg), dump_dir = "target")]
+    fn simplify_goto_chains(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        loop {
+            if i == n {
+                break;
+            }
+
+            if i % 2 == 0 {
+                if i % 3 == 0 {
+                    yield_!(i);
+                } else {
+                }
+            } else {
+                {
+                    {
+                        i += 0;
+                    }
+                }
+            }
+
+            i += 1;
+        }
+    }
+
+    let iter = simplify_goto_chains(10);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![0, 6]);
+
+    assert_fewer_blocks("simplify_goto_chains", line);
+}
+
+#[test]
+fn test_simplify_break_after_suspend() {
+    let line = line!() + 1;
+    #[generator(dump(mir), dump_passes(SimplifyCfg), dump_dir = "target")]
+    fn simplify_break_after_suspend() -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        loop {
+            yield_!(i);
+            i += 1;
+            if i == 3 {
+                break;
+            }
+        }
+        yield_!(10);
+    }
+
+    let iter = simplify_break_after_suspend();
+    assert_eq!(iter.collect::<Vec<_>>(), vec![0, 1, 2, 10]);
+
+    assert_fewer_blocks("simplify_break_after_suspend", line);
+}
+
+#[test]
+fn test_simplify_match() {
+    let line = line!() + 1;
+    #[generator(dump(mir), dump_passes(SimplifyCfg), dump_dir = "target")]
+    fn simplify_match(items: Vec<Option<usize>>) -> Box<Iterator<Item=usize>> {
+        for item in items {
+            match item {
+                Some(0) => {}
+                Some(_) => {}
+                None => {}
+            }
+
+            match item {
+                Some(x) => {
+                    yield_!(x);
+                }
+                None => {}
+            }
+        }
+    }
+
+    let iter = simplify_match(vec![Some(1), None, Some(0), Some(2)]);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 0, 2]);
+
+    assert_fewer_blocks("simplify_match", line);
+}
+
+#[test]
+fn test_structured_inner_loop() {
+    #[generator]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        while i < n {
+            let mut sum = 0;
+            let mut j = 0;
+            while j <= i {
+                if j % 2 == 0 {
+                    sum += j;
+                } else {
+                    sum += 2 * j;
+                }
+                j += 1;
+            }
+            yield_!(sum);
+            i += 1;
+        }
+    }
+
+    let iter = gen(5);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![0, 2, 4, 10, 14]);
+}
+
+#[test]
+fn test_structured_join() {
+    #[generator]
+    fn gen(items: Vec<usize>) -> Box<Iterator<Item=String>> {
+        for item in items {
+            let name = match item {
+                0 => "zero",
+                1 => "one",
+                _ => "many",
+            };
+
+            let suffix = if item % 2 == 0 {
+                "even"
+            } else {
+                "odd"
+            };
+
+            yield_!(format!("{} {}", name, suffix));
+        }
+    }
+
+    let iter = gen(vec![0, 1, 5]);
+    assert_eq!(iter.collect::<Vec<_>>(), vec!["zero even", "one odd", "many odd"]);
+}
+
+#[test]
+fn test_structured_break_continue() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        'outer: loop {
+            i += 1;
+            let mut j = 0;
+            loop {
+                j += 1;
+                if j > i {
+                    break;
+                }
+                if j == 2 {
+                    continue;
+                }
+                if i == 4 {
+                    break 'outer;
+                }
+                if i == 3 {
+                    continue 'outer;
+                }
+            }
+            yield_!(i * 10 + j);
+        }
+        yield_!(i);
+    }
+
+    let iter = gen();
+    assert_eq!(iter.collect::<Vec<_>>(), vec![12, 23, 4]);
+}
+
+#[test]
+fn test_const_prop_if() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        if true {
+            yield_!(1);
+        } else {
+            yield_!(2);
+        }
+
+        if !true {
+            yield_!(3);
+        }
+
+        while false {
+            yield_!(4);
+        }
+
+        if 1 == 1 {
+            yield_!(5);
+        }
+    }
+
+    let iter = gen();
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 5]);
+}
+
+#[test]
+fn test_const_prop_match() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=&'static str>> {
+        match 2 {
+            1 => { yield_!("one"); }
+            2 => { yield_!("two"); }
+            _ => { yield_!("many"); }
+        }
+
+        match 'x' {
+            'a' | 'b' => { yield_!("ab"); }
+            _ => { yield_!("other"); }
+        }
+    }
+
+    let iter = gen();
+    assert_eq!(iter.collect::<Vec<_>>(), vec!["two", "other"]);
+}
+
+#[test]
+fn test_opt_levels() {
+    #[generator(opt = 0)]
+    fn gen0(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        while i < n {
+            if false {
+                yield_!(100);
+            }
+            yield_!(i);
+            i += 1;
+        }
+    }
+
+    #[generator(opt = 1)]
+    fn gen1(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        while i < n {
+            if false {
+                yield_!(100);
+            }
+            yield_!(i);
+            i += 1;
+        }
+    }
+
+    #[generator(opt = "2")]
+    fn gen2(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        while i < n {
+            if false {
+                yield_!(100);
+            }
+            yield_!(i);
+            i += 1;
+        }
+    }
+
+    assert_eq!(gen0(3).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(gen1(3).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(gen2(3).collect::<Vec<_>>(), vec![0, 1, 2]);
+}
+
+#[test]
+fn test_disable_passes() {
+    let line = line!() + 1;
+    #[generator(disable_passes(ConstProp, SimplifyLocals), dump(mir), dump_dir = "target")]
+    fn disable_passes_gen() -> Box<Iterator<Item=usize>> {
+        if true {
+            yield_!(1);
+        } else {
+            yield_!(2);
+        }
+    }
+
+    assert_eq!(disable_passes_gen().collect::<Vec<_>>(), vec![1]);
+
+    // The disabled passes aren't dumped, and the `if` survives to the end of the pipeline.
+    assert!(find_dump("disable_passes_gen", line, ".ConstProp.before.mar").is_none());
+    assert!(find_dump("disable_passes_gen", line, ".SimplifyLocals.before.mar").is_none());
+
+    let mir = read_dump("disable_passes_gen", line, ".SimplifyCfg.after-const-prop-after.mar");
+    assert!(mir.contains("if("), "expected the `if` to be kept:\n{}", mir);
+}
+
+#[test]
+fn test_interpret() {
+    #[generator(interpret(6))]
+    fn gen(n: usize) -> Box<Iterator<Item=usize>> {
+        let mut i = 0;
+        while i < n {
+            if i % 2 == 0 {
+                yield_!(i);
+            } else if false {
+                yield_!(100);
+            }
+
+            match i {
+                3 => { yield_!(30); }
+                _ => {}
+            }
+
+            i += 1;
+        }
+    }
+
+    assert_eq!(gen(6).collect::<Vec<_>>(), vec![0, 2, 30, 4]);
+}
+
 /*
 #[test]
 fn test_let_assign() {
@@ -342,3 +797,164 @@ fn test_let_assign() {
     assert_eq!(iter.next(), None);
 }
 */
+
+#[test]
+fn test_yield_from() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        yield_!(1);
+        yield_from!(vec![2, 3]);
+        yield_from!(4..6);
+    }
+
+    let iter = gen();
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+}
+
+struct Tree {
+    value: usize,
+    children: Vec<Tree>,
+}
+
+fn tree() -> Tree {
+    Tree {
+        value: 1,
+        children: vec![
+            Tree { value: 2, children: vec![Tree { value: 3, children: vec![] }] },
+            Tree { value: 4, children: vec![] },
+        ],
+    }
+}
+
+#[test]
+fn test_recursive_yield_from() {
+    #[generator]
+    fn walk(tree: Tree) -> Box<Iterator<Item=usize>> {
+        yield_!(tree.value);
+
+        for child in moved!(tree).children {
+            yield_from!(walk(child));
+        }
+    }
+
+    let iter = walk(tree());
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_recursive_yield_from_impl_trait() {
+    #[generator]
+    fn walk(tree: Tree) -> impl Iterator<Item=usize> {
+        yield_!(tree.value);
+
+        for child in moved!(tree).children {
+            yield_from!(walk(child));
+        }
+    }
+
+    let iter = walk(tree());
+    assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn test_recursive_yield_from_borrowed() {
+    #[generator]
+    fn walk<'a>(tree: &'a Tree) -> Box<Iterator<Item=&'a Tree> + 'a> {
+        yield_!(tree);
+
+        for child in tree.children.iter() {
+            yield_from!(walk(child));
+        }
+    }
+
+    let tree = tree();
+    let values = walk(&tree).map(|tree| tree.value).collect::<Vec<_>>();
+    assert_eq!(values, vec![1, 2, 3, 4]);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_recursive_yield_from_borrowed_impl_trait() {
+    #[generator]
+    fn walk<'a>(tree: &'a Tree) -> impl Iterator<Item=&'a Tree> + 'a {
+        yield_!(tree);
+
+        for child in tree.children.iter() {
+            // The adapter around the recursive call is boxed along with it.
+            yield_from!(walk(child).filter(|tree| tree.value != 3));
+        }
+    }
+
+    let tree = tree();
+    let values = walk(&tree).map(|tree| tree.value).collect::<Vec<_>>();
+    assert_eq!(values, vec![1, 2, 4]);
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_yield_from_same_named_method() {
+    struct Walker(usize);
+
+    impl Walker {
+        fn walk(&self) -> ::std::ops::Range<usize> {
+            0..self.0
+        }
+    }
+
+    // Calling an unrelated method named like the generator doesn't box it, so it works in
+    // `no_std` functions.
+    #[generator(no_std)]
+    fn walk(walker: Walker) -> impl Iterator<Item=usize> {
+        yield_from!(walker.walk());
+    }
+
+    assert_eq!(walk(Walker(3)).collect::<Vec<_>>(), vec![0, 1, 2]);
+}
+
+/// The path of the dump with this suffix of the function `name` whose attribute is on `line`, if
+/// it was written. Matching the line skips the dumps left over from builds where the function was
+/// somewhere else in the file.
+fn find_dump(name: &str, line: u32, suffix: &str) -> Option<PathBuf> {
+    let path = Path::new("target").join(format!("stateful.{}.{}{}", name, line, suffix));
+
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Read the dump with this suffix of the function `name` whose attribute is on `line`.
+pub fn read_dump(name: &str, line: u32, suffix: &str) -> String {
+    let path = find_dump(name, line, suffix).unwrap();
+
+    let mut text = String::new();
+    File::open(path).unwrap().read_to_string(&mut text).unwrap();
+    text
+}
+
+#[test]
+fn test_dump_states() {
+    let line = line!() + 1;
+    #[generator(dump(states), dump_dir = "target")]
+    fn dump_states_gen(count: usize) -> Box<Iterator<Item=usize>> {
+        for item in 0..count {
+            yield_!(item);
+        }
+    }
+
+    assert_eq!(dump_states_gen(2).collect::<Vec<_>>(), vec![0, 1]);
+
+    let dot = read_dump("dump_states_gen", line, ".states.dot");
+    assert!(dot.starts_with("digraph StateMachine {"));
+    assert!(dot.contains("resume0 [label=\"ResumeState::State0Start\", peripheries=\"2\"];"));
+    assert!(dot.contains("internal0 [label=\"InternalState::State0Start\\l  count: _"));
+    assert!(dot.contains("-> done [label=\"return\", style=\"solid\"];"));
+    assert!(dot.contains("style=\"dashed\"];"));
+
+    let mermaid = read_dump("dump_states_gen", line, ".states.mmd");
+    assert!(mermaid.starts_with("stateDiagram\n"));
+    assert!(mermaid.contains("[*] --> resume0"));
+    assert!(mermaid.contains("--> [*] : return"));
+}
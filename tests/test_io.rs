@@ -0,0 +1,117 @@
+use std::io::{self, Read, Write};
+
+#[test]
+fn test_read_chunks() {
+    #[generator(read)]
+    fn body() -> Box<Read> {
+        yield_!(b"hello" as &'static [u8]);
+        yield_!(b"" as &'static [u8]);
+        yield_!(b", world" as &'static [u8]);
+    }
+
+    let mut reader = body();
+    let mut buf = [0; 3];
+    assert_eq!(reader.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf, b"hel");
+    assert_eq!(reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"lo");
+    assert_eq!(reader.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf, b", w");
+
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"orld");
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn test_read_to_string() {
+    #[generator(read)]
+    fn lines(count: usize) -> Box<Read> {
+        let mut i = 0;
+        while i < count {
+            yield_!(format!("line {}\n", i));
+            i += 1;
+        }
+    }
+
+    let mut s = String::new();
+    lines(3).read_to_string(&mut s).unwrap();
+    assert_eq!(s, "line 0\nline 1\nline 2\n");
+}
+
+#[test]
+fn test_write_lines() {
+    #[push_parser(write)]
+    fn sink(count: usize) -> Box<Write> {
+        let mut lines = 0;
+
+        while lines < count {
+            let input = needs_input!();
+
+            for (pos, &byte) in input.iter().enumerate() {
+                if byte == b'\n' {
+                    lines += 1;
+
+                    if lines == count {
+                        unconsumed!(input.len() - pos - 1);
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut writer = sink(2);
+    assert_eq!(writer.write(b"a\nb").unwrap(), 3);
+    assert_eq!(writer.write(b"\nc").unwrap(), 1);
+    assert_eq!(writer.write(b"d").unwrap(), 0);
+    writer.flush().unwrap();
+
+    // The bytes after the last line are not silently dropped.
+    let err = sink(1).write_all(b"a\nb").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+}
+
+#[test]
+fn test_write_error() {
+    #[push_parser(write)]
+    fn ascii() -> Box<Write> {
+        loop {
+            let input = needs_input!();
+
+            if input.iter().any(|&byte| byte >= 0x80) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not ascii"));
+            }
+        }
+    }
+
+    let mut writer = ascii();
+    writer.write_all(b"abc").unwrap();
+    let err = writer.write_all(b"\xff").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_copy() {
+    #[generator(read)]
+    fn source() -> Box<Read> {
+        yield_!(vec![1, 2, 3]);
+        yield_!(vec![4, 5]);
+    }
+
+    #[push_parser(write)]
+    fn sum(total: usize) -> Box<Write> {
+        let mut seen = 0;
+
+        while seen < total {
+            seen += needs_input!().len();
+        }
+
+        Ok(())
+    }
+
+    assert_eq!(io::copy(&mut source(), &mut sum(5)).unwrap(), 5);
+}
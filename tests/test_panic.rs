@@ -0,0 +1,102 @@
+use futures::{future, Future};
+use std::panic::{self, AssertUnwindSafe};
+
+#[test]
+fn test_generator_after_panic() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        yield_!(1);
+        panic!("boom");
+    }
+
+    let mut iter = gen();
+    assert_eq!(iter.next(), Some(1));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| iter.next()));
+    assert!(result.is_err());
+
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn test_generator_after_completion() {
+    #[generator]
+    fn gen() -> Box<Iterator<Item=usize>> {
+        yield_!(1);
+    }
+
+    let mut iter = gen();
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+#[should_panic(expected = "polled after completion")]
+fn test_future_after_completion() {
+    #[async]
+    fn fut() -> Box<Future<Item=u32, Error=()>> {
+        await!(future::ok::<u32, ()>(1))
+    }
+
+    let mut fut = fut();
+    assert_eq!(fut.poll(), Ok(::futures::Async::Ready(1)));
+
+    let _ = fut.poll();
+}
+
+#[test]
+#[should_panic(expected = "polled after completion")]
+fn test_future_after_error() {
+    #[async]
+    fn fut() -> Box<Future<Item=u32, Error=()>> {
+        await!(future::err::<u32, ()>(()))
+    }
+
+    let mut fut = fut();
+    assert_eq!(fut.poll(), Err(()));
+
+    let _ = fut.poll();
+}
+
+#[test]
+#[should_panic(expected = "polled after panic")]
+fn test_future_after_panic() {
+    #[async]
+    fn fut() -> Box<Future<Item=u32, Error=()>> {
+        let x = await!(future::ok::<u32, ()>(1));
+
+        if x == 1 {
+            panic!("boom");
+        }
+
+        x
+    }
+
+    let mut fut = fut();
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| fut.poll()));
+    assert!(result.is_err());
+
+    let _ = fut.poll();
+}
+
+#[cfg(feature = "impl_trait")]
+#[test]
+fn test_unwind_safe() {
+    fn assert_unwind_safe<T: panic::UnwindSafe>(value: T) -> T {
+        value
+    }
+
+    #[generator]
+    fn gen(items: Vec<usize>) -> impl Iterator<Item=usize> {
+        for item in moved!(items) {
+            yield_!(item);
+        }
+    }
+
+    let iter = assert_unwind_safe(gen(vec![1, 2]));
+    let result = panic::catch_unwind(|| iter.collect::<Vec<_>>());
+    assert_eq!(result.ok(), Some(vec![1, 2]));
+}
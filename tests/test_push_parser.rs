@@ -0,0 +1,124 @@
+use stateful_runtime::{Progress, PushParser};
+
+#[test]
+fn test_done_without_input() {
+    #[push_parser]
+    fn parser() -> Box<PushParser<Output=usize>> {
+        42
+    }
+
+    let mut parser = parser();
+    assert_eq!(parser.feed(b"ignored"), Progress::Done(42, 0));
+}
+
+#[test]
+fn test_needs_input() {
+    #[push_parser]
+    fn parser() -> Box<PushParser<Output=usize>> {
+        let input = needs_input!();
+        input.len()
+    }
+
+    let mut parser = parser();
+    assert_eq!(parser.feed(b"abc"), Progress::Done(3, 3));
+}
+
+#[test]
+fn test_line() {
+    #[push_parser]
+    fn line() -> Box<PushParser<Output=String>> {
+        let mut line = Vec::new();
+
+        loop {
+            let input = needs_input!();
+
+            match input.iter().position(|&byte| byte == b'\n') {
+                Some(pos) => {
+                    line.extend_from_slice(&input[..pos]);
+                    unconsumed!(input.len() - pos - 1);
+                    break;
+                }
+                None => {
+                    line.extend_from_slice(&input);
+                }
+            }
+        }
+
+        String::from_utf8(moved!(line)).unwrap()
+    }
+
+    let mut parser = line();
+    assert_eq!(parser.feed(b"he"), Progress::NeedsInput);
+    assert_eq!(parser.feed(b""), Progress::NeedsInput);
+    assert_eq!(parser.feed(b"llo"), Progress::NeedsInput);
+    assert_eq!(parser.feed(b"\nworld"), Progress::Done("hello".to_string(), 1));
+}
+
+#[test]
+fn test_length_prefixed() {
+    #[push_parser]
+    fn frame() -> Box<PushParser<Output=Vec<u8>>> {
+        let mut buf = Vec::new();
+
+        while buf.is_empty() {
+            buf.extend_from_slice(&needs_input!());
+        }
+
+        let len = buf[0] as usize;
+
+        while buf.len() < len + 1 {
+            buf.extend_from_slice(&needs_input!());
+        }
+
+        unconsumed!(buf.len() - len - 1);
+        buf[1..len + 1].to_vec()
+    }
+
+    let mut parser = frame();
+    assert_eq!(parser.feed(b""), Progress::NeedsInput);
+    assert_eq!(parser.feed(b"\x03a"), Progress::NeedsInput);
+    assert_eq!(parser.feed(b"b"), Progress::NeedsInput);
+    assert_eq!(parser.feed(b"cd"), Progress::Done(b"abc".to_vec(), 1));
+}
+
+#[test]
+fn test_pipelined() {
+    #[push_parser]
+    fn word() -> Box<PushParser<Output=Vec<u8>>> {
+        let mut buf = Vec::new();
+
+        loop {
+            let input = needs_input!();
+
+            match input.iter().position(|&byte| byte == b' ') {
+                Some(pos) => {
+                    buf.extend_from_slice(&input[..pos]);
+                    unconsumed!(input.len() - pos - 1);
+                    return moved!(buf);
+                }
+                None => {
+                    buf.extend_from_slice(&input);
+                }
+            }
+        }
+    }
+
+    // The rest of the input after the first word is handed to the next parser.
+    let input = b"get index.html ";
+    let mut words = vec![];
+    let mut pos = 0;
+
+    while pos < input.len() {
+        let mut parser = word();
+
+        match parser.feed(&input[pos..]) {
+            Progress::Done(word, consumed) => {
+                words.push(word);
+                pos += consumed;
+            }
+            Progress::NeedsInput => panic!("incomplete word"),
+        }
+    }
+
+    assert_eq!(words, vec![b"get".to_vec(), b"index.html".to_vec()]);
+}
@@ -0,0 +1,166 @@
+use test_generator::read_dump;
+
+#[test]
+fn test_single_state() {
+    #[state_machine]
+    fn machine() -> Box<Iterator<Item=usize>> {
+        'start: loop {
+            suspend!(1);
+            break;
+        }
+    }
+
+    let mut iter = machine();
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn test_break_falls_into_next_state() {
+    #[state_machine]
+    fn machine() -> Box<Iterator<Item=usize>> {
+        'first: loop {
+            suspend!(1);
+            break;
+        }
+
+        'second: loop {
+            suspend!(2);
+            break;
+        }
+    }
+
+    let mut iter = machine();
+    assert_eq!(iter.next(), Some(1));
+    assert_eq!(iter.next(), Some(2));
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn test_goto() {
+    #[state_machine]
+    fn machine(rounds: usize) -> Box<Iterator<Item=&'static str>> {
+        let mut count = 0;
+
+        'ping: loop {
+            if count == rounds {
+                return;
+            }
+
+            suspend!("ping");
+            goto!('pong);
+        }
+
+        'pong: loop {
+            count += 1;
+            suspend!("pong");
+            goto!('ping);
+        }
+    }
+
+    let mut iter = machine(2);
+    assert_eq!(iter.next(), Some("ping"));
+    assert_eq!(iter.next(), Some("pong"));
+    assert_eq!(iter.next(), Some("ping"));
+    assert_eq!(iter.next(), Some("pong"));
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn test_goto_backwards_out_of_nested_scopes() {
+    #[state_machine]
+    fn machine(items: Vec<usize>) -> Box<Iterator<Item=usize>> {
+        let mut iter = moved!(items).into_iter();
+
+        'even: loop {
+            match iter.next() {
+                Some(item) => {
+                    if item % 2 == 1 {
+                        goto!('odd);
+                    }
+
+                    suspend!(item);
+                }
+                None => { return; }
+            }
+        }
+
+        'odd: loop {
+            match iter.next() {
+                Some(item) => {
+                    if item % 2 == 0 {
+                        suspend!(item * 10);
+                        goto!('even);
+                    }
+                }
+                None => { return; }
+            }
+        }
+    }
+
+    let iter = machine(vec![0, 2, 3, 5, 4, 6, 7, 8]);
+    assert_eq!(iter.collect::<Vec<_>>(), vec![0, 2, 40, 6, 80]);
+}
+
+#[test]
+fn test_lexer() {
+    #[state_machine]
+    fn words(input: &'static str) -> Box<Iterator<Item=String>> {
+        let mut chars = input.chars();
+        let mut word = String::new();
+
+        'space: loop {
+            match chars.next() {
+                Some(' ') => { }
+                Some(c) => {
+                    word.push(c);
+                    goto!('word);
+                }
+                None => { return; }
+            }
+        }
+
+        'word: loop {
+            match chars.next() {
+                Some(' ') => {
+                    suspend!(word.clone());
+                    word.clear();
+                    goto!('space);
+                }
+                Some(c) => {
+                    word.push(c);
+                }
+                None => {
+                    suspend!(word.clone());
+                    return;
+                }
+            }
+        }
+    }
+
+    let iter = words("  a bc   def");
+    assert_eq!(iter.collect::<Vec<_>>(), vec!["a", "bc", "def"]);
+}
+
+#[test]
+fn test_states_are_named_after_labels() {
+    let line = line!() + 1;
+    #[state_machine(dump(states), dump_dir = "target")]
+    fn labelled_machine() -> Box<Iterator<Item=usize>> {
+        'read_header: loop {
+            suspend!(1);
+            goto!('body);
+        }
+
+        'body: loop {
+            suspend!(2);
+            break;
+        }
+    }
+
+    assert_eq!(labelled_machine().collect::<Vec<_>>(), vec![1, 2]);
+
+    let dot = read_dump("labelled_machine", line, ".states.dot");
+    assert!(dot.contains("InternalState::ReadHeader"), "{}", dot);
+    assert!(dot.contains("InternalState::Body"), "{}", dot);
+}